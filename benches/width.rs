@@ -0,0 +1,59 @@
+// Copyright 2022 Alexandros Frantzis
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use criterion::{criterion_group, criterion_main, Criterion, black_box};
+use unweave::{UnweaveTab, UnweaveCharWidth};
+use unweave::width::{ascii_grapheme_count, str_grapheme_count, grapheme_count_tab_expanded};
+
+const ASCII_LINE: &[u8] = b"[2022-05-10 12:34:56.789] worker-3: processed request 1234 in 5ms";
+const ASCII_TAB_LINE: &[u8] = b"[2022-05-10 12:34:56.789]\tworker-3:\tprocessed request 1234\tin 5ms";
+const UNICODE_LINE: &str = "[2022-05-10 12:34:56.789] ワーカー3: 요청 1234 처리됨 — 5ms";
+
+fn bench_grapheme_count_tab_expanded(c: &mut Criterion) {
+    let tab = UnweaveTab::Expand(8);
+    let char_width = UnweaveCharWidth::Display;
+
+    c.bench_function("grapheme_count_tab_expanded ascii", |b| {
+        b.iter(|| grapheme_count_tab_expanded(black_box(ASCII_LINE), tab, char_width, None))
+    });
+    c.bench_function("grapheme_count_tab_expanded ascii tabs", |b| {
+        b.iter(|| grapheme_count_tab_expanded(black_box(ASCII_TAB_LINE), tab, char_width, None))
+    });
+    c.bench_function("grapheme_count_tab_expanded unicode", |b| {
+        b.iter(|| grapheme_count_tab_expanded(black_box(UNICODE_LINE.as_bytes()), tab,
+                                              char_width, None))
+    });
+    c.bench_function("grapheme_count_tab_expanded ascii out", |b| {
+        let mut out = Vec::new();
+        b.iter(|| {
+            out.clear();
+            grapheme_count_tab_expanded(black_box(ASCII_TAB_LINE), tab, char_width, Some(&mut out))
+        })
+    });
+}
+
+fn bench_grapheme_counts(c: &mut Criterion) {
+    c.bench_function("ascii_grapheme_count", |b| {
+        b.iter(|| ASCII_LINE.iter().map(|c| ascii_grapheme_count(black_box(*c))).sum::<u32>())
+    });
+    c.bench_function("str_grapheme_count", |b| {
+        b.iter(|| str_grapheme_count(black_box("ワ"), UnweaveCharWidth::Display))
+    });
+}
+
+criterion_group!(benches, bench_grapheme_count_tab_expanded, bench_grapheme_counts);
+criterion_main!(benches);
@@ -0,0 +1,694 @@
+// Copyright 2022 Alexandros Frantzis
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unweave interleaved streams of text lines using regular expression
+//! matching.
+//!
+//! Each line is classified based on a stream tag found in the line by matching
+//! a regular expression, and lines are then separated into per-stream columns
+//! (see [unweave_into_columns]) or files (see [unweave_into_files]).
+//!
+//! ```
+//! use unweave::{UnweaveOptionsColumns, UnweaveOptionsTag, unweave_into_columns_with_writer};
+//! # let tmpdir = tempdir::TempDir::new("unweave-doc").unwrap();
+//! # let input = tmpdir.path().join("input");
+//! # std::fs::write(&input, "A:1\nB:1\nA:2\n").unwrap();
+//!
+//! let opts = UnweaveOptionsColumns {
+//!     tag: UnweaveOptionsTag::new("A|B"),
+//!     inputs: vec![input],
+//!     column_separator: Some("|".to_string()),
+//!     ..Default::default()
+//! };
+//!
+//! let mut output = Vec::new();
+//! unweave_into_columns_with_writer(&opts, Box::new(&mut output)).unwrap();
+//!
+//! assert_eq!(output, b"A:1|\n   |B:1\nA:2|\n");
+//! ```
+
+mod pattern_test;
+mod unweave_columns;
+mod unweave_files;
+mod util;
+pub mod width;
+
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+use std::io::IsTerminal;
+use std::fmt;
+use std::error::Error;
+
+pub use pattern_test::{test_pattern, test_pattern_with_writer};
+pub use unweave_columns::{unweave_into_columns, unweave_into_columns_with_writer, unweave_into_grouped};
+pub use unweave_files::{unweave_into_files, unweave_into_files_with_writer_factory, WriterFactory};
+pub use util::{stop_following_on_interrupt, stdin_path, FileLinesOwned};
+
+/// The default size of the buffers used for reading inputs and writing outputs.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Errors specific to unweave.
+#[derive(Debug)]
+pub enum UnweaveError {
+    ParsingFailure(String),
+    MissingOption(&'static str),
+    InvalidOption(&'static str),
+    InvalidOptionValue(&'static str, String),
+    InvalidTwoPassReread,
+    LineAndColumnWidth,
+    InvalidOutputFilePattern(char),
+    IncompleteOutputFilePattern,
+    UnknownTagGroup(String),
+    TagGroupAndTagJoin,
+    FollowRequiresSinglePass,
+    InvalidColumnMapEntry(String),
+    InvertAndUnmatched,
+    NoMatch,
+    InvalidStableNumbers,
+    ColumnAfterOutput(String),
+    NullDataAndLineEnding,
+    PatternTooLarge(String, usize),
+    WholeLineTagAndTagGroup,
+    InvalidStripTag,
+    PerInputColumnsAndColumnMap,
+    BinaryInput(PathBuf),
+    OutputStreamsFailed(usize),
+    OutputFileCollision(String, String, String),
+    LineTooLong(PathBuf, usize),
+    InvalidConfigEntry(PathBuf, usize),
+    UnknownConfigOption(PathBuf, String),
+    PatternAndPatternFile(String),
+    PreserveEolWithMultipleInputs,
+    PercentWithoutLineWidth,
+}
+
+impl fmt::Display for UnweaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ParsingFailure(e) =>
+                write!(f, "{}", e),
+            Self::MissingOption(o) =>
+                write!(f, "Missing required option '{}'", o),
+            Self::InvalidOption(o) =>
+                write!(f, "Invalid option '{}' for selected mode", o),
+            Self::InvalidOptionValue(o, v) =>
+                write!(f, "Invalid value '{}' for option '{}'", v, o),
+            Self::InvalidTwoPassReread =>
+                write!(f, "Cannot use two-pass mode reread for the specified inputs"),
+            Self::LineAndColumnWidth =>
+                write!(f, "Cannot specify both --line-width and a --column-width width"),
+            Self::InvalidOutputFilePattern(c) =>
+                write!(f, "Invalid character '{}' in output file pattern", c),
+            Self::IncompleteOutputFilePattern =>
+                write!(f, "Incomplete output file pattern"),
+            Self::UnknownTagGroup(g) =>
+                write!(f, "Capture group '{}' not found in pattern", g),
+            Self::TagGroupAndTagJoin =>
+                write!(f, "Cannot specify both --tag-group and --tag-join"),
+            Self::FollowRequiresSinglePass =>
+                write!(f, "Cannot use --follow with options that require two passes"),
+            Self::InvalidColumnMapEntry(e) =>
+                write!(f, "Invalid column map entry '{}'", e),
+            Self::InvertAndUnmatched =>
+                write!(f, "Cannot use --invert with --unmatched or --unmatched-file"),
+            Self::NoMatch =>
+                write!(f, "No lines matched the pattern"),
+            Self::InvalidStableNumbers =>
+                write!(f, "Cannot use --stable-numbers with --follow or with inputs that cannot be reread"),
+            Self::ColumnAfterOutput(t) =>
+                write!(f, concat!("Stream '{}' appeared after output had begun, misaligning ",
+                                  "earlier rows; use options that require two passes instead ",
+                                  "of --strict-single-pass (see NUMBER OF PASSES in the manual)"), t),
+            Self::NullDataAndLineEnding =>
+                write!(f, "Cannot specify --null-data with --line-ending or --line-ending-detect"),
+            Self::PatternTooLarge(p, l) =>
+                write!(f, "Pattern '{}' exceeds the size limit of {} bytes (see --size-limit)", p, l),
+            Self::WholeLineTagAndTagGroup =>
+                write!(f, "Cannot specify --whole-line-tag with --tag-group or --tag-join"),
+            Self::InvalidStripTag =>
+                write!(f, "Cannot use --strip-tag with --tag-join, --whole-line-tag or --invert"),
+            Self::PerInputColumnsAndColumnMap =>
+                write!(f, "Cannot specify --per-input-columns with --column-map"),
+            Self::BinaryInput(p) =>
+                write!(f, "Input '{}' appears to contain binary data (see --binary)", p.display()),
+            Self::OutputStreamsFailed(n) =>
+                write!(f, "Failed to write the lines of {} stream{}", n, if *n == 1 { "" } else { "s" }),
+            Self::OutputFileCollision(t1, t2, file) =>
+                write!(f, "Streams '{}' and '{}' have the same output file '{}'", t1, t2, file),
+            Self::LineTooLong(p, n) =>
+                write!(f, "Input '{}' contains a line longer than {} bytes (see --max-line-action)",
+                       p.display(), n),
+            Self::InvalidConfigEntry(p, n) =>
+                write!(f, "Invalid entry on line {} of config file '{}'", n, p.display()),
+            Self::UnknownConfigOption(p, k) =>
+                write!(f, "Option '{}' in config file '{}' is unknown or not allowed", k, p.display()),
+            Self::PatternAndPatternFile(p) =>
+                write!(f, concat!("Cannot specify both a pattern and --pattern-file: '{}' is not ",
+                                  "an existing input file"), p),
+            Self::PreserveEolWithMultipleInputs =>
+                write!(f, "Cannot use --preserve-eol with multiple inputs"),
+            Self::PercentWithoutLineWidth =>
+                write!(f, concat!("Cannot use a percentage column width without --line-width ",
+                                  "when the output is not a terminal")),
+        }
+    }
+}
+
+impl Error for UnweaveError {}
+
+/// How to perform the second pass, when one is needed.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum UnweaveTwoPass { Cached, Reread }
+
+/// How the width of the columns is determined. With LineAuto, the line width
+/// is the width of the terminal the output is written to, falling back to
+/// Undefined if the output is not a terminal. With Percent, each column is the
+/// specified percentage of the line width, which is either the specified one,
+/// or, if None, the width of the terminal, failing if the output is not a
+/// terminal.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum UnweaveWidth { Undefined, Column(u32), Line(u32), LineAuto, Percent(u32, Option<u32>) }
+
+impl UnweaveWidth {
+    fn is_column(&self) -> bool {
+        matches!(self, Self::Column(_))
+    }
+}
+
+/// Whether inputs may be memory mapped.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveMmap { Allow, Disallow }
+
+/// Whether to expand tabs, and to which tab width.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveTab { NoExpand, Expand(u32) }
+
+impl UnweaveTab {
+    fn is_expand(&self) -> bool {
+        matches!(self, Self::Expand(_))
+    }
+}
+
+/// Where tab stops are measured from when expanding tabs: the start of the
+/// column a line is printed in, or the start of the printed line.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveTabOrigin { Column, Line }
+
+/// The line ending of output lines.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveLineEnding { Lf, Crlf }
+
+impl UnweaveLineEnding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+        }
+    }
+}
+
+/// How the width of characters is measured.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveCharWidth { Mono, Display }
+
+/// When to report progress while processing the inputs.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveProgress { Never, Auto, Always }
+
+/// When to highlight stream tags in the output. With Auto, tags are
+/// highlighted only if the output is a terminal.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveHighlight { Never, Auto, Always }
+
+impl UnweaveHighlight {
+    /// Whether to highlight tags, given whether the output is standard output.
+    fn enabled(&self, to_stdout: bool) -> bool {
+        match self {
+            Self::Never => false,
+            Self::Auto => to_stdout && std::io::stdout().is_terminal(),
+            Self::Always => true,
+        }
+    }
+}
+
+/// How to handle inputs that appear to contain binary data.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveBinary { Refuse, Warn, Process }
+
+/// What to do with input lines longer than the maximum line length.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveMaxLineAction { Truncate, Error }
+
+/// What the header row shows for each column.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveColumnLabels { Tag, Index, None }
+
+/// Whether and how to print line numbers for wrapped lines.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveLineNumbers { Disabled, Blank, Repeat }
+
+/// Where to break lines that don't fit in their column.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveWrap { Char, Word }
+
+/// The alignment of column contents.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveAlign { Left, Right }
+
+/// The order in which lines from multiple inputs are processed.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveInterleave { Input, RoundRobin }
+
+/// How to handle inputs that run out of lines before others, with round-robin
+/// interleaving.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveRrFill { Skip, Pad }
+
+/// How to print ASCII control characters other than tab.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveShowControl { Raw, Caret, Hex, Drop }
+
+/// How to handle empty lines that don't match the pattern.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveBlank { Drop, LastColumn, AllColumns }
+
+/// How to change the case of stream tags in output file names.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveFilenameCase { Preserve, Lower, Upper }
+
+/// Where to print lines that don't match the pattern.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnweaveUnmatched { Drop, First, Last }
+
+/// The capture group of the pattern that holds the stream tag.
+#[derive(PartialEq, Clone, Debug)]
+pub enum UnweaveTagGroup { Last, Index(usize), Name(String) }
+
+/// Regular expression syntax flags applied to the tag patterns.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct UnweaveRegexFlags {
+    pub multi_line: bool,
+    pub dot_matches_new_line: bool,
+    pub swap_greed: bool,
+    pub ignore_whitespace: bool,
+}
+
+/// Options that determine how stream tags are found.
+#[derive(Clone, Debug)]
+pub struct UnweaveOptionsTag {
+    pub patterns: Vec<String>,
+    pub group: UnweaveTagGroup,
+    pub join: Option<String>,
+    pub ignore_case: bool,
+    pub replace: Vec<(String, String)>,
+    pub invert: bool,
+    pub trim: bool,
+    pub size_limit: Option<usize>,
+    pub regex_flags: UnweaveRegexFlags,
+    pub match_window: Option<usize>,
+    pub field: Option<(String, usize)>,
+    pub whole_line: bool,
+    pub strip: bool,
+}
+
+impl UnweaveOptionsTag {
+    /// Creates tag options for the specified pattern, with all other
+    /// options at their defaults.
+    pub fn new(pattern: &str) -> Self {
+        UnweaveOptionsTag {
+            patterns: vec![pattern.to_string()],
+            group: UnweaveTagGroup::Last,
+            join: None,
+            ignore_case: false,
+            replace: Vec::new(),
+            invert: false,
+            trim: false,
+            size_limit: None,
+            regex_flags: UnweaveRegexFlags::default(),
+            match_window: None,
+            field: None,
+            whole_line: false,
+            strip: false,
+        }
+    }
+}
+
+/// Options for unweaving into columns.
+#[derive(Clone, Debug)]
+pub struct UnweaveOptionsColumns {
+    pub tag: UnweaveOptionsTag,
+    pub output: Option<PathBuf>,
+    pub inputs: Vec<PathBuf>,
+    pub mmap: UnweaveMmap,
+    pub width: UnweaveWidth,
+    pub column_separator: Option<String>,
+    pub two_pass: UnweaveTwoPass,
+    pub tab: UnweaveTab,
+    pub unmatched: UnweaveUnmatched,
+    pub header: bool,
+    pub footer: bool,
+    pub truncate_marker: Option<String>,
+    pub align: UnweaveAlign,
+    pub tab_origin: UnweaveTabOrigin,
+    pub sort_columns: bool,
+    pub reverse_columns: bool,
+    pub max_columns: Option<u32>,
+    pub max_lines_per_stream: Option<u64>,
+    pub line_numbers: UnweaveLineNumbers,
+    pub show_offset: bool,
+    pub follow: bool,
+    pub char_width: UnweaveCharWidth,
+    pub show_control: UnweaveShowControl,
+    pub line_ending: UnweaveLineEnding,
+    pub stats: bool,
+    pub require_match: bool,
+    pub column_map: Option<PathBuf>,
+    pub per_input_columns: bool,
+    pub jobs: u32,
+    pub max_cache_bytes: Option<u64>,
+    pub spill_stdin: bool,
+    pub fill_char: String,
+    pub outer_separator: bool,
+    pub sparse_separators: bool,
+    pub split_lines: Option<u64>,
+    pub uniq: bool,
+    pub highlight: UnweaveHighlight,
+    pub binary: UnweaveBinary,
+    pub max_line_bytes: Option<usize>,
+    pub max_line_action: UnweaveMaxLineAction,
+    pub interleave: UnweaveInterleave,
+    pub rr_fill: UnweaveRrFill,
+    pub blank: UnweaveBlank,
+    pub buffer_size: usize,
+    pub strict_single_pass: bool,
+    pub column_width_overrides: Vec<(String, u32)>,
+    pub min_column_width: Option<u32>,
+    pub wrap: UnweaveWrap,
+    pub wrap_marker: Option<String>,
+    pub warn_invalid_utf8: bool,
+    pub null_data: bool,
+    pub record_separator: Option<String>,
+    pub sort_key: Option<String>,
+    pub layout: Option<PathBuf>,
+    pub split_on: Option<String>,
+    pub group_separator: String,
+    pub progress: UnweaveProgress,
+    pub column_labels: UnweaveColumnLabels,
+    pub line_buffered: bool,
+    pub line_ending_detect: bool,
+}
+
+impl UnweaveOptionsColumns {
+    /// Whether the options allow unweaving with a single pass of the data.
+    /// The header and column sorting or reversing need to know all the columns
+    /// before printing anything, and the footer needs to know all the columns
+    /// when printing ends, so they always require two passes. Records are
+    /// split from the cached contents of the inputs, and lines are sorted in
+    /// the cached contents, so they also require two passes. Per-input
+    /// columns read round-robin may be placed before existing columns, so
+    /// they require two passes too.
+    fn allows_single_pass(&self) -> bool {
+        self.column_separator.is_none() && !self.sparse_separators && self.width.is_column() &&
+            !self.prints_header() && !self.footer && !self.sort_columns && !self.reverse_columns &&
+            self.record_separator.is_none() && self.sort_key.is_none() &&
+            !(self.per_input_columns && self.interleave == UnweaveInterleave::RoundRobin)
+    }
+
+    /// Whether stream tags are highlighted in the printed lines.
+    fn highlights(&self) -> bool {
+        self.highlight.enabled(self.output.is_none())
+    }
+
+    /// Whether a header row is printed.
+    fn prints_header(&self) -> bool {
+        self.header && self.column_labels != UnweaveColumnLabels::None
+    }
+
+    /// The regex separating records, if records are used instead of lines.
+    fn record_separator_regex(&self) -> Result<Option<regex::bytes::Regex>> {
+        self.record_separator.as_ref()
+            .map(|s| regex::bytes::Regex::new(s)
+                 .map_err(|_| UnweaveError::InvalidOptionValue("record-separator", s.clone()).into()))
+            .transpose()
+    }
+
+    /// The regex extracting the key to sort the lines of each column by, if
+    /// lines are sorted.
+    fn sort_key_regex(&self) -> Result<Option<regex::bytes::Regex>> {
+        self.sort_key.as_ref()
+            .map(|s| regex::bytes::Regex::new(s)
+                 .map_err(|_| UnweaveError::InvalidOptionValue("sort-key", s.clone()).into()))
+            .transpose()
+    }
+
+    /// The terminator of input lines.
+    fn line_terminator(&self) -> util::LineTerminator {
+        match (self.null_data, self.line_ending_detect) {
+            (true, _) => util::LineTerminator::Byte(b'\0'),
+            (false, true) => util::LineTerminator::Detect,
+            (false, false) => util::LineTerminator::Byte(b'\n'),
+        }
+    }
+
+    /// The string ending output lines.
+    fn output_line_ending(&self) -> &'static str {
+        if self.null_data { "\0" } else { self.line_ending.as_str() }
+    }
+
+    /// The maximum length of input lines.
+    fn max_line(&self) -> Option<util::MaxLine> {
+        self.max_line_bytes.map(|bytes| util::MaxLine { bytes, action: self.max_line_action })
+    }
+
+    /// Checks that the options are consistent with each other and with the
+    /// inputs.
+    pub fn validate(&self) -> Result<()> {
+        // With spill_stdin a non-rereadable stdin is copied to a temporary
+        // file, which can be reread.
+        if self.two_pass == UnweaveTwoPass::Reread &&
+            self.inputs.iter().any(|f| !(util::path_contents_can_be_reread(f) ||
+                                         (self.spill_stdin && util::path_is_stdin(f))))
+        {
+            bail!(UnweaveError::InvalidTwoPassReread);
+        }
+
+        if self.follow && !self.allows_single_pass() {
+            bail!(UnweaveError::FollowRequiresSinglePass);
+        }
+
+        if !width::is_single_width_grapheme(&self.fill_char) {
+            bail!(UnweaveError::InvalidOptionValue("fill-char", self.fill_char.clone()));
+        }
+
+        self.record_separator_regex()?;
+        self.sort_key_regex()?;
+
+        Ok(())
+    }
+}
+
+impl Default for UnweaveOptionsColumns {
+    fn default() -> Self {
+        UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new(""),
+            output: None,
+            inputs: Vec::new(),
+            mmap: UnweaveMmap::Allow,
+            width: UnweaveWidth::Undefined,
+            column_separator: None,
+            two_pass: UnweaveTwoPass::Cached,
+            tab: UnweaveTab::Expand(8),
+            unmatched: UnweaveUnmatched::Drop,
+            header: false,
+            footer: false,
+            truncate_marker: None,
+            align: UnweaveAlign::Left,
+            tab_origin: UnweaveTabOrigin::Column,
+            sort_columns: false,
+            reverse_columns: false,
+            max_columns: None,
+            max_lines_per_stream: None,
+            line_numbers: UnweaveLineNumbers::Disabled,
+            show_offset: false,
+            follow: false,
+            char_width: UnweaveCharWidth::Display,
+            show_control: UnweaveShowControl::Raw,
+            line_ending: UnweaveLineEnding::Lf,
+            stats: false,
+            require_match: false,
+            column_map: None,
+            per_input_columns: false,
+            jobs: 1,
+            max_cache_bytes: None,
+            spill_stdin: false,
+            fill_char: " ".to_string(),
+            outer_separator: false,
+            sparse_separators: false,
+            split_lines: None,
+            uniq: false,
+            highlight: UnweaveHighlight::Never,
+            binary: UnweaveBinary::Warn,
+            max_line_bytes: None,
+            max_line_action: UnweaveMaxLineAction::Error,
+            interleave: UnweaveInterleave::Input,
+            rr_fill: UnweaveRrFill::Skip,
+            blank: UnweaveBlank::Drop,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            strict_single_pass: false,
+            column_width_overrides: Vec::new(),
+            min_column_width: None,
+            wrap: UnweaveWrap::Char,
+            wrap_marker: None,
+            warn_invalid_utf8: false,
+            null_data: false,
+            record_separator: None,
+            sort_key: None,
+            layout: None,
+            split_on: None,
+            group_separator: String::new(),
+            progress: UnweaveProgress::Never,
+            column_labels: UnweaveColumnLabels::Tag,
+            line_buffered: false,
+            line_ending_detect: false,
+        }
+    }
+}
+
+/// Options for unweaving into files.
+#[derive(Clone, Debug)]
+pub struct UnweaveOptionsFiles {
+    pub tag: UnweaveOptionsTag,
+    pub output: Option<PathBuf>,
+    pub inputs: Vec<PathBuf>,
+    pub mmap: UnweaveMmap,
+    pub unmatched_file: Option<PathBuf>,
+    pub max_lines_per_stream: Option<u64>,
+    pub follow: bool,
+    pub line_ending: UnweaveLineEnding,
+    pub append: bool,
+    pub prefix_tag: bool,
+    pub stats: bool,
+    pub require_match: bool,
+    pub stable_numbers: bool,
+    pub filename_case: UnweaveFilenameCase,
+    pub sanitize_filenames: Option<String>,
+    pub no_merge: bool,
+    pub manifest: Option<PathBuf>,
+    pub max_open_files: Option<usize>,
+    pub buffer_size: usize,
+    pub null_data: bool,
+    pub dry_run: bool,
+    pub progress: UnweaveProgress,
+    pub line_buffered: bool,
+    pub line_ending_detect: bool,
+    pub count_only: bool,
+    pub preserve_eol: bool,
+    pub keep_going: bool,
+    pub uniq: bool,
+    pub highlight: UnweaveHighlight,
+    pub binary: UnweaveBinary,
+    pub max_line_bytes: Option<usize>,
+    pub max_line_action: UnweaveMaxLineAction,
+    pub jobs: u32,
+}
+
+impl Default for UnweaveOptionsFiles {
+    fn default() -> Self {
+        UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new(""),
+            output: None,
+            inputs: Vec::new(),
+            mmap: UnweaveMmap::Allow,
+            unmatched_file: None,
+            max_lines_per_stream: None,
+            follow: false,
+            line_ending: UnweaveLineEnding::Lf,
+            append: false,
+            prefix_tag: false,
+            stats: false,
+            require_match: false,
+            stable_numbers: false,
+            filename_case: UnweaveFilenameCase::Preserve,
+            sanitize_filenames: None,
+            no_merge: false,
+            manifest: None,
+            max_open_files: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            null_data: false,
+            dry_run: false,
+            progress: UnweaveProgress::Never,
+            line_buffered: false,
+            line_ending_detect: false,
+            count_only: false,
+            preserve_eol: false,
+            keep_going: false,
+            uniq: false,
+            highlight: UnweaveHighlight::Never,
+            binary: UnweaveBinary::Warn,
+            max_line_bytes: None,
+            max_line_action: UnweaveMaxLineAction::Error,
+            jobs: 1,
+        }
+    }
+}
+
+impl UnweaveOptionsFiles {
+    /// Whether stream tags are highlighted in the written lines.
+    fn highlights(&self) -> bool {
+        self.highlight.enabled(self.output.as_deref() == Some(Path::new("-")))
+    }
+
+    /// The terminator of input lines.
+    fn line_terminator(&self) -> util::LineTerminator {
+        match (self.null_data, self.line_ending_detect) {
+            (true, _) => util::LineTerminator::Byte(b'\0'),
+            (false, true) => util::LineTerminator::Detect,
+            (false, false) => util::LineTerminator::Byte(b'\n'),
+        }
+    }
+
+    /// The string ending output lines.
+    fn output_line_ending(&self) -> &'static str {
+        if self.null_data { "\0" } else { self.line_ending.as_str() }
+    }
+
+    /// The maximum length of input lines.
+    fn max_line(&self) -> Option<util::MaxLine> {
+        self.max_line_bytes.map(|bytes| util::MaxLine { bytes, action: self.max_line_action })
+    }
+
+    /// Checks that the options are consistent with each other and with the
+    /// inputs.
+    pub fn validate(&self) -> Result<()> {
+        // Stable numbers require a pass over the inputs before the output is
+        // written.
+        if self.stable_numbers &&
+            (self.follow || self.inputs.iter().any(|f| !util::path_contents_can_be_reread(f)))
+        {
+            bail!(UnweaveError::InvalidStableNumbers);
+        }
+
+        // Only the last line of the last input is known to be the last line
+        // written to its output file, so a line without a line ending can't
+        // be joined with a line of a later input.
+        if self.preserve_eol && self.inputs.len() > 1 {
+            bail!(UnweaveError::PreserveEolWithMultipleInputs);
+        }
+
+        Ok(())
+    }
+}
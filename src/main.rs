@@ -15,115 +15,67 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-mod unweave_columns;
-mod unweave_files;
-mod util;
-
-use anyhow::{Result, bail};
+use anyhow::{Result, Context, bail};
 use getopts::Options;
-use unweave_columns::unweave_into_columns;
-use unweave_files::unweave_into_files;
-use std::path::{PathBuf, Path};
-use std::fmt;
-use std::error::Error;
-
-#[derive(Debug)]
-enum UnweaveError {
-    ParsingFailure(String),
-    MissingOption(&'static str),
-    InvalidOption(&'static str),
-    InvalidOptionValue(&'static str, String),
-    InvalidTwoPassReread,
-    LineAndColumnWidth,
-    InvalidOutputFilePattern(char),
-    IncompleteOutputFilePattern,
-}
+use unweave::*;
+use std::path::{Path, PathBuf};
+use std::ffi::OsString;
 
-impl fmt::Display for UnweaveError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::ParsingFailure(e) =>
-                write!(f, "{}", e),
-            Self::MissingOption(o) =>
-                write!(f, "Missing required option '{}'", o),
-            Self::InvalidOption(o) =>
-                write!(f, "Invalid option '{}' for selected mode", o),
-            Self::InvalidOptionValue(o, v) =>
-                write!(f, "Invalid value '{}' for option '{}'", v, o),
-            Self::InvalidTwoPassReread =>
-                write!(f, "Cannot use two-pass mode reread for the specified inputs"),
-            Self::LineAndColumnWidth =>
-                write!(f, "Cannot specify both --line-width and --column-width"),
-            Self::InvalidOutputFilePattern(c) =>
-                write!(f, "Invalid character '{}' in output file pattern", c),
-            Self::IncompleteOutputFilePattern =>
-                write!(f, "Incomplete output file pattern"),
-        }
-    }
+// Parsed only once, so the size difference between the variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum UnweaveOptions {
+    Columns(UnweaveOptionsColumns),
+    Files(UnweaveOptionsFiles),
+    Grouped(UnweaveOptionsColumns),
+    Test(UnweaveOptionsColumns),
 }
 
-impl Error for UnweaveError {}
-
-#[derive(PartialEq, Copy, Clone)]
-enum UnweaveTwoPass { Cached, Reread }
-#[derive(PartialEq)]
-enum UnweaveWidth { Undefined, Column(u32), Line(u32) }
-
-impl UnweaveWidth {
-    fn is_column(&self) -> bool {
-        if let Self::Column(_) = self { true } else { false }
-    }
+/// The environment variables that affect the parsed options.
+#[derive(Default)]
+struct Environment {
+    // Whether NO_COLOR is set to a non-empty value, disabling automatic
+    // highlighting
+    no_color: bool,
+    // The value of UNWEAVE_TABSIZE, the default tab width
+    tabsize: Option<String>,
 }
 
-#[derive(Copy, Clone, PartialEq)]
-enum UnweaveMmap { Allow, Disallow }
-
-#[derive(Copy, Clone, PartialEq)]
-enum UnweaveTab { NoExpand, Expand(u32) }
-
-impl UnweaveTab {
-    fn is_expand(&self) -> bool {
-        if let Self::Expand(_) = self { true } else { false }
+impl Environment {
+    /// Reads the environment variables of the process.
+    fn from_process() -> Self {
+        Environment {
+            no_color: std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()),
+            tabsize: std::env::var("UNWEAVE_TABSIZE").ok(),
+        }
     }
 }
 
-struct UnweaveOptionsColumns {
-    pattern: String,
-    output: Option<PathBuf>,
-    inputs: Vec<PathBuf>,
-    mmap: UnweaveMmap,
-    width: UnweaveWidth,
-    column_separator: Option<String>,
-    two_pass: UnweaveTwoPass,
-    tab: UnweaveTab,
-}
-
-struct UnweaveOptionsFiles {
-    pattern: String,
-    output: Option<PathBuf>,
-    inputs: Vec<PathBuf>,
-    mmap: UnweaveMmap,
-}
-
-enum UnweaveOptions {
-    Columns(UnweaveOptionsColumns),
-    Files(UnweaveOptionsFiles),
-}
-
-fn parse_options(args: &[impl AsRef<std::ffi::OsStr>]) -> Result<UnweaveOptions> {
+/// Parses the command-line arguments into options. Default options are read
+/// from the config file specified with --config, or otherwise from the
+/// default config file, if any.
+fn parse_options(args: &[impl AsRef<std::ffi::OsStr>], default_config: Option<&Path>,
+                 env: &Environment) -> Result<UnweaveOptions>
+{
     let mut opts = Options::new();
     opts
         .optopt(
             "m", "mode",
             concat!(
                 "the unweave output mode, into separate columns in a single file ",
-                "(\"columns\", the default), or separate files (\"files\")"
+                "(\"columns\", the default), separate files (\"files\"), or groups of ",
+                "lines, one per column, in a single file (\"grouped\"), or test the pattern, ",
+                "showing the stream tag found in each line (\"test\")"
             ),
             "MODE"
         )
         .optopt(
             "c", "column-width",
-            "the width, in characters, of each column in the output (for columns mode)",
+            concat!(
+                "the width, in characters, of each column in the output (for columns mode), ",
+                "or a percentage of the line width, e.g., \"20%\", optionally followed or ",
+                "replaced by comma separated TAG:WIDTH entries that set the width of the ",
+                "columns of specific streams"
+            ),
             "COLUMN-WIDTH",
         )
         .optopt(
@@ -131,13 +83,16 @@ fn parse_options(args: &[impl AsRef<std::ffi::OsStr>]) -> Result<UnweaveOptions>
             concat!(
                 "the width, in characters, of each line in the output (for ",
                 "columns mode), with all columns having the same automatically ",
-                "calculated width"
+                "calculated width, or \"auto\" for the width of the terminal"
             ),
             "LINE-WIDTH",
         )
         .optopt(
             "s", "column-separator",
-            "the separator to print between columns in the output (for columns mode)",
+            concat!(
+                "the separator to print between columns in the output (for columns mode), ",
+                "or a comma separated list of separators for successive column gaps"
+            ),
             "COLUMN-SEPARATOR",
         )
         .optopt(
@@ -149,16 +104,140 @@ fn parse_options(args: &[impl AsRef<std::ffi::OsStr>]) -> Result<UnweaveOptions>
             ),
             "PASS-MODE",
         )
+        .optopt(
+            "", "max-cache-bytes",
+            concat!(
+                "use the \"reread\" two-pass mode instead of \"cached\" if the combined ",
+                "size of the inputs exceeds the specified number of bytes, and all inputs ",
+                "can be reread"
+            ),
+            "BYTES"
+        )
+        .optflag(
+            "", "spill-stdin",
+            concat!(
+                "if two passes are needed, copy non-rereadable standard input to a ",
+                "temporary file and use the \"reread\" two-pass mode"
+            )
+        )
+        .optopt(
+            "j", "jobs",
+            concat!(
+                "in columns mode, the number of inputs to scan in parallel during the ",
+                "first pass of the \"cached\" two-pass mode, and in files mode, the ",
+                "number of threads writing the output files (default: 1)"
+            ),
+            "JOBS"
+        )
         .optflag(
             "n", "no-mmap",
             "do not use mmap to access file contents"
         )
+        .optopt(
+            "", "line-ending",
+            "the line ending to use in the output, either \"lf\" (the default) or \"crlf\"",
+            "LINE-ENDING"
+        )
+        .optflag(
+            "", "line-ending-detect",
+            concat!(
+                "split input lines at carriage returns, for inputs that contain carriage ",
+                "returns but no newlines"
+            )
+        )
+        .optflag(
+            "z", "null-data",
+            "input and output lines are terminated by a NUL byte instead of a newline"
+        )
+        .optopt(
+            "", "record-separator",
+            concat!(
+                "in columns mode, treat the parts of the inputs separated by matches of REGEX ",
+                "as the lines to unweave, e.g., \"\\n\\n\" for records separated by blank lines"
+            ),
+            "REGEX"
+        )
+        .optopt(
+            "", "layout",
+            concat!(
+                "in columns mode, write the index, stream tag, start offset and width of ",
+                "each column to PATH, as tab separated values"
+            ),
+            "PATH"
+        )
+        .optopt(
+            "", "sort-key",
+            concat!(
+                "in columns and grouped modes, sort the lines of each column by the key ",
+                "matched by REGEX (its first capture group, if any)"
+            ),
+            "REGEX"
+        )
+        .optopt(
+            "", "group-separator",
+            "in grouped mode, the line to write between groups (default: an empty line)",
+            "STR"
+        )
+        .optopt(
+            "", "split-on",
+            concat!(
+                "in columns mode, split each line on STR into parts that are unweaved ",
+                "as separate lines"
+            ),
+            "STR"
+        )
+        .optflag(
+            "", "require-match",
+            "exit with an error if no lines matched the pattern"
+        )
+        .optflag(
+            "", "strict-single-pass",
+            concat!(
+                "in columns mode, when using a single pass, fail if a stream appears after ",
+                "output has begun, instead of printing misaligned rows"
+            )
+        )
+        .optflag(
+            "", "stats",
+            concat!(
+                "after processing, write the number of lines of each stream to stderr ",
+                "(in columns mode, without --output, only the statistics are written)"
+            )
+        )
+        .optflagopt(
+            "", "progress",
+            concat!(
+                "periodically write the number of lines processed to stderr, either only ",
+                "when stderr is a terminal (\"auto\", the default) or always (\"always\")"
+            ),
+            "WHEN"
+        )
+        .optflag(
+            "", "line-buffered",
+            "flush the output after each line, e.g., for viewing the output while it is produced"
+        )
+        .optflag(
+            "", "warn-invalid-utf8",
+            concat!(
+                "in columns mode, after processing, write the number of invalid UTF-8 ",
+                "sequences in the printed lines, and where the first few are, to stderr"
+            )
+        )
+        .optflag(
+            "f", "follow",
+            concat!(
+                "keep reading the last input as it grows, like 'tail -f', until ",
+                "interrupted (requires single pass in columns mode, implies --no-mmap)"
+            )
+        )
         .optopt(
             "o", "output",
             concat!(
                 "output file (for columns mode), or an output file template (for files mode) ",
-                "in which '%t' is replaced with the stream tag and '%Nd' with the stream ",
-                "number (starting from 0) zero-padded to a length of N digits"
+                "in which '%t' is replaced with the stream tag, '%Nd' with the stream ",
+                "number (starting from 0) zero-padded to a length of N digits, and '%i' ",
+                "and '%I' with the basename and full path of the input file. In files mode, ",
+                "the output \"-\" writes all streams to standard output"
             ),
             "OUTPUT"
         )
@@ -166,279 +245,2687 @@ fn parse_options(args: &[impl AsRef<std::ffi::OsStr>]) -> Result<UnweaveOptions>
             "t", "tab-width",
             concat!(
                 "in columns mode, the number of spaces to replace tab characters with ",
-                "(default: 8), or \"noexpand\" to disable tab expansion"
+                "(default: $UNWEAVE_TABSIZE, or 8), or \"noexpand\" to disable tab expansion"
             ),
             "TAB-WIDTH"
         )
-        .optflag(
-            "", "version",
-            "output version information and exit"
+        .optopt(
+            "", "tab-origin",
+            concat!(
+                "in columns mode, measure tab stops from the start of each column ",
+                "(\"column\", the default) or from the start of the printed line (\"line\")"
+            ),
+            "ORIGIN"
+        )
+        .optopt(
+            "g", "tag-group",
+            concat!(
+                "the capture group to use as the stream tag, either as a group index ",
+                "or a group name (default: the last capture group, or the whole match ",
+                "if there is no explicit capture group)"
+            ),
+            "GROUP"
+        )
+        .optopt(
+            "", "tag-join",
+            concat!(
+                "build the stream tag by joining the contents of all capture groups, ",
+                "in order, with the specified separator"
+            ),
+            "SEPARATOR"
         )
         .optflag(
-            "h", "help",
-            "display this help and exit"
-        );
-
-    let matches = opts.parse(args).map_err(|e| UnweaveError::ParsingFailure(e.to_string()))?;
-
-    if matches.opt_present("help") {
-        print!("{}",
-            opts.usage(
-                concat!(
-                    "Usage: unweave [OPTION...] PATTERN [FILE..]\n",
-                    "Unweave interleaved streams of text lines using regular expression matching.\n",
-                    "\n",
-                    "Each line is classified based on a stream tag extracted using the regular\n",
-                    "expression PATTERN. The first capture group (or the whole match if there is no\n",
-                    "explicit capture group) is used as the stream tag for the match. Without a \n",
-                    "FILE, or when FILE is -, read standard input.",
-                )
-            )
-        );
-        std::process::exit(0);
-    }
-
-    if matches.opt_present("version") {
-        print!(
+            "", "whole-line-tag",
             concat!(
-                "unweave 1.0.0\n",
-                "Copyright (C) 2022 Alexandros Frantzis\n",
-                "License GPL-3.0-or-later <https://gnu.org/licenses/gpl.html>.\n"
+                "use the whole line, trimmed of whitespace, as the stream tag of lines ",
+                "that match the pattern, grouping identical lines into a stream"
             )
-        );
-        std::process::exit(0);
-    }
-
-    let pattern = match matches.free.first() {
-        None => bail!(UnweaveError::MissingOption("pattern")),
-        Some(p) if p.is_empty() => 
-            bail!(UnweaveError::InvalidOptionValue("pattern", p.to_string())),
-        Some(p) => p.to_string(),
-    };
-
-    // Input from stdin (either no input file or "-") is marked with the
-    // "/dev/stdin" filename. This allows us to open the file on systems that
-    // support it, potentially getting direct access to the underlying file in
-    // case of redirection. On systems where the file doesn't exist we fall back
-    // to using io::stdin (see util::open_file()).
-    let mut inputs: Vec<_> = matches.free
-        .iter()
-        .skip(1)
-        .map(|m| PathBuf::from(if m == "-" { "/dev/stdin" } else { m }))
-        .collect();
-    if inputs.is_empty() {
-        inputs.push(PathBuf::from("/dev/stdin"));
-    }
-
-    let mode = matches.opt_str("mode").unwrap_or("columns".to_string());
-    match mode.as_str() {
-        "columns" | "files" => {},
-        _ => bail!(UnweaveError::InvalidOptionValue("mode", mode)),
-    };
-
-    if mode == "files" {
-        if !matches.opt_present("output") {
-            return Err(UnweaveError::MissingOption("output").into());
-        }
-        for opt in &["line-width", "column-width", "two-pass", "tab-width"] {
-            if matches.opt_present(opt) {
-                bail!(UnweaveError::InvalidOption(opt));
-            }
-        }
-    }
-
-    if matches.opt_present("line-width") && matches.opt_present("column-width") {
-        bail!(UnweaveError::LineAndColumnWidth);
-    }
-
-    let width = 
-        if matches.opt_present("line-width") {
-            match matches.opt_get::<u32>("line-width") {
-                Ok(Some(lw)) if lw > 0 => UnweaveWidth::Line(lw),
-                _ => bail!(
-                    UnweaveError::InvalidOptionValue(
-                        "line-width",
-                        matches.opt_str("line-width").unwrap_or("".to_string())
-                    )
-                ),
-            }
-        } else if matches.opt_present("column-width") {
-            match matches.opt_get::<u32>("column-width") {
-                Ok(Some(cw)) if cw > 0 => UnweaveWidth::Column(cw),
-                _ => bail!(
-                    UnweaveError::InvalidOptionValue(
-                        "column-width",
-                        matches.opt_str("column-width").unwrap_or("".to_string())
-                    )
-                ),
-            }
-        } else {
-            UnweaveWidth::Undefined
-        };
-
-    let two_pass = matches.opt_str("two-pass").unwrap_or("cached".to_string());
-    let two_pass = match two_pass.as_str() {
-        "cached" => UnweaveTwoPass::Cached,
-        "reread" => UnweaveTwoPass::Reread,
-        _ => bail!(UnweaveError::InvalidOptionValue("two-pass", two_pass)),
-    };
-
-    if two_pass == UnweaveTwoPass::Reread &&
-        inputs.iter().any(|f| !util::path_contents_can_be_reread(Path::new(f)))
-    {
-        bail!(UnweaveError::InvalidTwoPassReread);
-    }
-
-    let mmap = if matches.opt_present("no-mmap") { 
-        UnweaveMmap::Disallow
-    } else { 
-        UnweaveMmap::Allow
-    };
-
-    let tab = match matches.opt_get::<u32>("tab-width") {
-        Ok(None) => UnweaveTab::Expand(8),
-        Ok(Some(tw)) if tw > 0 => UnweaveTab::Expand(tw),
-        _ => { 
-            if matches.opt_str("tab-width") == Some("noexpand".to_string()) {
-                UnweaveTab::NoExpand
-            } else {
-                bail!(
-                    UnweaveError::InvalidOptionValue(
-                        "tab-width",
-                        matches.opt_str("tab-width").unwrap_or("".to_string())
-                    )
-                )
-            }
-        }
-    };
-
-    match mode.as_str() {
-        "columns" => {
-            Ok(
-                UnweaveOptions::Columns(UnweaveOptionsColumns {
-                    pattern: pattern,
-                    output: matches.opt_str("output").map(PathBuf::from),
-                    inputs: inputs,
-                    mmap: mmap,
-                    width: width,
-                    column_separator: matches.opt_str("column-separator"),
-                    two_pass: two_pass,
-                    tab: tab,
-                })
+        )
+        .optmulti(
+            "", "tag-replace",
+            concat!(
+                "replace the first match of PATTERN in the stream tag with REPLACEMENT, ",
+                "which may refer to capture groups of PATTERN as $N or $name (can be ",
+                "specified multiple times, with replacements applied in order)"
+            ),
+            "PATTERN=REPLACEMENT"
+        )
+        .optmulti(
+            "", "pattern",
+            concat!(
+                "an additional pattern to try, in order, for lines that don't match the ",
+                "main pattern (can be specified multiple times)"
+            ),
+            "PATTERN"
+        )
+        .optopt(
+            "", "pattern-file",
+            concat!(
+                "read the pattern from the specified file instead of the command line, ",
+                "in which case all positional arguments are input files"
+            ),
+            "FILE"
+        )
+        .optopt(
+            "", "inputs-from",
+            concat!(
+                "read additional input file paths from the specified file, one per line, ",
+                "after any positional input files (\"-\" is the standard input)"
+            ),
+            "FILE"
+        )
+        .optflagopt(
+            "", "highlight",
+            concat!(
+                "highlight the stream tag in each output line, either only when the output ",
+                "is a terminal (\"auto\", the default) or always (\"always\")"
+            ),
+            "WHEN"
+        )
+        .optopt(
+            "", "binary",
+            concat!(
+                "how to handle inputs that appear to contain binary data: fail (\"refuse\"), ",
+                "print a warning and process them (\"warn\", the default), or process them ",
+                "(\"process\")"
+            ),
+            "BINARY-MODE"
+        )
+        .optopt(
+            "", "max-line-bytes",
+            "the maximum length of input lines in bytes, see --max-line-action",
+            "N"
+        )
+        .optopt(
+            "", "max-line-action",
+            concat!(
+                "what to do with input lines longer than --max-line-bytes: fail (\"error\", ",
+                "the default), or keep only their first N bytes (\"truncate\")"
+            ),
+            "ACTION"
+        )
+        .optflag(
+            "", "strip-tag",
+            "remove the stream tag from lines before printing or writing them"
+        )
+        .optflag(
+            "", "trim-tag",
+            "trim leading and trailing ASCII whitespace from stream tags"
+        )
+        .optopt(
+            "", "size-limit",
+            "the maximum size of each compiled pattern, to reject overly complex patterns",
+            "BYTES"
+        )
+        .optopt(
+            "", "regex-flags",
+            concat!(
+                "regex syntax flags for the patterns: m (multi-line), s (dot matches new line), ",
+                "U (swap greed), x (ignore whitespace)"
+            ),
+            "FLAGS"
+        )
+        .optopt(
+            "", "match-window",
+            "only search the first N bytes of each line for the pattern",
+            "N"
+        )
+        .optopt(
+            "", "tag-field",
+            concat!(
+                "only search the Nth field of each line for the pattern, with fields ",
+                "separated by DELIM, e.g., \",:3\""
+            ),
+            "DELIM:N"
+        )
+        .optflag(
+            "v", "invert",
+            concat!(
+                "drop lines that match the pattern, and treat all other lines as a single ",
+                "stream with the tag \"unmatched\""
             )
-        },
-        "files" => {
-            Ok(
-                UnweaveOptions::Files(UnweaveOptionsFiles {
-                    pattern: pattern,
-                    output: matches.opt_str("output").map(PathBuf::from),
-                    inputs: inputs,
-                    mmap: mmap,
-                })
+        )
+        .optflag(
+            "i", "ignore-case",
+            concat!(
+                "match the pattern case-insensitively, and treat stream tags that differ ",
+                "only in case as the same stream"
             )
-        },
-        _ => panic!(),
-    }
-}
-
+        )
+        .optopt(
+            "", "unmatched",
+            concat!(
+                "in columns mode, print lines that don't match the pattern in an extra ",
+                "column, placed either before (\"first\") or after (\"last\") all ",
+                "other columns"
+            ),
+            "POSITION"
+        )
+        .optopt(
+            "", "unmatched-file",
+            "in files mode, write lines that don't match the pattern to the specified file",
+            "PATH"
+        )
+        .optflag(
+            "", "append",
+            "in files mode, append to existing output files instead of overwriting them"
+        )
+        .optflag(
+            "", "prefix-tag",
+            "in files mode, prefix each line with its stream tag followed by ': '"
+        )
+        .optopt(
+            "", "filename-case",
+            concat!(
+                "in files mode, change the case of stream tags in output file names ",
+                "(preserve, lower, upper, default: preserve)"
+            ),
+            "CASE"
+        )
+        .optflagopt(
+            "", "sanitize-filenames",
+            concat!(
+                "in files mode, replace the specified characters of stream tags in output ",
+                "file names with '_' (default: '/' and NUL)"
+            ),
+            "CHARS"
+        )
+        .optflag(
+            "", "no-merge",
+            concat!(
+                "in files mode, fail if streams with different tags would be written to ",
+                "the same output file, e.g., due to --filename-case"
+            )
+        )
+        .optopt(
+            "", "buffer-size",
+            "the size of the buffers used for reading inputs and writing outputs (default: 8192)",
+            "BYTES"
+        )
+        .optopt(
+            "", "max-open-files",
+            concat!(
+                "in files mode, the maximum number of output files to keep open at the ",
+                "same time (default: based on the limit of open file descriptors)"
+            ),
+            "N"
+        )
+        .optflag(
+            "", "dry-run",
+            concat!(
+                "in files mode, write the names and line counts of the output files ",
+                "to stdout, instead of creating the files"
+            )
+        )
+        .optopt(
+            "", "manifest",
+            concat!(
+                "in files mode, write the tag, output file name and number of lines of ",
+                "each stream to PATH"
+            ),
+            "PATH"
+        )
+        .optflag(
+            "", "preserve-eol",
+            concat!(
+                "in files mode, don't end the last line of an input with a line ending ",
+                "if it doesn't have one"
+            )
+        )
+        .optflag(
+            "", "count-only",
+            "in files mode, write only the number of lines of each output file to it"
+        )
+        .optflag(
+            "", "keep-going",
+            concat!(
+                "in files mode, report failures to write to an output file and keep writing ",
+                "the other output files, failing at the end"
+            )
+        )
+        .optflag(
+            "", "stable-numbers",
+            "in files mode, number streams in sorted tag order instead of order of appearance"
+        )
+        .optflag(
+            "", "header",
+            concat!(
+                "in columns mode, print a header row with the stream tag of each column, ",
+                "or in grouped mode, a header line with the stream tag of each group"
+            )
+        )
+        .optflag(
+            "", "footer",
+            "in columns mode, print a footer row with the number of lines of each column"
+        )
+        .optopt(
+            "", "column-labels",
+            concat!(
+                "in columns mode, what the header row shows for each column: the stream ",
+                "tag (\"tag\", the default), the column index (\"index\"), or nothing, ",
+                "omitting the header row (\"none\")"
+            ),
+            "LABELS"
+        )
+        .optflag(
+            "", "truncate",
+            concat!(
+                "in columns mode, truncate lines that don't fit in their column, ",
+                "instead of wrapping them"
+            )
+        )
+        .optopt(
+            "", "truncate-marker",
+            concat!(
+                "the marker to replace the end of truncated lines with (default: \"…\"), ",
+                "implies --truncate"
+            ),
+            "MARKER"
+        )
+        .optopt(
+            "", "blank",
+            concat!(
+                "in columns mode, drop empty lines that don't match the pattern (\"drop\", ",
+                "the default), print them in the column of the previous line ",
+                "(\"last-column\"), or print them as an empty row (\"all-columns\")"
+            ),
+            "BLANK-MODE"
+        )
+        .optopt(
+            "", "interleave",
+            concat!(
+                "in columns mode, read all lines from each input before moving to the ",
+                "next input (\"input\", the default), or read one line from each input ",
+                "in turn (\"roundrobin\")"
+            ),
+            "INTERLEAVE-MODE"
+        )
+        .optopt(
+            "", "rr-fill",
+            concat!(
+                "in columns mode with round-robin interleaving, skip inputs that have no ",
+                "more lines (\"skip\", the default), or print an empty row in their turn ",
+                "(\"pad\")"
+            ),
+            "FILL-MODE"
+        )
+        .optopt(
+            "", "wrap",
+            concat!(
+                "in columns mode, wrap lines that don't fit in their column at any ",
+                "character (\"char\", the default) or at spaces (\"word\")"
+            ),
+            "WRAP-MODE"
+        )
+        .optopt(
+            "", "wrap-marker",
+            concat!(
+                "in columns mode, the marker to append to lines that are wrapped ",
+                "at the character that fills the column"
+            ),
+            "MARKER"
+        )
+        .optopt(
+            "", "align",
+            concat!(
+                "in columns mode, align the contents of each column to the left ",
+                "(\"left\", the default) or to the right (\"right\")"
+            ),
+            "ALIGNMENT"
+        )
+        .optflag(
+            "", "sort-columns",
+            "in columns mode, order the columns by stream tag instead of order of appearance"
+        )
+        .optflag(
+            "", "reverse-columns",
+            concat!(
+                "in columns mode, reverse the order of the columns, placing the last ",
+                "stream to appear first"
+            )
+        )
+        .optopt(
+            "", "column-map",
+            concat!(
+                "in columns mode, a file in which each line contains a stream tag and a ",
+                "column index, placing the columns of the listed tags first, ordered by index"
+            ),
+            "FILE"
+        )
+        .optflag(
+            "", "per-input-columns",
+            "in columns mode, give each input its own columns, even for tags shared between inputs"
+        )
+        .optopt(
+            "", "min-column-width",
+            concat!(
+                "in columns mode, the minimum width, in characters, of each column ",
+                "(except for columns with an explicit per stream width)"
+            ),
+            "MIN-WIDTH"
+        )
+        .optopt(
+            "", "max-columns",
+            concat!(
+                "in columns mode, the maximum number of columns for stream tags, with ",
+                "lines for any additional tags printed in a shared overflow column"
+            ),
+            "MAX-COLUMNS"
+        )
+        .optopt(
+            "", "max-lines-per-stream",
+            "the maximum number of lines to output for each stream",
+            "MAX-LINES"
+        )
+        .optflag(
+            "", "uniq",
+            "drop lines that are identical to the previous line output for the same stream"
+        )
+        .optflag(
+            "", "outer-separator",
+            "in columns mode, also print the column separator at the start and end of each line"
+        )
+        .optopt(
+            "", "split-lines",
+            concat!(
+                "in columns mode, split the output into numbered files, e.g., ",
+                "OUTPUT.001, OUTPUT.002, with N rows each (requires --output)"
+            ),
+            "N"
+        )
+        .optflag(
+            "", "sparse-separators",
+            "in columns mode, only print the column separators next to the column with contents"
+        )
+        .optopt(
+            "", "fill-char",
+            concat!(
+                "in columns mode, the character to pad columns with, which must occupy ",
+                "a single terminal cell (default: space)"
+            ),
+            "CHAR"
+        )
+        .optopt(
+            "", "char-width",
+            concat!(
+                "in columns mode, measure characters by the number of terminal cells they ",
+                "occupy, with wide characters (e.g., CJK) taking two cells (\"display\", ",
+                "the default), or count every character as a single cell (\"mono\")"
+            ),
+            "WIDTH-MODE"
+        )
+        .optopt(
+            "", "show-control",
+            concat!(
+                "in columns mode, how to print control characters other than tab: as is ",
+                "(\"raw\", the default), in caret notation, e.g., ^A (\"caret\"), as ",
+                "hexadecimal escapes, e.g., \\x01 (\"hex\"), or not at all (\"drop\")"
+            ),
+            "CONTROL-MODE"
+        )
+        .optflagopt(
+            "", "line-numbers",
+            concat!(
+                "in columns mode, prefix each row with the number of the input line ",
+                "(counting across all inputs), and either leave the number blank ",
+                "(\"blank\", the default) or repeat it (\"repeat\") for rows of wrapped lines"
+            ),
+            "WRAPPED"
+        )
+        .optflag(
+            "", "show-offset",
+            concat!(
+                "in columns mode, prefix each row with the byte offset of the input line ",
+                "within its input, after any line number"
+            )
+        )
+        .optopt(
+            "", "config",
+            "read default options from PATH (default: ~/.config/unweave/config, if it exists)",
+            "PATH"
+        )
+        .optflag(
+            "", "version",
+            "output version information and exit"
+        )
+        .optflag(
+            "h", "help",
+            "display this help and exit"
+        );
+
+    let matches = opts.parse(args).map_err(|e| UnweaveError::ParsingFailure(e.to_string()))?;
+
+    // Options from the config file are placed before the command-line
+    // arguments, which are parsed again along with them.
+    let config_args = read_config_args(&matches, default_config)?;
+    let matches = if config_args.is_empty() {
+        matches
+    } else {
+        let args = config_args.into_iter().chain(args.iter().map(|a| a.as_ref().to_os_string()));
+        opts.parse(args).map_err(|e| UnweaveError::ParsingFailure(e.to_string()))?
+    };
+
+    if matches.opt_present("help") {
+        print!("{}",
+            opts.usage(
+                concat!(
+                    "Usage: unweave [OPTION...] PATTERN [FILE..]\n",
+                    "  or:  unweave [OPTION...] --pattern-file=PATTERN-FILE [FILE..]\n",
+                    "Unweave interleaved streams of text lines using regular expression matching.\n",
+                    "\n",
+                    "Each line is classified based on a stream tag extracted using the regular\n",
+                    "expression PATTERN. The last capture group (or the whole match if there is no\n",
+                    "explicit capture group) is used as the stream tag for the match, unless a\n",
+                    "different group is selected with --tag-group. Without a FILE, or when FILE\n",
+                    "is -, read standard input.",
+                )
+            )
+        );
+        std::process::exit(0);
+    }
+
+    if matches.opt_present("version") {
+        print!(
+            concat!(
+                "unweave 1.0.0\n",
+                "Copyright (C) 2022 Alexandros Frantzis\n",
+                "License GPL-3.0-or-later <https://gnu.org/licenses/gpl.html>.\n"
+            )
+        );
+        std::process::exit(0);
+    }
+
+    // When the pattern is read from a file, all positional arguments are
+    // input files. A first positional argument that isn't an existing file is
+    // most likely a pattern given by mistake, so it's rejected instead of
+    // being silently treated as an input file.
+    let (pattern, free_inputs) = match matches.opt_str("pattern-file") {
+        Some(path) => {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read pattern file {}", path))?;
+            let pattern = contents.strip_suffix('\n').unwrap_or(&contents);
+            let pattern = pattern.strip_suffix('\r').unwrap_or(pattern);
+            if pattern.is_empty() {
+                bail!(UnweaveError::InvalidOptionValue("pattern-file", path));
+            }
+            if let Some(p) = matches.free.first().filter(|p| *p != "-" && !Path::new(p).exists()) {
+                bail!(UnweaveError::PatternAndPatternFile(p.to_string()));
+            }
+            (pattern.to_string(), &matches.free[..])
+        },
+        None => match matches.free.first() {
+            None => bail!(UnweaveError::MissingOption("pattern")),
+            Some(p) if p.is_empty() => 
+                bail!(UnweaveError::InvalidOptionValue("pattern", p.to_string())),
+            Some(p) => (p.to_string(), &matches.free[1..]),
+        },
+    };
+
+    // Additional patterns are tried in order after the main pattern
+    let mut patterns = vec![pattern];
+    for p in matches.opt_strs("pattern") {
+        if p.is_empty() {
+            bail!(UnweaveError::InvalidOptionValue("pattern", p));
+        }
+        patterns.push(p);
+    }
+
+    // Input from stdin (either no input file or "-") is marked with the
+    // platform's stdin path (see util::stdin_path()).
+    let input_path = |m: &str| if m == "-" { stdin_path() } else { PathBuf::from(m) };
+    let mut inputs: Vec<_> = free_inputs
+        .iter()
+        .map(|m| input_path(m))
+        .collect();
+    if let Some(path) = matches.opt_str("inputs-from") {
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read inputs file {}", path))?;
+        inputs.extend(contents.lines().filter(|l| !l.is_empty()).map(input_path));
+    }
+    if inputs.is_empty() {
+        inputs.push(stdin_path());
+    }
+
+    let mode = matches.opt_str("mode").unwrap_or("columns".to_string());
+    match mode.as_str() {
+        "columns" | "files" | "grouped" | "test" => {},
+        _ => bail!(UnweaveError::InvalidOptionValue("mode", mode)),
+    };
+
+    if mode == "files" && !matches.opt_present("output") {
+        return Err(UnweaveError::MissingOption("output").into());
+    }
+
+    if let Some(opt) = invalid_options_for_mode(&mode).find(|o| matches.opt_present(o)) {
+        bail!(UnweaveError::InvalidOption(opt));
+    }
+
+    if mode != "files" && matches.opt_present("split-lines") && !matches.opt_present("output") {
+        return Err(UnweaveError::MissingOption("output").into());
+    }
+
+    let column_widths = match matches.opt_str("column-width") {
+        Some(cw) => parse_column_widths(&cw)?,
+        None => (None, Vec::new()),
+    };
+
+    // A percentage column width is relative to the line width, and per tag
+    // widths apply with any line width, but a fixed column width conflicts
+    // with a line width.
+    let percent = match column_widths.0 {
+        Some(UnweaveWidth::Percent(p, _)) => Some(p),
+        _ => None,
+    };
+
+    if matches.opt_present("line-width") && matches!(column_widths.0, Some(UnweaveWidth::Column(_))) {
+        bail!(UnweaveError::LineAndColumnWidth);
+    }
+
+    let width = 
+        if matches.opt_present("line-width") {
+            match (matches.opt_get::<u32>("line-width"), percent) {
+                (Ok(Some(lw)), Some(p)) if lw > 0 => UnweaveWidth::Percent(p, Some(lw)),
+                (Ok(Some(lw)), None) if lw > 0 => UnweaveWidth::Line(lw),
+                (_, Some(p)) if matches.opt_str("line-width").as_deref() == Some("auto") =>
+                    UnweaveWidth::Percent(p, None),
+                _ if matches.opt_str("line-width").as_deref() == Some("auto") => UnweaveWidth::LineAuto,
+                _ => bail!(
+                    UnweaveError::InvalidOptionValue(
+                        "line-width",
+                        matches.opt_str("line-width").unwrap_or("".to_string())
+                    )
+                ),
+            }
+        } else {
+            column_widths.0.unwrap_or(UnweaveWidth::Undefined)
+        };
+
+    let two_pass = matches.opt_str("two-pass").unwrap_or("cached".to_string());
+    let two_pass = match two_pass.as_str() {
+        "cached" => UnweaveTwoPass::Cached,
+        "reread" => UnweaveTwoPass::Reread,
+        _ => bail!(UnweaveError::InvalidOptionValue("two-pass", two_pass)),
+    };
+
+    let spill_stdin = matches.opt_present("spill-stdin");
+
+    let follow = matches.opt_present("follow");
+
+    let mmap = if matches.opt_present("no-mmap") || follow {
+        UnweaveMmap::Disallow
+    } else { 
+        UnweaveMmap::Allow
+    };
+
+    let tab = match matches.opt_get::<u32>("tab-width") {
+        Ok(None) => {
+            // Invalid values of the environment variable are ignored
+            let tabsize = env.tabsize.as_deref()
+                .and_then(|s| s.parse::<u32>().ok())
+                .filter(|tw| *tw > 0);
+            UnweaveTab::Expand(tabsize.unwrap_or(8))
+        },
+        Ok(Some(tw)) if tw > 0 => UnweaveTab::Expand(tw),
+        _ => { 
+            if matches.opt_str("tab-width") == Some("noexpand".to_string()) {
+                UnweaveTab::NoExpand
+            } else {
+                bail!(
+                    UnweaveError::InvalidOptionValue(
+                        "tab-width",
+                        matches.opt_str("tab-width").unwrap_or("".to_string())
+                    )
+                )
+            }
+        }
+    };
+
+    let unmatched = match matches.opt_str("unmatched").as_deref() {
+        None => UnweaveUnmatched::Drop,
+        Some("first") => UnweaveUnmatched::First,
+        Some("last") => UnweaveUnmatched::Last,
+        Some(u) => bail!(UnweaveError::InvalidOptionValue("unmatched", u.to_string())),
+    };
+
+    let min_column_width = match matches.opt_get::<u32>("min-column-width") {
+        Ok(None) => None,
+        Ok(Some(m)) if m > 0 => Some(m),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "min-column-width",
+                matches.opt_str("min-column-width").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let max_columns = match matches.opt_get::<u32>("max-columns") {
+        Ok(None) => None,
+        Ok(Some(m)) if m > 0 => Some(m),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "max-columns",
+                matches.opt_str("max-columns").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let max_lines_per_stream = match matches.opt_get::<u64>("max-lines-per-stream") {
+        Ok(None) => None,
+        Ok(Some(m)) if m > 0 => Some(m),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "max-lines-per-stream",
+                matches.opt_str("max-lines-per-stream").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let max_cache_bytes = match matches.opt_get::<u64>("max-cache-bytes") {
+        Ok(None) => None,
+        Ok(Some(m)) if m > 0 => Some(m),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "max-cache-bytes",
+                matches.opt_str("max-cache-bytes").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let buffer_size = match matches.opt_get_default::<usize>("buffer-size", DEFAULT_BUFFER_SIZE) {
+        Ok(b) if b > 0 => b,
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "buffer-size",
+                matches.opt_str("buffer-size").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let max_open_files = match matches.opt_get::<usize>("max-open-files") {
+        Ok(None) => None,
+        Ok(Some(m)) if m > 0 => Some(m),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "max-open-files",
+                matches.opt_str("max-open-files").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let jobs = match matches.opt_get::<u32>("jobs") {
+        Ok(None) => 1,
+        Ok(Some(j)) if j > 0 => j,
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "jobs",
+                matches.opt_str("jobs").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let line_numbers = match (matches.opt_present("line-numbers"),
+                              matches.opt_str("line-numbers").as_deref()) {
+        (false, _) => UnweaveLineNumbers::Disabled,
+        (true, None) | (true, Some("blank")) => UnweaveLineNumbers::Blank,
+        (true, Some("repeat")) => UnweaveLineNumbers::Repeat,
+        (true, Some(l)) => bail!(UnweaveError::InvalidOptionValue("line-numbers", l.to_string())),
+    };
+
+    let line_ending = match matches.opt_str("line-ending").as_deref() {
+        None | Some("lf") => UnweaveLineEnding::Lf,
+        Some("crlf") => UnweaveLineEnding::Crlf,
+        Some(l) => bail!(UnweaveError::InvalidOptionValue("line-ending", l.to_string())),
+    };
+
+    let progress = match (matches.opt_present("progress"), matches.opt_str("progress").as_deref()) {
+        (false, _) => UnweaveProgress::Never,
+        (true, None) | (true, Some("auto")) => UnweaveProgress::Auto,
+        (true, Some("always")) => UnweaveProgress::Always,
+        (true, Some(p)) => bail!(UnweaveError::InvalidOptionValue("progress", p.to_string())),
+    };
+
+    // As suggested by https://no-color.org, NO_COLOR disables highlighting
+    // unless it's explicitly requested with --highlight=always.
+    let highlight = match (matches.opt_present("highlight"), matches.opt_str("highlight").as_deref()) {
+        (false, _) => UnweaveHighlight::Never,
+        (true, None) | (true, Some("auto")) if env.no_color => UnweaveHighlight::Never,
+        (true, None) | (true, Some("auto")) => UnweaveHighlight::Auto,
+        (true, Some("always")) => UnweaveHighlight::Always,
+        (true, Some(h)) => bail!(UnweaveError::InvalidOptionValue("highlight", h.to_string())),
+    };
+
+    let binary = match matches.opt_str("binary").as_deref() {
+        Some("refuse") => UnweaveBinary::Refuse,
+        None | Some("warn") => UnweaveBinary::Warn,
+        Some("process") => UnweaveBinary::Process,
+        Some(b) => bail!(UnweaveError::InvalidOptionValue("binary", b.to_string())),
+    };
+
+    let max_line_bytes = match matches.opt_get::<usize>("max-line-bytes") {
+        Ok(None) => None,
+        Ok(Some(n)) if n > 0 => Some(n),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "max-line-bytes",
+                matches.opt_str("max-line-bytes").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let max_line_action = match matches.opt_str("max-line-action").as_deref() {
+        None | Some("error") => UnweaveMaxLineAction::Error,
+        Some("truncate") => UnweaveMaxLineAction::Truncate,
+        Some(a) => bail!(UnweaveError::InvalidOptionValue("max-line-action", a.to_string())),
+    };
+
+    let column_labels = match matches.opt_str("column-labels").as_deref() {
+        None | Some("tag") => UnweaveColumnLabels::Tag,
+        Some("index") => UnweaveColumnLabels::Index,
+        Some("none") => UnweaveColumnLabels::None,
+        Some(l) => bail!(UnweaveError::InvalidOptionValue("column-labels", l.to_string())),
+    };
+
+    let null_data = matches.opt_present("null-data");
+    let line_ending_detect = matches.opt_present("line-ending-detect");
+    if null_data && (matches.opt_present("line-ending") || line_ending_detect) {
+        bail!(UnweaveError::NullDataAndLineEnding);
+    }
+
+    let char_width = match matches.opt_str("char-width").as_deref() {
+        None | Some("display") => UnweaveCharWidth::Display,
+        Some("mono") => UnweaveCharWidth::Mono,
+        Some(c) => bail!(UnweaveError::InvalidOptionValue("char-width", c.to_string())),
+    };
+
+    let show_control = match matches.opt_str("show-control").as_deref() {
+        None | Some("raw") => UnweaveShowControl::Raw,
+        Some("caret") => UnweaveShowControl::Caret,
+        Some("hex") => UnweaveShowControl::Hex,
+        Some("drop") => UnweaveShowControl::Drop,
+        Some(c) => bail!(UnweaveError::InvalidOptionValue("show-control", c.to_string())),
+    };
+
+    let fill_char = opt_str_unescaped(&matches, "fill-char")?.unwrap_or(" ".to_string());
+
+    let split_on = matches.opt_str("split-on");
+    if split_on.as_deref() == Some("") {
+        bail!(UnweaveError::InvalidOptionValue("split-on", String::new()));
+    }
+
+    let blank = match matches.opt_str("blank").as_deref() {
+        None | Some("drop") => UnweaveBlank::Drop,
+        Some("last-column") => UnweaveBlank::LastColumn,
+        Some("all-columns") => UnweaveBlank::AllColumns,
+        Some(b) => bail!(UnweaveError::InvalidOptionValue("blank", b.to_string())),
+    };
+
+    let interleave = match matches.opt_str("interleave").as_deref() {
+        None | Some("input") => UnweaveInterleave::Input,
+        Some("roundrobin") => UnweaveInterleave::RoundRobin,
+        Some(i) => bail!(UnweaveError::InvalidOptionValue("interleave", i.to_string())),
+    };
+
+    let rr_fill = match matches.opt_str("rr-fill").as_deref() {
+        None | Some("skip") => UnweaveRrFill::Skip,
+        Some("pad") => UnweaveRrFill::Pad,
+        Some(f) => bail!(UnweaveError::InvalidOptionValue("rr-fill", f.to_string())),
+    };
+
+    let sanitize_filenames = match matches.opt_default("sanitize-filenames", "/\0") {
+        Some(c) if c.is_empty() => bail!(UnweaveError::InvalidOptionValue("sanitize-filenames", c)),
+        c => c,
+    };
+
+    let filename_case = match matches.opt_str("filename-case").as_deref() {
+        None | Some("preserve") => UnweaveFilenameCase::Preserve,
+        Some("lower") => UnweaveFilenameCase::Lower,
+        Some("upper") => UnweaveFilenameCase::Upper,
+        Some(c) => bail!(UnweaveError::InvalidOptionValue("filename-case", c.to_string())),
+    };
+
+    let wrap = match matches.opt_str("wrap").as_deref() {
+        None | Some("char") => UnweaveWrap::Char,
+        Some("word") => UnweaveWrap::Word,
+        Some(w) => bail!(UnweaveError::InvalidOptionValue("wrap", w.to_string())),
+    };
+
+    let align = match matches.opt_str("align").as_deref() {
+        None | Some("left") => UnweaveAlign::Left,
+        Some("right") => UnweaveAlign::Right,
+        Some(a) => bail!(UnweaveError::InvalidOptionValue("align", a.to_string())),
+    };
+
+    let split_lines = match matches.opt_get::<u64>("split-lines") {
+        Ok(None) => None,
+        Ok(Some(n)) if n > 0 => Some(n),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "split-lines",
+                matches.opt_str("split-lines").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let tab_origin = match matches.opt_str("tab-origin").as_deref() {
+        None | Some("column") => UnweaveTabOrigin::Column,
+        Some("line") => UnweaveTabOrigin::Line,
+        Some(o) => bail!(UnweaveError::InvalidOptionValue("tab-origin", o.to_string())),
+    };
+
+    let truncate_marker = match opt_str_unescaped(&matches, "truncate-marker")? {
+        Some(m) => Some(m),
+        None if matches.opt_present("truncate") => Some("…".to_string()),
+        None => None,
+    };
+
+    // Inverted matching drops matching lines, so there are no unmatched lines
+    if matches.opt_present("invert") &&
+        (matches.opt_present("unmatched") || matches.opt_present("unmatched-file"))
+    {
+        bail!(UnweaveError::InvertAndUnmatched);
+    }
+
+    if matches.opt_present("tag-group") && matches.opt_present("tag-join") {
+        bail!(UnweaveError::TagGroupAndTagJoin);
+    }
+
+    if matches.opt_present("whole-line-tag") &&
+       (matches.opt_present("tag-group") || matches.opt_present("tag-join")) {
+        bail!(UnweaveError::WholeLineTagAndTagGroup);
+    }
+
+    if matches.opt_present("strip-tag") &&
+       ["tag-join", "whole-line-tag", "invert"].iter().any(|o| matches.opt_present(o)) {
+        bail!(UnweaveError::InvalidStripTag);
+    }
+
+    if matches.opt_present("per-input-columns") && matches.opt_present("column-map") {
+        bail!(UnweaveError::PerInputColumnsAndColumnMap);
+    }
+
+    let tag_group = match matches.opt_str("tag-group") {
+        None => UnweaveTagGroup::Last,
+        Some(g) if g.is_empty() => bail!(UnweaveError::InvalidOptionValue("tag-group", g)),
+        Some(g) => match g.parse::<usize>() {
+            Ok(i) => UnweaveTagGroup::Index(i),
+            Err(_) => UnweaveTagGroup::Name(g),
+        },
+    };
+
+    let size_limit = match matches.opt_get::<usize>("size-limit") {
+        Ok(None) => None,
+        Ok(Some(s)) if s > 0 => Some(s),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "size-limit",
+                matches.opt_str("size-limit").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let match_window = match matches.opt_get::<usize>("match-window") {
+        Ok(None) => None,
+        Ok(Some(w)) if w > 0 => Some(w),
+        _ => bail!(
+            UnweaveError::InvalidOptionValue(
+                "match-window",
+                matches.opt_str("match-window").unwrap_or("".to_string())
+            )
+        ),
+    };
+
+    let tag_field = match matches.opt_str("tag-field") {
+        None => None,
+        Some(f) => match f.rsplit_once(':').map(|(d, n)| (d, n.parse::<usize>())) {
+            Some((d, Ok(n))) if !d.is_empty() && n > 0 => Some((d.to_string(), n)),
+            _ => bail!(UnweaveError::InvalidOptionValue("tag-field", f)),
+        },
+    };
+
+    let mut regex_flags = UnweaveRegexFlags::default();
+    if let Some(flags) = matches.opt_str("regex-flags") {
+        for flag in flags.chars() {
+            match flag {
+                'm' => regex_flags.multi_line = true,
+                's' => regex_flags.dot_matches_new_line = true,
+                'U' => regex_flags.swap_greed = true,
+                'x' => regex_flags.ignore_whitespace = true,
+                _ => bail!(UnweaveError::InvalidOptionValue("regex-flags", flags)),
+            }
+        }
+    }
+
+    let mut tag_replace = Vec::new();
+    for r in matches.opt_strs("tag-replace") {
+        match r.split_once('=') {
+            Some((p, r)) if !p.is_empty() => tag_replace.push((p.to_string(), r.to_string())),
+            _ => bail!(UnweaveError::InvalidOptionValue("tag-replace", r)),
+        }
+    }
+
+    let tag = UnweaveOptionsTag {
+        patterns,
+        group: tag_group,
+        join: matches.opt_str("tag-join"),
+        ignore_case: matches.opt_present("ignore-case"),
+        replace: tag_replace,
+        invert: matches.opt_present("invert"),
+        trim: matches.opt_present("trim-tag"),
+        size_limit,
+        regex_flags,
+        match_window,
+        field: tag_field,
+        whole_line: matches.opt_present("whole-line-tag"),
+        strip: matches.opt_present("strip-tag"),
+    };
+
+    match mode.as_str() {
+        "columns" | "grouped" | "test" => {
+            let opts = UnweaveOptionsColumns {
+                tag,
+                output: matches.opt_str("output").map(PathBuf::from),
+                inputs,
+                mmap,
+                width,
+                column_separator: opt_str_unescaped(&matches, "column-separator")?,
+                two_pass,
+                tab,
+                unmatched,
+                header: matches.opt_present("header"),
+                footer: matches.opt_present("footer"),
+                truncate_marker,
+                align,
+                tab_origin,
+                sort_columns: matches.opt_present("sort-columns"),
+                reverse_columns: matches.opt_present("reverse-columns"),
+                max_columns,
+                max_lines_per_stream,
+                line_numbers,
+                show_offset: matches.opt_present("show-offset"),
+                follow,
+                char_width,
+                show_control,
+                line_ending,
+                stats: matches.opt_present("stats"),
+                require_match: matches.opt_present("require-match"),
+                column_map: matches.opt_str("column-map").map(PathBuf::from),
+                per_input_columns: matches.opt_present("per-input-columns"),
+                jobs,
+                max_cache_bytes,
+                spill_stdin,
+                fill_char,
+                outer_separator: matches.opt_present("outer-separator"),
+                sparse_separators: matches.opt_present("sparse-separators"),
+                split_lines,
+                uniq: matches.opt_present("uniq"),
+                highlight,
+                binary,
+                max_line_bytes,
+                max_line_action,
+                interleave,
+                rr_fill,
+                blank,
+                buffer_size,
+                strict_single_pass: matches.opt_present("strict-single-pass"),
+                column_width_overrides: column_widths.1,
+                min_column_width,
+                wrap,
+                wrap_marker: opt_str_unescaped(&matches, "wrap-marker")?,
+                warn_invalid_utf8: matches.opt_present("warn-invalid-utf8"),
+                null_data,
+                record_separator: matches.opt_str("record-separator"),
+                sort_key: matches.opt_str("sort-key"),
+                layout: matches.opt_str("layout").map(PathBuf::from),
+                split_on,
+                group_separator: opt_str_unescaped(&matches, "group-separator")?.unwrap_or_default(),
+                progress,
+                column_labels,
+                line_buffered: matches.opt_present("line-buffered"),
+                line_ending_detect,
+            };
+
+            opts.validate()?;
+
+            match mode.as_str() {
+                "grouped" => Ok(UnweaveOptions::Grouped(opts)),
+                "test" => Ok(UnweaveOptions::Test(opts)),
+                _ => Ok(UnweaveOptions::Columns(opts)),
+            }
+        },
+        "files" => {
+            let opts = UnweaveOptionsFiles {
+                tag,
+                output: matches.opt_str("output").map(PathBuf::from),
+                inputs,
+                mmap,
+                unmatched_file: matches.opt_str("unmatched-file").map(PathBuf::from),
+                max_lines_per_stream,
+                follow,
+                line_ending,
+                append: matches.opt_present("append"),
+                prefix_tag: matches.opt_present("prefix-tag"),
+                stats: matches.opt_present("stats"),
+                require_match: matches.opt_present("require-match"),
+                stable_numbers: matches.opt_present("stable-numbers"),
+                filename_case,
+                sanitize_filenames,
+                no_merge: matches.opt_present("no-merge"),
+                manifest: matches.opt_str("manifest").map(PathBuf::from),
+                max_open_files,
+                buffer_size,
+                null_data,
+                dry_run: matches.opt_present("dry-run"),
+                progress,
+                line_buffered: matches.opt_present("line-buffered"),
+                line_ending_detect,
+                count_only: matches.opt_present("count-only"),
+                preserve_eol: matches.opt_present("preserve-eol"),
+                keep_going: matches.opt_present("keep-going"),
+                jobs,
+                uniq: matches.opt_present("uniq"),
+                highlight,
+                binary,
+                max_line_bytes,
+                max_line_action,
+            };
+
+            opts.validate()?;
+
+            Ok(UnweaveOptions::Files(opts))
+        },
+        _ => panic!(),
+    }
+}
+
+/// Column widths for the columns of specific tags.
+type ColumnWidthOverrides = Vec<(String, u32)>;
+
+/// The options that are invalid in files mode.
+const FILES_INVALID_OPTIONS: &[&str] = &[
+    "line-width", "column-width", "two-pass", "tab-width", "tab-origin", "unmatched",
+    "header", "footer", "column-labels", "truncate", "truncate-marker", "wrap", "wrap-marker", "align",
+    "sort-columns", "reverse-columns",
+    "min-column-width", "max-columns", "line-numbers", "show-offset", "char-width", "show-control",
+    "fill-char", "outer-separator",
+    "sparse-separators",
+    "column-map", "per-input-columns", "interleave", "rr-fill", "blank",
+    "max-cache-bytes", "spill-stdin", "strict-single-pass",
+    "warn-invalid-utf8", "record-separator", "sort-key", "split-on", "split-lines",
+    "layout",
+];
+
+/// The options that are invalid in grouped mode.
+const GROUPED_INVALID_OPTIONS: &[&str] = &[
+    "line-width", "column-width", "two-pass", "tab-width", "tab-origin",
+    "footer", "truncate", "truncate-marker", "wrap", "wrap-marker", "align",
+    "min-column-width", "line-numbers", "show-offset", "char-width",
+    "fill-char", "column-separator", "outer-separator", "sparse-separators",
+    "rr-fill", "max-cache-bytes", "spill-stdin", "strict-single-pass",
+    "warn-invalid-utf8", "split-lines", "follow", "layout",
+];
+
+/// The options that are invalid in test mode.
+const TEST_INVALID_OPTIONS: &[&str] = &[
+    "line-width", "column-width", "column-separator", "two-pass", "max-cache-bytes",
+    "spill-stdin", "jobs", "record-separator", "sort-key", "split-on", "strict-single-pass",
+    "layout",
+    "stats", "progress", "line-buffered", "follow", "tab-width", "tab-origin",
+    "highlight", "strip-tag", "unmatched", "header", "footer", "column-labels",
+    "truncate", "truncate-marker", "blank", "interleave", "rr-fill", "wrap",
+    "wrap-marker", "align", "sort-columns", "reverse-columns", "column-map",
+    "per-input-columns", "min-column-width", "max-columns", "max-lines-per-stream",
+    "uniq", "outer-separator", "split-lines", "sparse-separators", "fill-char",
+    "char-width", "show-control", "line-numbers", "show-offset",
+];
+
+/// The options that are only valid in grouped mode.
+const GROUPED_ONLY_OPTIONS: &[&str] = &["group-separator"];
+
+/// The options that are only valid in files mode.
+const FILES_ONLY_OPTIONS: &[&str] = &[
+    "unmatched-file", "append", "prefix-tag", "stable-numbers",
+    "filename-case", "sanitize-filenames", "no-merge", "max-open-files", "dry-run",
+    "count-only", "manifest", "preserve-eol", "keep-going",
+];
+
+/// Returns the options that are invalid for the specified mode.
+fn invalid_options_for_mode(mode: &str) -> impl Iterator<Item=&'static str> {
+    let invalid: &[&str] = match mode {
+        "files" => FILES_INVALID_OPTIONS,
+        "grouped" => GROUPED_INVALID_OPTIONS,
+        "test" => TEST_INVALID_OPTIONS,
+        _ => &[],
+    };
+    let grouped_only = if mode == "grouped" { &[][..] } else { GROUPED_ONLY_OPTIONS };
+    let files_only = if mode == "files" { &[][..] } else { FILES_ONLY_OPTIONS };
+
+    invalid.iter().chain(grouped_only).chain(files_only).copied()
+}
+
+/// The options that can be set in the config file.
+const CONFIG_OPTIONS: &[&str] = &[
+    "mode", "column-separator", "column-width", "line-width", "tab-width", "highlight",
+];
+
+/// Returns the path of the default config file, if it exists.
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("unweave").join("config")).filter(|p| p.is_file())
+}
+
+/// Reads a config file, in which each line is either empty, a comment starting
+/// with '#', or a "key = value" entry, where key is the name of an option.
+/// Values may be enclosed in double quotes, e.g., to preserve spaces at their
+/// start or end. Returns the entries, with a single entry for each option.
+fn read_config(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = std::fs::read_to_string(path).with_context(
+        || format!("Failed to read config file {}", path.display())
+    )?;
+    let mut entries = Vec::new();
+
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => (k.trim(), v.trim()),
+            _ => bail!(UnweaveError::InvalidConfigEntry(path.to_path_buf(), n + 1)),
+        };
+        if !CONFIG_OPTIONS.contains(&key) {
+            bail!(UnweaveError::UnknownConfigOption(path.to_path_buf(), key.to_string()));
+        }
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+        // Later entries override earlier entries for the same option
+        entries.retain(|(k, _)| k != key);
+        entries.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(entries)
+}
+
+/// Returns the options from the config file as arguments, leaving out options
+/// specified on the command line, and options that are invalid for the
+/// selected mode. The line and column width options are mutually exclusive,
+/// so neither is taken from the config file if either is specified on the
+/// command line.
+fn read_config_args(matches: &getopts::Matches, default_config: Option<&Path>)
+    -> Result<Vec<OsString>>
+{
+    let path = match (matches.opt_str("config"), default_config) {
+        (Some(p), _) => PathBuf::from(p),
+        (None, Some(p)) => p.to_path_buf(),
+        (None, None) => return Ok(Vec::new()),
+    };
+    let entries = read_config(&path)?;
+
+    let config_mode = entries.iter().find(|(k, _)| k == "mode").map(|(_, v)| v.clone());
+    let mode = matches.opt_str("mode").or(config_mode).unwrap_or("columns".to_string());
+    let width_present = matches.opt_present("column-width") || matches.opt_present("line-width");
+
+    Ok(
+        entries.into_iter()
+            .filter(|(k, _)| !matches.opt_present(k))
+            .filter(|(k, _)| !(width_present && (k == "column-width" || k == "line-width")))
+            .filter(|(k, _)| !invalid_options_for_mode(&mode).any(|o| o == k))
+            .map(|(k, v)| OsString::from(format!("--{}={}", k, v)))
+            .collect()
+    )
+}
+
+/// Parses the value of the column-width option, which is a comma separated
+/// list of a global column width and TAG:WIDTH entries, each of them optional.
+/// The global column width may also be a percentage of the line width, e.g.,
+/// "20%". Returns the global column width, if any, and the per tag column
+/// widths.
+fn parse_column_widths(s: &str) -> Result<(Option<UnweaveWidth>, ColumnWidthOverrides)> {
+    let invalid = || UnweaveError::InvalidOptionValue("column-width", s.to_string());
+    let mut global = None;
+    let mut overrides = Vec::new();
+
+    for entry in s.split(',') {
+        match entry.rsplit_once(':') {
+            Some((tag, w)) if !tag.is_empty() => match w.parse::<u32>() {
+                Ok(w) if w > 0 => overrides.push((tag.to_string(), w)),
+                _ => bail!(invalid()),
+            },
+            None if global.is_none() => match entry.strip_suffix('%') {
+                Some(p) => match p.parse::<u32>() {
+                    Ok(p) if p > 0 && p <= 100 => global = Some(UnweaveWidth::Percent(p, None)),
+                    _ => bail!(invalid()),
+                },
+                None => match entry.parse::<u32>() {
+                    Ok(w) if w > 0 => global = Some(UnweaveWidth::Column(w)),
+                    _ => bail!(invalid()),
+                },
+            },
+            _ => bail!(invalid()),
+        }
+    }
+
+    Ok((global, overrides))
+}
+
+/// Returns the value of an option with any escape sequences decoded.
+fn opt_str_unescaped(matches: &getopts::Matches, name: &'static str) -> Result<Option<String>> {
+    match matches.opt_str(name) {
+        Some(s) => match unescape(&s) {
+            Some(u) => Ok(Some(u)),
+            None => bail!(UnweaveError::InvalidOptionValue(name, s)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Decodes the escape sequences \t, \n, \r, \0, \\, \xNN and \u{N...} in
+/// a string. Returns None if the string contains an invalid escape sequence.
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let c = match chars.next()? {
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            'x' => {
+                let rest = chars.as_str();
+                let hex = rest.get(..2)?;
+                chars = rest[2..].chars();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) if b.is_ascii() => b as char,
+                    _ => return None,
+                }
+            },
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                let hex = &rest[..end];
+                chars = rest[end + 1..].chars();
+                if hex.is_empty() || hex.len() > 6 {
+                    return None;
+                }
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            },
+            _ => return None,
+        };
+        unescaped.push(c);
+    }
+
+    Some(unescaped)
+}
+
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    let opts = parse_options(&args[1..])?;
+    let opts = parse_options(&args[1..], default_config_path().as_deref(), &Environment::from_process())?;
+
+    let follow = match &opts {
+        UnweaveOptions::Files(o) => o.follow,
+        UnweaveOptions::Columns(o) | UnweaveOptions::Grouped(o) | UnweaveOptions::Test(o) => o.follow,
+    };
+
+    // Stop following on SIGINT, so that any buffered output is flushed.
+    if follow {
+        stop_following_on_interrupt()?;
+    }
+
+    unweave(&opts)
+}
+
+/// Perform the unweave operation selected by the options.
+fn unweave(opts: &UnweaveOptions) -> Result<()> {
+    let (matched, require_match) = match opts {
+        UnweaveOptions::Files(o) => (unweave_into_files(o)?, o.require_match),
+        UnweaveOptions::Columns(o) => (unweave_into_columns(o)?, o.require_match),
+        UnweaveOptions::Grouped(o) => (unweave_into_grouped(o)?, o.require_match),
+        UnweaveOptions::Test(o) => (test_pattern(o)?, o.require_match),
+    };
+
+    if require_match && matched == 0 {
+        bail!(UnweaveError::NoMatch);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses options without a default config file and environment
+    /// variables, so that the tests don't depend on the config file and
+    /// environment of the user running them.
+    fn parse_options(args: &[impl AsRef<std::ffi::OsStr>]) -> Result<UnweaveOptions> {
+        super::parse_options(args, None, &Environment::default())
+    }
+
+    #[test]
+    fn options_require_pattern() {
+        let opts = parse_options(&["--mode=columns"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=columns", ""]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=columns", "bla"]);
+        assert!(opts.is_ok());
+    }
+
+    #[test]
+    fn options_pattern_file() {
+        let tmpdir = tempdir::TempDir::new("unweave-test").unwrap();
+        let pattern_file = tmpdir.path().join("pattern");
+        let input = tmpdir.path().join("input");
+        let output = tmpdir.path().join("output");
+        std::fs::write(&pattern_file, b"^(\\w+):\n").unwrap();
+        std::fs::write(&input, b"A: 1\nB: 1\n").unwrap();
+
+        let pattern_file_opt = format!("--pattern-file={}", pattern_file.display());
+        let output_opt = format!("--output={}", output.display());
+        let opts = parse_options(&[&pattern_file_opt, &output_opt, "-s", "|",
+                                   input.to_str().unwrap()]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.patterns == ["^(\\w+):"]);
+        assert!(opts.inputs == [input]);
+
+        unweave_into_columns(&opts).unwrap();
+        assert!(std::fs::read_to_string(&output).unwrap() == "A: 1|\n    |B: 1\n");
+
+        std::fs::write(&pattern_file, b"\n").unwrap();
+        let opts = parse_options(&[&pattern_file_opt, "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--pattern-file=/nonexistent/pattern", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_pattern_and_pattern_file() {
+        let tmpdir = tempdir::TempDir::new("unweave-test").unwrap();
+        let pattern_file = tmpdir.path().join("pattern");
+        let input = tmpdir.path().join("input");
+        std::fs::write(&pattern_file, b"^(\\w+):\n").unwrap();
+        std::fs::write(&input, b"A: 1\n").unwrap();
+
+        let pattern_file_opt = format!("--pattern-file={}", pattern_file.display());
+        let opts = parse_options(&[&pattern_file_opt, "^(\\w+):", input.to_str().unwrap()]);
+        assert!(matches!(opts.err().unwrap().downcast_ref(),
+                         Some(UnweaveError::PatternAndPatternFile(p)) if p == "^(\\w+):"));
+
+        // Existing files and standard input are input files
+        let opts = parse_options(&[&pattern_file_opt, input.to_str().unwrap(), "-"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.inputs == [input, stdin_path()]);
+    }
+
+    #[test]
+    fn options_inputs_from() {
+        let tmpdir = tempdir::TempDir::new("unweave-test").unwrap();
+        let inputs_file = tmpdir.path().join("inputs");
+        let input1 = tmpdir.path().join("input1");
+        let input2 = tmpdir.path().join("input2");
+        let output = tmpdir.path().join("output");
+        std::fs::write(&inputs_file, format!("{}\n\n{}\n", input1.display(), input2.display())).unwrap();
+        std::fs::write(&input1, b"A:1\nB:1\n").unwrap();
+        std::fs::write(&input2, b"A:2\n").unwrap();
+
+        let inputs_from_opt = format!("--inputs-from={}", inputs_file.display());
+        let output_opt = format!("--output={}", output.display());
+        let opts = parse_options(&[&inputs_from_opt, &output_opt, "-s", "|", "A|B"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.inputs == [input1.clone(), input2.clone()]);
+
+        unweave_into_columns(&opts).unwrap();
+        assert!(std::fs::read_to_string(&output).unwrap() == "A:1|\n   |B:1\nA:2|\n");
+
+        // Listed inputs follow positional inputs, and "-" is the standard input
+        std::fs::write(&inputs_file, b"-\n").unwrap();
+        let opts = parse_options(&[&inputs_from_opt, "A|B", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.inputs == [PathBuf::from("bla"), stdin_path()]);
+
+        let opts = parse_options(&["--inputs-from=/nonexistent/inputs", "A|B"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_dash_is_stdin() {
+        let opts = parse_options(&["bla", "input1", "-"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.inputs == [PathBuf::from("input1"), stdin_path()]);
+    }
+
+    #[test]
+    fn options_multiple_patterns() {
+        let opts = parse_options(&["--pattern=b", "--pattern", "c", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.patterns == ["a", "b", "c"]);
+        assert!(opts.inputs == [PathBuf::from("bla")]);
+        let opts = parse_options(&["--pattern=", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_invert() {
+        let opts = parse_options(&["--invert", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.invert);
+        let opts = parse_options(&["--invert", "--unmatched=last", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "%t", "--invert",
+                                   "--unmatched-file=u", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_require_output_for_files() {
+        let opts = parse_options(&["--mode=files", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_for_files_do_not_accept_width() {
+        let opts = parse_options(&["--mode=files", "--column-width=10", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "--line-width=10", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_do_not_accept_both_column_and_line_width() {
+        let opts = parse_options(&["--mode=files", "--column-width=10",
+                                   "--line-width=10", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_processed_for_columns() {
+        let opts = parse_options(&["--mode=columns", "--column-width=10", "--output=output1",
+                                   "--tab-width=3", "bla", "input1", "input2"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.patterns == ["bla"]);
+        assert!(opts.output == Some(PathBuf::from("output1")));
+        assert!(opts.width == UnweaveWidth::Column(10));
+        assert!(opts.inputs == [PathBuf::from("input1"), PathBuf::from("input2")]);
+        assert!(opts.mmap == UnweaveMmap::Allow);
+        assert!(opts.tab == UnweaveTab::Expand(3));
+    }
+
+    #[test]
+    fn options_processed_for_files() {
+        let opts = parse_options(&["--mode=files", "--output=output1",
+                                   "bla", "input1", "input2"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.patterns == ["bla"]);
+        assert!(opts.output == Some(PathBuf::from("output1")));
+        assert!(opts.inputs == [PathBuf::from("input1"), PathBuf::from("input2")]);
+        assert!(opts.mmap == UnweaveMmap::Allow);
+    }
+
+    #[test]
+    fn options_input_from_stdin_adds_dev_stdin() {
+        let opts = parse_options(&["--mode=files", "--output=output1", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.patterns == ["bla"]);
+        assert!(opts.output == Some(PathBuf::from("output1")));
+        assert!(opts.inputs == [stdin_path()]);
+    }
+
+    #[test]
+    fn options_no_mmap() {
+        let opts = parse_options(&["--no-mmap", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.mmap == UnweaveMmap::Disallow);
+    }
+
+    #[test]
+    fn options_short() {
+        let opts = parse_options(&["-m", "columns", "-c", "10", "-o", "output1",
+                                   "-t", "7", "-n", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.patterns == ["bla"]);
+        assert!(opts.output == Some(PathBuf::from("output1")));
+        assert!(opts.width == UnweaveWidth::Column(10));
+        assert!(opts.inputs == [stdin_path()]);
+        assert!(opts.mmap == UnweaveMmap::Disallow);
+        assert!(opts.tab == UnweaveTab::Expand(7));
+    }
+
+    #[test]
+    fn options_tag_group() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.group == UnweaveTagGroup::Last);
+        let opts = parse_options(&["--tag-group=2", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.group == UnweaveTagGroup::Index(2));
+        let opts = parse_options(&["-g", "name", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.group == UnweaveTagGroup::Name("name".to_string()));
+    }
+
+    #[test]
+    fn options_tag_replace() {
+        let opts = parse_options(&["--tag-replace=-[0-9]+=", "--tag-replace=a=b=c", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.replace == vec![("-[0-9]+".to_string(), "".to_string()),
+                                         ("a".to_string(), "b=c".to_string())]);
+        let opts = parse_options(&["--tag-replace=bla", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--tag-replace==bla", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_do_not_accept_both_tag_group_and_tag_join() {
+        let opts = parse_options(&["--tag-group=1", "--tag-join=-", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--tag-join=-", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.join == Some("-".to_string()));
+    }
+
+    #[test]
+    fn options_ignore_case() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.tag.ignore_case);
+        let opts = parse_options(&["-i", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.ignore_case);
+    }
+
+    #[test]
+    fn options_size_limit() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.size_limit.is_none());
+
+        let opts = parse_options(&["--size-limit=4096", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.size_limit == Some(4096));
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--size-limit=4096", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.size_limit == Some(4096));
+
+        let opts = parse_options(&["--size-limit=0", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--size-limit=x", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_regex_flags() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.regex_flags == UnweaveRegexFlags::default());
+
+        let opts = parse_options(&["--regex-flags=msUx", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.regex_flags == UnweaveRegexFlags {
+            multi_line: true, dot_matches_new_line: true, swap_greed: true, ignore_whitespace: true
+        });
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--regex-flags=x", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.regex_flags.ignore_whitespace);
+
+        let opts = parse_options(&["--regex-flags=mi", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_tag_field() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.field.is_none());
+
+        let opts = parse_options(&["--tag-field=,:3", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.field == Some((",".to_string(), 3)));
+
+        // The field number follows the last ':'
+        let opts = parse_options(&["--mode=files", "-o", "out", "--tag-field=::2", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.field == Some((":".to_string(), 2)));
+
+        for field in ["--tag-field=,:0", "--tag-field=:2", "--tag-field=,", "--tag-field=,:x"] {
+            let opts = parse_options(&[field, "bla"]);
+            assert!(opts.is_err());
+        }
+    }
+
+    #[test]
+    fn options_match_window() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.match_window.is_none());
+
+        let opts = parse_options(&["--match-window=64", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.match_window == Some(64));
+
+        let opts = parse_options(&["--match-window=0", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--match-window=x", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_whole_line_tag() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.tag.whole_line);
+
+        let opts = parse_options(&["--whole-line-tag", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.whole_line);
+
+        let opts = parse_options(&["--whole-line-tag", "--tag-group=1", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--whole-line-tag", "--tag-join=-", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_strip_tag() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.tag.strip);
+
+        let opts = parse_options(&["--strip-tag", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.strip);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--strip-tag", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.strip);
+
+        for opt in &["--tag-join=-", "--whole-line-tag", "--invert"] {
+            let opts = parse_options(&["--strip-tag", opt, "bla"]);
+            assert!(opts.is_err());
+        }
+    }
+
+    #[test]
+    fn options_unmatched() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.unmatched == UnweaveUnmatched::Drop);
+        let opts = parse_options(&["--unmatched=last", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.unmatched == UnweaveUnmatched::Last);
+        let opts = parse_options(&["--unmatched=middle", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--unmatched=first", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_unmatched_file() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--unmatched-file=rest", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.unmatched_file == Some(PathBuf::from("rest")));
+        let opts = parse_options(&["--unmatched-file=rest", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_prefix_tag() {
+        let opts = parse_options(&["--mode=files", "-o", "-", "--prefix-tag", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.prefix_tag);
+        assert!(opts.output == Some(PathBuf::from("-")));
+        let opts = parse_options(&["--prefix-tag", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_append() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--append", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.append);
+        let opts = parse_options(&["--append", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_truncate() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.truncate_marker.is_none());
+        let opts = parse_options(&["--truncate", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.truncate_marker == Some("…".to_string()));
+        let opts = parse_options(&["--truncate-marker=>", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.truncate_marker == Some(">".to_string()));
+    }
+
+    #[test]
+    fn options_align() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.align == UnweaveAlign::Left);
+        let opts = parse_options(&["--align=right", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.align == UnweaveAlign::Right);
+        let opts = parse_options(&["--align=center", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_tab_origin() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tab_origin == UnweaveTabOrigin::Column);
+        let opts = parse_options(&["--tab-origin=line", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tab_origin == UnweaveTabOrigin::Line);
+        let opts = parse_options(&["--tab-origin=input", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--tab-origin=line", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_max_columns() {
+        let opts = parse_options(&["--max-columns=3", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.max_columns == Some(3));
+        let opts = parse_options(&["--max-columns=0", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_max_lines_per_stream() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--max-lines-per-stream=2",
+                                   "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.max_lines_per_stream == Some(2));
+        let opts = parse_options(&["--max-lines-per-stream=0", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_show_offset() {
+        let opts = parse_options(&["--show-offset", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.show_offset);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--show-offset", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_line_numbers() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_numbers == UnweaveLineNumbers::Disabled);
+        let opts = parse_options(&["--line-numbers", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_numbers == UnweaveLineNumbers::Blank);
+        let opts = parse_options(&["--line-numbers=repeat", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_numbers == UnweaveLineNumbers::Repeat);
+    }
+
+    #[test]
+    fn options_follow() {
+        let opts = parse_options(&["-c", "10", "--follow", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.follow);
+        assert!(opts.mmap == UnweaveMmap::Disallow);
+        let opts = parse_options(&["--mode=files", "-o", "out", "--follow", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.follow);
+        assert!(opts.mmap == UnweaveMmap::Disallow);
+        let opts = parse_options(&["--follow", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["-c", "10", "--header", "--follow", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_char_width() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.char_width == UnweaveCharWidth::Display);
+        let opts = parse_options(&["--char-width=mono", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.char_width == UnweaveCharWidth::Mono);
+        let opts = parse_options(&["--char-width=bla", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_show_control() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.show_control == UnweaveShowControl::Raw);
+        let opts = parse_options(&["--show-control=caret", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.show_control == UnweaveShowControl::Caret);
+        let opts = parse_options(&["--show-control=hex", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.show_control == UnweaveShowControl::Hex);
+        let opts = parse_options(&["--show-control=drop", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.show_control == UnweaveShowControl::Drop);
+        let opts = parse_options(&["--show-control=bla", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--show-control=caret", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_line_ending() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_ending == UnweaveLineEnding::Lf);
+        let opts = parse_options(&["--line-ending=crlf", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_ending == UnweaveLineEnding::Crlf);
+        let opts = parse_options(&["--mode=files", "-o", "out", "--line-ending=crlf", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.line_ending == UnweaveLineEnding::Crlf);
+        let opts = parse_options(&["--line-ending=cr", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_stats() {
+        let opts = parse_options(&["--stats", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.stats);
+        let opts = parse_options(&["--mode=files", "-o", "out", "--stats", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.stats);
+    }
+
+    #[test]
+    fn options_column_map() {
+        let opts = parse_options(&["--column-map=map", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.column_map == Some(PathBuf::from("map")));
+        let opts = parse_options(&["--mode=files", "-o", "out", "--column-map=map", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_per_input_columns() {
+        let opts = parse_options(&["--per-input-columns", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.per_input_columns);
+        let opts = parse_options(&["--per-input-columns", "--column-map=map", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--per-input-columns", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_jobs() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.jobs == 1);
+        let opts = parse_options(&["-j", "4", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.jobs == 4);
+        let opts = parse_options(&["--jobs=0", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--jobs=2", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.jobs == 2);
+    }
+
+    #[test]
+    fn options_max_cache_bytes() {
+        let opts = parse_options(&["--max-cache-bytes=1000", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.max_cache_bytes == Some(1000));
+        let opts = parse_options(&["--max-cache-bytes=0", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_spill_stdin() {
+        let opts = parse_options(&["--spill-stdin", "--two-pass=reread", "-"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.spill_stdin);
+        let opts = parse_options(&["--mode=files", "--spill-stdin", "-o", "%t", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_fill_char() {
+        let opts = parse_options(&["--fill-char=.", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.fill_char == ".");
+        assert!(parse_options(&["--fill-char=..", "bla"]).is_err());
+        assert!(parse_options(&["--fill-char=\u{4e2d}", "bla"]).is_err());
+        assert!(parse_options(&["--fill-char=\t", "bla"]).is_err());
+        assert!(parse_options(&["--fill-char=", "bla"]).is_err());
+    }
+
+    #[test]
+    fn options_split_lines() {
+        let opts = parse_options(&["-o", "out", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.split_lines.is_none());
+        let opts = parse_options(&["-o", "out", "--split-lines=1000", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.split_lines == Some(1000));
+        let opts = parse_options(&["--split-lines=1000", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["-o", "out", "--split-lines=0", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "%t", "--split-lines=10", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_sparse_separators() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.sparse_separators);
+        let opts = parse_options(&["--sparse-separators", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.sparse_separators);
+        let opts = parse_options(&["--mode=files", "--sparse-separators", "-o", "%t", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_outer_separator() {
+        let opts = parse_options(&["--outer-separator", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.outer_separator);
+        let opts = parse_options(&["--mode=files", "--outer-separator", "-o", "%t", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_interleave() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.interleave == UnweaveInterleave::Input);
+        let opts = parse_options(&["--interleave=roundrobin", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.interleave == UnweaveInterleave::RoundRobin);
+        let opts = parse_options(&["--interleave=bla", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_rr_fill() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.rr_fill == UnweaveRrFill::Skip);
+        let opts = parse_options(&["--interleave=roundrobin", "--rr-fill=pad", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.rr_fill == UnweaveRrFill::Pad);
+        let opts = parse_options(&["--rr-fill=bla", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--rr-fill=pad", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_blank() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.blank == UnweaveBlank::Drop);
+        let opts = parse_options(&["--blank=last-column", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.blank == UnweaveBlank::LastColumn);
+        let opts = parse_options(&["--blank=all-columns", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.blank == UnweaveBlank::AllColumns);
+        let opts = parse_options(&["--blank=bla", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_require_match() {
+        let tmpdir = tempdir::TempDir::new("unweave-test").unwrap();
+        let input = tmpdir.path().join("input");
+        let output = tmpdir.path().join("output");
+        std::fs::write(&input, b"A:1\nB:1\n").unwrap();
+        let input = input.to_str().unwrap();
+        let output_opt = format!("--output={}", output.display());
+
+        let opts = parse_options(&["--require-match", &output_opt, "Z", input]).unwrap();
+        assert!(matches!(&opts, UnweaveOptions::Columns(o) if o.require_match));
+        let err = unweave(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::NoMatch)));
+
+        let opts = parse_options(&["--require-match", &output_opt, "A", input]).unwrap();
+        assert!(unweave(&opts).is_ok());
+
+        let opts = parse_options(&[&output_opt, "Z", input]).unwrap();
+        assert!(unweave(&opts).is_ok());
+
+        let output_opt = format!("--output={}.%t", output.display());
+        let opts = parse_options(&["--mode=files", "--require-match", &output_opt, "Z", input]).unwrap();
+        assert!(matches!(&opts, UnweaveOptions::Files(o) if o.require_match));
+        let err = unweave(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::NoMatch)));
+    }
+
+    #[test]
+    fn options_stable_numbers() {
+        let tmpdir = tempdir::TempDir::new("unweave-test").unwrap();
+        let input = tmpdir.path().join("input");
+        std::fs::write(&input, b"A:1\n").unwrap();
+        let input = input.to_str().unwrap();
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--stable-numbers", "a", input]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.stable_numbers);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--stable-numbers", "a",
+                                   "/nonexistent/input"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--stable-numbers", "-f", "a", input]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--stable-numbers", "a", input]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_filename_case() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.filename_case == UnweaveFilenameCase::Preserve);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--filename-case=lower", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.filename_case == UnweaveFilenameCase::Lower);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--filename-case=upper", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.filename_case == UnweaveFilenameCase::Upper);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--filename-case=title", "a", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--filename-case=lower", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_sanitize_filenames() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.sanitize_filenames.is_none());
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--sanitize-filenames", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.sanitize_filenames.as_deref() == Some("/\0"));
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--sanitize-filenames=/:", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.sanitize_filenames.as_deref() == Some("/:"));
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--sanitize-filenames=", "a", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--sanitize-filenames", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_max_open_files() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.max_open_files.is_none());
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--max-open-files=2", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.max_open_files == Some(2));
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--max-open-files=0", "a", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--max-open-files=x", "a", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--max-open-files=2", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_buffer_size() {
+        let opts = parse_options(&["a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.buffer_size == DEFAULT_BUFFER_SIZE);
+
+        let opts = parse_options(&["--buffer-size=1048576", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.buffer_size == 1048576);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--buffer-size=16", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.buffer_size == 16);
+
+        let opts = parse_options(&["--buffer-size=0", "a", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--buffer-size=x", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_strict_single_pass() {
+        let opts = parse_options(&["-c", "10", "--strict-single-pass", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.strict_single_pass);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--strict-single-pass", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_column_width_overrides() {
+        let opts = parse_options(&["-c", "10,B:3,C:D:5", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Column(10));
+        assert!(opts.column_width_overrides == [("B".to_string(), 3), ("C:D".to_string(), 5)]);
+
+        let opts = parse_options(&["-c", "B:3", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Undefined);
+        assert!(opts.column_width_overrides == [("B".to_string(), 3)]);
+
+        for cw in &["B:0", ":3", "B:", "10,20", "B:3,", "x"] {
+            let opts = parse_options(&["-c", cw, "a", "bla"]);
+            assert!(opts.is_err());
+        }
+    }
+
+    #[test]
+    fn options_column_width_percent() {
+        let opts = parse_options(&["-c", "20%,B:3", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Percent(20, None));
+        assert!(opts.column_width_overrides == [("B".to_string(), 3)]);
+
+        let opts = parse_options(&["-c", "50%", "-l", "20", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Percent(50, Some(20)));
+
+        let opts = parse_options(&["-c", "50%", "-l", "auto", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Percent(50, None));
+
+        for cw in &["0%", "101%", "%", "x%", "10%,20%"] {
+            let opts = parse_options(&["-c", cw, "a", "bla"]);
+            assert!(opts.is_err());
+        }
+        let opts = parse_options(&["-c", "10", "-l", "20", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_column_width_overrides_with_line_width() {
+        let opts = parse_options(&["-l", "20", "-c", "B:3", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Line(20));
+        assert!(opts.column_width_overrides == [("B".to_string(), 3)]);
+
+        let opts = parse_options(&["-l", "auto", "-c", "B:3", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::LineAuto);
+        assert!(opts.column_width_overrides == [("B".to_string(), 3)]);
+
+        let opts = parse_options(&["-l", "20", "-c", "50%,B:3", "a", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Percent(50, Some(20)));
+        assert!(opts.column_width_overrides == [("B".to_string(), 3)]);
+
+        let opts = parse_options(&["-l", "20", "-c", "10,B:3", "a", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_min_column_width() {
+        let opts = parse_options(&["--min-column-width=6", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.min_column_width == Some(6));
+        let opts = parse_options(&["--min-column-width=0", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--min-column-width=6", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_wrap() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.wrap == UnweaveWrap::Char);
+        let opts = parse_options(&["--wrap=word", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.wrap == UnweaveWrap::Word);
+        let opts = parse_options(&["--wrap=line", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--wrap=word", "bla"]);
+        assert!(opts.is_err());
 
-    Ok(
-        match &opts {
-            UnweaveOptions::Files(o) => unweave_into_files(o),
-            UnweaveOptions::Columns(o) => unweave_into_columns(o),
-        }?
-    )
-}
+        let opts = parse_options(&["--wrap-marker=-", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.wrap_marker.as_deref() == Some("-"));
+        let opts = parse_options(&["--mode=files", "-o", "out", "--wrap-marker=-", "bla"]);
+        assert!(opts.is_err());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn options_warn_invalid_utf8() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.warn_invalid_utf8);
+
+        let opts = parse_options(&["--warn-invalid-utf8", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.warn_invalid_utf8);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--warn-invalid-utf8", "bla"]);
+        assert!(opts.is_err());
+    }
 
     #[test]
-    fn options_require_pattern() {
-        let opts = parse_options(&["--mode=columns"]);
+    fn options_null_data() {
+        let opts = parse_options(&["-z", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.null_data);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--null-data", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.null_data);
+
+        let opts = parse_options(&["--null-data", "--line-ending=crlf", "bla"]);
         assert!(opts.is_err());
-        let opts = parse_options(&["--mode=columns", ""]);
+    }
+
+    #[test]
+    fn options_record_separator() {
+        let opts = parse_options(&["--record-separator=\n\n", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.record_separator.as_deref() == Some("\n\n"));
+
+        let opts = parse_options(&["--record-separator=(", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--record-separator=\n\n", "bla"]);
         assert!(opts.is_err());
-        let opts = parse_options(&["--mode=columns", "bla"]);
-        assert!(opts.is_ok());
     }
 
     #[test]
-    fn options_require_output_for_files() {
-        let opts = parse_options(&["--mode=files", "bla"]);
+    fn options_mode_grouped() {
+        let opts = parse_options(&["--mode=grouped", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Grouped(o) = opts { o } else { panic!("") };
+        assert!(opts.group_separator.is_empty());
+
+        let opts = parse_options(&["--mode=grouped", "--group-separator=--", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Grouped(o) = opts { o } else { panic!("") };
+        assert!(opts.group_separator == "--");
+
+        let opts = parse_options(&["--group-separator=--", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--group-separator=--", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=grouped", "--header", "--column-labels=index", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Grouped(o) = opts { o } else { panic!("") };
+        assert!(opts.header);
+        assert!(opts.column_labels == UnweaveColumnLabels::Index);
+
+        let opts = parse_options(&["--mode=grouped", "--line-width=80", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=grouped", "--unmatched-file=u", "bla"]);
         assert!(opts.is_err());
     }
 
     #[test]
-    fn options_for_files_do_not_accept_width() {
-        let opts = parse_options(&["--mode=files", "--column-width=10", "bla"]);
+    fn options_mode_test() {
+        let opts = parse_options(&["--mode=test", "--tag-join=-", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Test(o) = opts { o } else { panic!("") };
+        assert!(opts.tag.join.as_deref() == Some("-"));
+
+        let opts = parse_options(&["--mode=test", "--column-separator=|", "bla"]);
         assert!(opts.is_err());
-        let opts = parse_options(&["--mode=files", "--line-width=10", "bla"]);
+        let opts = parse_options(&["--mode=test", "--append", "bla"]);
         assert!(opts.is_err());
     }
 
     #[test]
-    fn options_do_not_accept_both_column_and_line_width() {
-        let opts = parse_options(&["--mode=files", "--column-width=10",
-                                   "--line-width=10", "bla"]);
+    fn options_layout() {
+        let opts = parse_options(&["--layout=layout.tsv", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.layout == Some(PathBuf::from("layout.tsv")));
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--layout=layout.tsv", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=grouped", "--layout=layout.tsv", "bla"]);
         assert!(opts.is_err());
     }
 
     #[test]
-    fn options_processed_for_columns() {
-        let opts = parse_options(&["--mode=columns", "--column-width=10", "--output=output1",
-                                   "--tab-width=3", "bla", "input1", "input2"]).unwrap();
+    fn options_sort_key() {
+        let opts = parse_options(&["--sort-key=t=(\\d+)", "bla"]).unwrap();
         let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
-        assert!(opts.pattern == "bla");
-        assert!(opts.output == Some(PathBuf::from("output1")));
-        assert!(opts.width == UnweaveWidth::Column(10));
-        assert!(opts.inputs == [PathBuf::from("input1"), PathBuf::from("input2")]);
-        assert!(opts.mmap == UnweaveMmap::Allow);
-        assert!(opts.tab == UnweaveTab::Expand(3));
+        assert!(opts.sort_key.as_deref() == Some("t=(\\d+)"));
+
+        let opts = parse_options(&["--mode=grouped", "--sort-key=t", "bla"]).unwrap();
+        assert!(matches!(opts, UnweaveOptions::Grouped(o) if o.sort_key.is_some()));
+
+        let opts = parse_options(&["--sort-key=(", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--sort-key=t", "bla"]);
+        assert!(opts.is_err());
     }
 
     #[test]
-    fn options_processed_for_files() {
-        let opts = parse_options(&["--mode=files", "--output=output1",
-                                   "bla", "input1", "input2"]).unwrap();
+    fn options_reverse_columns() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.reverse_columns);
+
+        let opts = parse_options(&["--reverse-columns", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.reverse_columns);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--reverse-columns", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_escapes() {
+        let opts = parse_options(&["-s", "\\t", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.column_separator.as_deref() == Some("\t"));
+
+        let opts = parse_options(&["-s", "a\\\\t\\x41\\u{2502}\\n", "--fill-char=\\u{b7}", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.column_separator.as_deref() == Some("a\\tA\u{2502}\n"));
+        assert!(opts.fill_char == "\u{b7}");
+
+        for s in &["\\", "\\q", "\\x4", "\\xff", "\\u{}", "\\u{41", "\\u41", "\\u{d800}"] {
+            let opts = parse_options(&["-s", s, "bla"]);
+            assert!(opts.is_err());
+        }
+    }
+
+    #[test]
+    fn options_split_on() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.split_on.is_none());
+
+        let opts = parse_options(&["--split-on=;", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.split_on.as_deref() == Some(";"));
+
+        let opts = parse_options(&["--split-on=", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--split-on=;", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_dry_run() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--dry-run", "bla"]).unwrap();
         let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
-        assert!(opts.pattern == "bla");
-        assert!(opts.output == Some(PathBuf::from("output1")));
-        assert!(opts.inputs == [PathBuf::from("input1"), PathBuf::from("input2")]);
-        assert!(opts.mmap == UnweaveMmap::Allow);
+        assert!(opts.dry_run);
+
+        let opts = parse_options(&["--dry-run", "bla"]);
+        assert!(opts.is_err());
     }
 
     #[test]
-    fn options_input_from_stdin_adds_dev_stdin() {
-        let opts = parse_options(&["--mode=files", "--output=output1", "bla"]).unwrap();
+    fn options_uniq() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.uniq);
+
+        let opts = parse_options(&["--uniq", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.uniq);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--uniq", "bla"]).unwrap();
         let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
-        assert!(opts.pattern == "bla");
-        assert!(opts.output == Some(PathBuf::from("output1")));
-        assert!(opts.inputs == [PathBuf::from("/dev/stdin")]);
+        assert!(opts.uniq);
     }
 
     #[test]
-    fn options_no_mmap() {
-        let opts = parse_options(&["--no-mmap", "bla"]).unwrap();
+    fn options_keep_going() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--keep-going", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.keep_going);
+
+        let opts = parse_options(&["--keep-going", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_manifest() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--manifest=list", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.manifest == Some(PathBuf::from("list")));
+
+        let opts = parse_options(&["--manifest=list", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_no_merge() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "--no-merge", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.no_merge);
+
+        let opts = parse_options(&["--no-merge", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_preserve_eol() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(!opts.preserve_eol);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--preserve-eol", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.preserve_eol);
+
+        let opts = parse_options(&["--preserve-eol", "bla"]);
+        assert!(opts.is_err());
+
+        // Unterminated last lines of inputs would be joined with the lines of
+        // the next inputs written to the same output file
+        let opts = parse_options(&["--mode=files", "-o", "out", "--preserve-eol", "bla", "in1", "in2"]);
+        assert!(matches!(opts.err().unwrap().downcast_ref(),
+                         Some(UnweaveError::PreserveEolWithMultipleInputs)));
+    }
+
+    #[test]
+    fn options_count_only() {
+        let opts = parse_options(&["--mode=files", "-o", "out", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(!opts.count_only);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--count-only", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.count_only);
+
+        let opts = parse_options(&["--count-only", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_progress() {
+        let opts = parse_options(&["bla"]).unwrap();
         let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
-        assert!(opts.mmap == UnweaveMmap::Disallow);
+        assert!(opts.progress == UnweaveProgress::Never);
+
+        let opts = parse_options(&["--progress", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.progress == UnweaveProgress::Auto);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--progress=always", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.progress == UnweaveProgress::Always);
+
+        let opts = parse_options(&["--progress=sometimes", "bla"]);
+        assert!(opts.is_err());
     }
 
     #[test]
-    fn options_short() {
-        let opts = parse_options(&["-m", "columns", "-c", "10", "-o", "output1",
-                                   "-t", "7", "-n", "bla"]).unwrap();
+    fn options_highlight() {
+        let opts = parse_options(&["bla"]).unwrap();
         let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
-        assert!(opts.pattern == "bla");
-        assert!(opts.output == Some(PathBuf::from("output1")));
-        assert!(opts.width == UnweaveWidth::Column(10));
-        assert!(opts.inputs == [PathBuf::from("/dev/stdin")]);
-        assert!(opts.mmap == UnweaveMmap::Disallow);
-        assert!(opts.tab == UnweaveTab::Expand(7));
+        assert!(opts.highlight == UnweaveHighlight::Never);
+
+        let opts = parse_options(&["--highlight", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.highlight == UnweaveHighlight::Auto);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--highlight=always", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.highlight == UnweaveHighlight::Always);
+
+        let opts = parse_options(&["--highlight=never", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_highlight_no_color() {
+        let env = Environment { no_color: true, ..Default::default() };
+
+        let opts = super::parse_options(&["--highlight", "bla"], None, &env).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.highlight == UnweaveHighlight::Never);
+
+        let opts = super::parse_options(&["--highlight=auto", "bla"], None, &env).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.highlight == UnweaveHighlight::Never);
+
+        // Explicitly requested highlighting is still enabled
+        let opts = super::parse_options(&["--highlight=always", "bla"], None, &env).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.highlight == UnweaveHighlight::Always);
+    }
+
+    #[test]
+    fn options_line_width_auto() {
+        let opts = parse_options(&["--line-width=auto", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::LineAuto);
+
+        let opts = parse_options(&["--line-width=automatic", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_max_line_bytes() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.max_line_bytes.is_none());
+        assert!(opts.max_line_action == UnweaveMaxLineAction::Error);
+
+        let opts = parse_options(&["--max-line-bytes=100", "--max-line-action=truncate", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.max_line_bytes == Some(100));
+        assert!(opts.max_line_action == UnweaveMaxLineAction::Truncate);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--max-line-bytes=10", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.max_line_bytes == Some(10));
+
+        let opts = parse_options(&["--max-line-bytes=0", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--max-line-action=skip", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_binary() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.binary == UnweaveBinary::Warn);
+
+        let opts = parse_options(&["--binary=refuse", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.binary == UnweaveBinary::Refuse);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--binary=process", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.binary == UnweaveBinary::Process);
+
+        let opts = parse_options(&["--binary=skip", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_footer() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.footer);
+
+        let opts = parse_options(&["--footer", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.footer);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--footer", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_column_labels() {
+        let opts = parse_options(&["--header", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.column_labels == UnweaveColumnLabels::Tag);
+
+        let opts = parse_options(&["--header", "--column-labels=index", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.header);
+        assert!(opts.column_labels == UnweaveColumnLabels::Index);
+
+        let opts = parse_options(&["--column-labels=none", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.column_labels == UnweaveColumnLabels::None);
+
+        let opts = parse_options(&["--column-labels=number", "bla"]);
+        assert!(opts.is_err());
+        let opts = parse_options(&["--mode=files", "-o", "out", "--column-labels=index", "bla"]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn options_line_buffered() {
+        let opts = parse_options(&["bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(!opts.line_buffered);
+
+        let opts = parse_options(&["--line-buffered", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_buffered);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--line-buffered", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.line_buffered);
+    }
+
+    #[test]
+    fn options_line_ending_detect() {
+        let opts = parse_options(&["--line-ending-detect", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.line_ending_detect);
+
+        let opts = parse_options(&["--mode=files", "-o", "out", "--line-ending-detect", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Files(o) = opts { o } else { panic!("") };
+        assert!(opts.line_ending_detect);
+
+        let opts = parse_options(&["--line-ending-detect", "-z", "bla"]);
+        assert!(opts.is_err());
     }
 
     #[test]
@@ -447,4 +2934,63 @@ mod tests {
         let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
         assert!(opts.tab == UnweaveTab::NoExpand);
     }
+
+    #[test]
+    fn options_config() {
+        let tmpdir = tempdir::TempDir::new("unweave-test").unwrap();
+        let config = tmpdir.path().join("config");
+        std::fs::write(&config, "# Defaults\ncolumn-width = 10\n\ncolumn-separator = \" | \"\n").unwrap();
+        let config_opt = format!("--config={}", config.display());
+
+        let opts = parse_options(&[&config_opt, "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Column(10));
+        assert!(opts.column_separator.as_deref() == Some(" | "));
+
+        // Command-line options take precedence
+        let opts = parse_options(&[&config_opt, "-c", "5", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Column(5));
+
+        let opts = parse_options(&[&config_opt, "-l", "20", "bla"]).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.width == UnweaveWidth::Line(20));
+
+        // Options for columns mode don't apply to other modes
+        let opts = parse_options(&[&config_opt, "--mode=files", "-o", "out", "bla"]).unwrap();
+        assert!(matches!(opts, UnweaveOptions::Files(_)));
+
+        std::fs::write(&config, "mode = files\n").unwrap();
+        let opts = parse_options(&[&config_opt, "-o", "out", "bla"]).unwrap();
+        assert!(matches!(opts, UnweaveOptions::Files(_)));
+
+        // Options invalid for the selected mode are left out
+        std::fs::write(&config, "highlight = always\n").unwrap();
+        let opts = parse_options(&[&config_opt, "--mode=test", "bla"]).unwrap();
+        assert!(matches!(opts, UnweaveOptions::Test(_)));
+
+        for contents in &["unmatched = last\n", "column-width\n", " = 10\n"] {
+            std::fs::write(&config, contents).unwrap();
+            let opts = parse_options(&[&config_opt, "bla"]);
+            assert!(opts.is_err());
+        }
+    }
+
+    #[test]
+    fn options_tab_width_env() {
+        let env = Environment { tabsize: Some("4".to_string()), ..Default::default() };
+        let opts = super::parse_options(&["bla"], None, &env).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tab == UnweaveTab::Expand(4));
+
+        // An explicit --tab-width takes precedence
+        let opts = super::parse_options(&["--tab-width=3", "bla"], None, &env).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tab == UnweaveTab::Expand(3));
+
+        let env = Environment { tabsize: Some("0".to_string()), ..Default::default() };
+        let opts = super::parse_options(&["bla"], None, &env).unwrap();
+        let opts = if let UnweaveOptions::Columns(o) = opts { o } else { panic!("") };
+        assert!(opts.tab == UnweaveTab::Expand(8));
+    }
 }
@@ -0,0 +1,150 @@
+// Copyright 2022 Alexandros Frantzis
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::UnweaveOptionsColumns;
+use crate::util::{TagFinder, FileLines, is_broken_pipe};
+
+use anyhow::{Result, Context};
+
+use std::io::{Write, BufWriter, self};
+use std::fs::File;
+
+/// Tests the pattern against the lines of the inputs, writing, for each line,
+/// whether it matched the pattern, its stream tag and the byte range of the
+/// tag within the line, along with the line itself. The output is written to
+/// the output file specified in the options, or to the standard output.
+/// Returns the number of lines that matched the pattern.
+pub fn test_pattern(opts: &UnweaveOptionsColumns) -> Result<u64> {
+    let writer: Box<dyn Write> = match &opts.output {
+        Some(o) => Box::new(
+            BufWriter::with_capacity(
+                opts.buffer_size,
+                File::create(o).with_context(
+                    || format!("Failed to create output file {}", o.display())
+                )?
+            )
+        ),
+        None => Box::new(BufWriter::with_capacity(opts.buffer_size, io::stdout())),
+    };
+
+    test_pattern_with_writer(opts, writer)
+}
+
+/// Tests the pattern against the lines of the inputs, like test_pattern,
+/// writing the output to the specified writer. The output file specified in
+/// the options is ignored.
+pub fn test_pattern_with_writer<'w>(opts: &UnweaveOptionsColumns,
+                                    mut writer: Box<dyn Write + 'w>) -> Result<u64> {
+    let mut matched = 0;
+
+    match test_pattern_into(opts, &mut writer, &mut matched) {
+        Err(e) if !is_broken_pipe(&e) => Err(e),
+        _ => Ok(matched),
+    }
+}
+
+/// Writes one line for each input line, with the line number (prefixed by
+/// the input path when there are multiple inputs), whether the line matched,
+/// the stream tag, the byte range of the tag and the line itself, separated
+/// by tabs. The tag and range are empty for unmatched lines, and the range is
+/// also empty for tags that aren't a single range of the line.
+fn test_pattern_into(opts: &UnweaveOptionsColumns, writer: &mut dyn Write,
+                     matched: &mut u64) -> Result<()> {
+    let mut tag_finder = TagFinder::new(&opts.tag)?;
+    let line_ending = opts.output_line_ending().as_bytes();
+
+    for input in &opts.inputs {
+        let mut file_lines = FileLines::new(input, opts.mmap, opts.buffer_size, opts.line_terminator())?;
+        file_lines.check_binary(input, opts.binary)?;
+        file_lines.set_max_line(opts.max_line());
+        let prefix = if opts.inputs.len() > 1 { format!("{}:", input.display()) } else { String::new() };
+        let mut line_number = 0;
+
+        while let Some(line) = file_lines.next() {
+            line_number += 1;
+            write!(writer, "{}{}\t", prefix, line_number)?;
+            match tag_finder.find_tag_in(line) {
+                Some(tag) => {
+                    *matched += 1;
+                    writer.write_all(b"matched\t")?;
+                    writer.write_all(&tag)?;
+                    writer.write_all(b"\t")?;
+                    if let Some(range) = tag_finder.found_tag_range() {
+                        write!(writer, "{}-{}", range.start, range.end)?;
+                    }
+                },
+                None => writer.write_all(b"unmatched\t\t")?,
+            }
+            writer.write_all(b"\t")?;
+            writer.write_all(line)?;
+            writer.write_all(line_ending)?;
+        }
+
+        file_lines.check_line_length(input)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnweaveOptionsTag;
+    use tempdir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_pattern_matched_and_unmatched() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let input = tmpdir.path().join("input");
+        fs::write(&input, b"A:1\nX\nz B:2").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("([AB]):"),
+            inputs: vec![input.clone()],
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        assert!(test_pattern_with_writer(&opts, Box::new(&mut output)).unwrap() == 2);
+
+        assert!(output ==
+                concat!("1\tmatched\tA\t0-1\tA:1\n",
+                        "2\tunmatched\t\t\tX\n",
+                        "3\tmatched\tB\t2-3\tz B:2\n").as_bytes());
+
+        // Composite tags have no single range, and multiple inputs are
+        // identified by their path
+        opts.tag.patterns = vec!["([AB]):(\\d)".to_string()];
+        opts.tag.join = Some("-".to_string());
+        opts.inputs = vec![input.clone(), input.clone()];
+
+        let mut output = Vec::new();
+        assert!(test_pattern_with_writer(&opts, Box::new(&mut output)).unwrap() == 4);
+
+        let path = input.display();
+        assert!(String::from_utf8(output).unwrap() ==
+                format!(concat!("{0}:1\tmatched\tA-1\t\tA:1\n",
+                                "{0}:2\tunmatched\t\t\tX\n",
+                                "{0}:3\tmatched\tB-2\t\tz B:2\n",
+                                "{0}:1\tmatched\tA-1\t\tA:1\n",
+                                "{0}:2\tunmatched\t\t\tX\n",
+                                "{0}:3\tmatched\tB-2\t\tz B:2\n"), path));
+    }
+}
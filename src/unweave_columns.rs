@@ -15,58 +15,240 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{UnweaveOptionsColumns, UnweaveTwoPass, UnweaveWidth, UnweaveTab};
-use crate::util::{TagFinder, FileLines, trim_newline, SliceFullLines, FileContents,
-                  ascii_grapheme_count, str_grapheme_count, grapheme_count_tab_expanded,
-                  for_each_grapheme, Grapheme};
+use crate::{UnweaveOptionsColumns, UnweaveError, UnweaveTwoPass, UnweaveWidth, UnweaveTab, UnweaveUnmatched,
+            UnweaveAlign, UnweaveLineNumbers, UnweaveCharWidth, UnweaveInterleave, UnweaveBlank,
+            UnweaveWrap, UnweaveColumnLabels, UnweaveTabOrigin, UnweaveRrFill};
+use crate::util::{TagFinder, FileLines, SliceTrimmedLines, FileContents, write_stats,
+                  path_size, path_contents_can_be_reread, path_is_stdin, SpilledInput,
+                  for_each_grapheme, Grapheme, for_each_invalid_utf8, write_invalid_utf8_summary,
+                  Progress, terminal_width, write_highlighted, is_broken_pipe, show_control_chars,
+                  sub_records, record_lines, limit_line_length, sort_by_line_key, LineTerminator};
+use crate::width::{ascii_grapheme_count, str_grapheme_count, grapheme_count_tab_expanded,
+                   grapheme_count_tab_expanded_at};
 
 use ahash::AHashMap;
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, bail};
 
 use std::io::{Write, BufWriter, self};
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::num::NonZeroU32;
+use std::ops::Range;
+use std::borrow::Cow;
+
+/// An optional writer to print the output to, instead of the output specified
+/// in the options.
+type OutputWriter<'w> = Option<Box<dyn Write + 'w>>;
 
 /// Helper that handles printing of lines at specific columns.
-struct ColumnPrinter {
-    bufwriter: Box<dyn Write>,
-    sep: String,
+struct ColumnPrinter<'w> {
+    bufwriter: Box<dyn Write + 'w>,
+    // The separators between columns, indexed by gap, with the last one
+    // repeated for any remaining gaps
+    seps: Vec<String>,
     tab: UnweaveTab,
+    tab_origin: UnweaveTabOrigin,
     column_widths: Vec<u32>,
+    // The number of terminal cells before each column, excluding the line
+    // number gutter
+    column_offsets: Vec<u32>,
     column_prefixes: Vec<String>,
     column_suffixes: Vec<String>,
+    truncate_marker: Option<Vec<u8>>,
+    align: UnweaveAlign,
+    wrap: UnweaveWrap,
+    wrap_marker: Option<Vec<u8>>,
+    line_numbers: UnweaveLineNumbers,
+    line_number_width: usize,
+    line_number: u64,
+    line_number_printed: bool,
+    show_offset: bool,
+    offset_width: usize,
+    line_offset: Option<u64>,
+    char_width: UnweaveCharWidth,
+    line_ending: &'static str,
+    fill_char: String,
+    outer_separator: bool,
+    sparse_separators: bool,
+    // A block of 8 fill characters
+    fill: Vec<u8>,
+    has_output: bool,
+    line_buffered: bool,
+    // The output path and number of rows of each chunk, when splitting the
+    // output into numbered chunk files
+    split: Option<(PathBuf, u64)>,
+    chunk_number: u32,
+    chunk_rows: u64,
+    buffer_size: usize,
+    header: Option<Vec<Vec<u8>>>,
+    highlight: bool,
+    // The byte range of the tag to highlight in the line being printed
+    highlight_range: Option<Range<usize>>,
 }
 
-impl ColumnPrinter {
-    /// Create a new ColumnPrinter.
-    fn new(opts: &UnweaveOptionsColumns) -> Result<Self> {
-        Ok(
-            ColumnPrinter {
-                bufwriter: match &opts.output {
-                    Some(o) => Box::new(
-                        BufWriter::new(
-                            File::create(o).with_context(
-                                || format!("Failed to create output file {}", o.display())
-                            )?
-                        )
-                    ),
-                    None => Box::new(BufWriter::new(io::stdout())),
-                },
-                sep: match &opts.column_separator {
-                    Some(s) => s.clone(),
-                    None => "".to_string(),
-                },
-                tab: opts.tab,
-                column_widths: Vec::new(),
-                column_prefixes: Vec::new(),
-                column_suffixes: Vec::new(),
+impl<'w> ColumnPrinter<'w> {
+    /// Create a new ColumnPrinter, writing to the specified writer, or to the
+    /// output specified in the options if no writer is provided.
+    fn new(opts: &UnweaveOptionsColumns, writer: OutputWriter<'w>) -> Result<Self> {
+        let writer: Box<dyn Write + 'w> = match (writer, &opts.output) {
+            (Some(w), _) => w,
+            (None, Some(o)) if opts.split_lines.is_some() => {
+                let mut column_printer = Self::with_writer(opts, Box::new(io::sink()));
+                column_printer.split = Some((o.clone(), opts.split_lines.unwrap()));
+                column_printer.start_chunk()?;
+                return Ok(column_printer);
+            },
+            (None, Some(o)) => Box::new(
+                BufWriter::with_capacity(
+                    opts.buffer_size,
+                    File::create(o).with_context(
+                        || format!("Failed to create output file {}", o.display())
+                    )?
+                )
+            ),
+            // Only the statistics are printed if there is no explicit output
+            (None, None) if opts.stats => Box::new(io::sink()),
+            (None, None) => Box::new(BufWriter::with_capacity(opts.buffer_size, io::stdout())),
+        };
+
+        Ok(Self::with_writer(opts, writer))
+    }
+
+    /// Create a new ColumnPrinter, writing to the specified writer.
+    fn with_writer(opts: &UnweaveOptionsColumns, writer: Box<dyn Write + 'w>) -> Self {
+        ColumnPrinter {
+            bufwriter: writer,
+            seps: match &opts.column_separator {
+                Some(s) => split_column_separators(s),
+                None => vec!["".to_string()],
+            },
+            tab: opts.tab,
+            tab_origin: opts.tab_origin,
+            column_widths: Vec::new(),
+            column_offsets: Vec::new(),
+            column_prefixes: Vec::new(),
+            column_suffixes: Vec::new(),
+            truncate_marker: opts.truncate_marker.as_ref().map(|m| m.as_bytes().to_vec()),
+            align: opts.align,
+            wrap: opts.wrap,
+            wrap_marker: opts.wrap_marker.as_ref()
+                .filter(|m| !m.is_empty())
+                .map(|m| m.as_bytes().to_vec()),
+            line_numbers: opts.line_numbers,
+            line_number_width: 6,
+            line_number: 0,
+            line_number_printed: false,
+            show_offset: opts.show_offset,
+            offset_width: 6,
+            line_offset: None,
+            char_width: opts.char_width,
+            line_ending: opts.output_line_ending(),
+            fill_char: opts.fill_char.clone(),
+            outer_separator: opts.outer_separator,
+            sparse_separators: opts.sparse_separators,
+            fill: opts.fill_char.repeat(8).into_bytes(),
+            has_output: false,
+            line_buffered: opts.line_buffered,
+            split: None,
+            chunk_number: 0,
+            chunk_rows: 0,
+            buffer_size: opts.buffer_size,
+            header: None,
+            highlight: opts.highlights(),
+            highlight_range: None,
+        }
+    }
+
+    /// Switches the output to the next numbered chunk file, e.g.,
+    /// "output.002", and prints the header row, if any, at its start.
+    fn start_chunk(&mut self) -> Result<()> {
+        let (output, _) = self.split.as_ref().unwrap();
+        self.chunk_number += 1;
+        let mut path = output.clone().into_os_string();
+        path.push(format!(".{:03}", self.chunk_number));
+        let path = PathBuf::from(path);
+
+        self.flush()?;
+        self.bufwriter = Box::new(
+            BufWriter::with_capacity(
+                self.buffer_size,
+                File::create(&path).with_context(
+                    || format!("Failed to create output file {}", path.display())
+                )?
+            )
+        );
+        self.chunk_rows = 0;
+
+        if let Some(header) = self.header.clone() {
+            self.print_row(&header)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts a printed row, first switching to the next output chunk if the
+    /// current one is full.
+    fn start_row(&mut self) -> Result<()> {
+        if let Some((_, rows)) = self.split {
+            if self.chunk_rows == rows {
+                self.start_chunk()?;
             }
-        )
+            self.chunk_rows += 1;
+        }
+        Ok(())
+    }
+
+    /// Flushes any buffered output.
+    fn flush(&mut self) -> Result<()> {
+        self.bufwriter.flush().context("Failed to write to output")
+    }
+
+    /// Ends a printed row, flushing the output if line buffered.
+    fn end_row(&mut self) -> Result<()> {
+        if self.line_buffered {
+            self.bufwriter.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the separator to print after the specified column.
+    fn sep(&self, col: usize) -> &str {
+        &self.seps[col.min(self.seps.len() - 1)]
+    }
+
+    /// Returns the separator to print after column i in rows with contents
+    /// in column col. With sparse separators, separators that don't border
+    /// the column with the contents are replaced with fill characters.
+    fn sep_for_row(&self, i: usize, col: usize) -> String {
+        let sep = self.sep(i);
+        if !self.sparse_separators || i == col || i + 1 == col {
+            sep.to_string()
+        } else {
+            self.blank(sep)
+        }
+    }
+
+    /// Returns fill characters spanning the width of the specified text.
+    fn blank(&self, s: &str) -> String {
+        let width = grapheme_count_tab_expanded(s.as_bytes(), self.tab, self.char_width, None);
+        self.fill_char.repeat(width as usize)
+    }
+
+    /// Returns the number of terminal cells taken up by the separators, and
+    /// any outer separators, in a row with the specified number of columns.
+    fn separators_width(&self, ncolumns: usize) -> u32 {
+        let width = |s: &str| grapheme_count_tab_expanded(s.as_bytes(), self.tab, self.char_width, None);
+        let mut total = (0..ncolumns.saturating_sub(1)).map(|i| width(self.sep(i))).sum();
+        if self.outer_separator && ncolumns > 0 {
+            total += width(self.sep(0).trim_start()) + width(self.sep(ncolumns - 1).trim_end());
+        }
+        total
     }
 
     /// Sets the number of columns and their widths to print with.
     fn set_column_widths(&mut self, column_widths: &[u32]) {
         self.column_widths = column_widths.to_vec();
+        self.column_offsets.clear();
         self.column_prefixes.clear();
         self.column_suffixes.clear();
 
@@ -74,69 +256,434 @@ impl ColumnPrinter {
             let mut prefix = String::new();
             let mut suffix = String::new();
 
-            for w in &column_widths[..col] {
-                prefix.push_str(&(" ".repeat(*w as usize) + &self.sep));
+            // Avoid leading and trailing whitespace around the outer separators
+            if self.outer_separator {
+                let sep = if self.sparse_separators && col != 0 {
+                    self.blank(self.sep(0).trim_start())
+                } else {
+                    self.sep(0).trim_start().to_string()
+                };
+                prefix.push_str(&sep);
+            }
+
+            for (i, w) in column_widths[..col].iter().enumerate() {
+                prefix.push_str(&(self.fill_char.repeat(*w as usize) + &self.sep_for_row(i, col)));
             }
 
-            for w in &column_widths[col+1..] {
-                suffix.push_str(&(self.sep.clone() + &" ".repeat(*w as usize)));
+            for (i, w) in column_widths.iter().enumerate().skip(col + 1) {
+                suffix.push_str(&(self.sep_for_row(i - 1, col) + &self.fill_char.repeat(*w as usize)));
             }
-            suffix.truncate(suffix.trim_end().len());
-            suffix.push('\n');
+            let last_col = column_widths.len() - 1;
+            if self.outer_separator && !(self.sparse_separators && col != last_col) {
+                suffix.push_str(self.sep(last_col).trim_end());
+            } else {
+                suffix.truncate(suffix.trim_end().len());
+            }
+            suffix.push_str(self.line_ending);
 
+            self.column_offsets.push(
+                grapheme_count_tab_expanded(prefix.as_bytes(), self.tab, self.char_width, None)
+            );
             self.column_prefixes.push(prefix);
             self.column_suffixes.push(suffix);
         }
     }
 
+    /// Returns the number of terminal cells from the start of the printed line
+    /// to the start of the specified column.
+    fn line_start_offset(&self, col: u32) -> u32 {
+        let gutter = match self.line_numbers {
+            UnweaveLineNumbers::Disabled => 0,
+            _ => self.line_number_width as u32 + 1,
+        };
+        let offset_gutter = if self.show_offset { self.offset_width as u32 + 1 } else { 0 };
+        gutter + offset_gutter + self.column_offsets[col as usize]
+    }
+
+    /// Sets the width of the line number gutter to fit line numbers up to the
+    /// specified maximum.
+    fn set_max_line_number(&mut self, max_line_number: u64) {
+        self.line_number_width = max_line_number.to_string().len();
+    }
+
+    /// Sets the width of the byte offset gutter to fit offsets up to the
+    /// specified maximum.
+    fn set_max_offset(&mut self, max_offset: u64) {
+        self.offset_width = max_offset.to_string().len();
+    }
+
+    /// Sets the byte offset of the line printed next, shown in the byte offset
+    /// gutter of its first row.
+    fn set_line_offset(&mut self, offset: u64) {
+        self.line_offset = Some(offset);
+    }
+
+    /// Write the byte offset gutter for the current row, if enabled. Rows
+    /// continuing a wrapped line get a blank gutter.
+    fn write_offset(&mut self) -> Result<()> {
+        if !self.show_offset {
+            return Ok(());
+        }
+
+        match self.line_offset.take() {
+            Some(offset) => write!(self.bufwriter, "{:>1$} ", offset, self.offset_width)?,
+            None => write!(self.bufwriter, "{:>1$} ", "", self.offset_width)?,
+        }
+
+        Ok(())
+    }
+
+    /// Write the line number gutter for the current row, if enabled. Rows
+    /// continuing a wrapped line get a blank gutter or repeat the line
+    /// number, depending on the options.
+    fn write_line_number(&mut self) -> Result<()> {
+        let show = match self.line_numbers {
+            UnweaveLineNumbers::Disabled => return Ok(()),
+            UnweaveLineNumbers::Blank => !self.line_number_printed,
+            UnweaveLineNumbers::Repeat => true,
+        };
+
+        if show {
+            write!(self.bufwriter, "{:>1$} ", self.line_number, self.line_number_width)?;
+        } else {
+            write!(self.bufwriter, "{:>1$} ", "", self.line_number_width)?;
+        }
+        self.line_number_printed = true;
+
+        Ok(())
+    }
+
+    /// Write the specified number of fill characters, to pad column contents
+    /// to the required width.
+    fn write_fill(&mut self, mut remaining: u32) -> Result<()> {
+        let fill_len = self.fill.len() / 8;
+
+        while remaining > 0 {
+            if remaining >= 8 {
+                self.bufwriter.write_all(&self.fill)?;
+                remaining -= 8;
+            } else if remaining >= 4 {
+                self.bufwriter.write_all(&self.fill[..4 * fill_len])?;
+                remaining -= 4;
+            } else if remaining >= 2 {
+                self.bufwriter.write_all(&self.fill[..2 * fill_len])?;
+                remaining -= 2;
+            } else if remaining >= 1 {
+                self.bufwriter.write_all(&self.fill[..fill_len])?;
+                remaining -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether stream tags are highlighted in printed lines.
+    fn highlights(&self) -> bool {
+        self.highlight
+    }
+
+    /// Sets the byte range of the tag to highlight in the next printed line.
+    fn set_highlight_range(&mut self, range: Option<Range<usize>>) {
+        self.highlight_range = range;
+    }
+
+    /// Writes a chunk of the line being printed, highlighting the part of the
+    /// tag in it, if any. The chunk starts with the specified range of the
+    /// line, and may be followed by a marker.
+    fn write_chunk(&mut self, chunk: &[u8], line_range: &Range<usize>) -> Result<()> {
+        let range = self.highlight_range.as_ref().map(|h| {
+            let start = h.start.clamp(line_range.start, line_range.end);
+            let end = h.end.clamp(line_range.start, line_range.end);
+            (start - line_range.start)..(end - line_range.start)
+        });
+        write_highlighted(&mut self.bufwriter, chunk, range)?;
+        Ok(())
+    }
+
     /// Print data in a column, assuming that the data can fit without
-    /// wrapping.
+    /// wrapping. The data starts with the specified range of the line being
+    /// printed.
     fn print_in_column_unwrapped(&mut self, chunk: &[u8], col: u32,
-                                 grapheme_count: Option<NonZeroU32>) -> Result<()> {
+                                 grapheme_count: Option<NonZeroU32>,
+                                 line_range: Range<usize>) -> Result<()> {
         let col = col as usize;
         let column_width = self.column_widths[col];
 
-        self.bufwriter.write(self.column_prefixes[col].as_bytes())?;
-        self.bufwriter.write(chunk)?;
-        // Avoid trailing whitespace
-        if !self.column_suffixes[col].trim_end().is_empty() {
-            let grapheme_count = match grapheme_count {
-                Some(g) => g.get(),
-                _ => grapheme_count_tab_expanded(chunk, self.tab, None)
-            };
+        self.start_row()?;
+        self.write_line_number()?;
+        self.write_offset()?;
+        self.bufwriter.write_all(self.column_prefixes[col].as_bytes())?;
 
-            // Fill in to reach required width
-            let mut remaining = if grapheme_count < column_width {
-                column_width - grapheme_count
-            } else {
-                0
-            };
+        let tab = self.tab;
+        let char_width = self.char_width;
+        let grapheme_count = || match grapheme_count {
+            Some(g) => g.get(),
+            _ => grapheme_count_tab_expanded(chunk, tab, char_width, None)
+        };
 
-            while remaining > 0 {
-                if remaining >= 8 {
-                    self.bufwriter.write(b"        ")?;
-                    remaining -= 8;
-                } else if remaining >= 4 {
-                    self.bufwriter.write(b"    ")?;
-                    remaining -= 4;
-                } else if remaining >= 2 {
-                    self.bufwriter.write(b"  ")?;
-                    remaining -= 2;
-                } else if remaining >= 1 {
-                    self.bufwriter.write(b" ")?;
-                    remaining -= 1;
+        match self.align {
+            UnweaveAlign::Left => {
+                self.write_chunk(chunk, &line_range)?;
+                // Avoid trailing whitespace
+                if !self.column_suffixes[col].trim_end().is_empty() || self.fill_char != " " {
+                    let remaining = column_width.saturating_sub(grapheme_count());
+                    self.write_fill(remaining)?;
+                }
+            },
+            UnweaveAlign::Right => {
+                // Avoid trailing whitespace
+                if !chunk.is_empty() || !self.column_suffixes[col].trim_end().is_empty() ||
+                   self.fill_char != " " {
+                    let remaining = column_width.saturating_sub(grapheme_count());
+                    self.write_fill(remaining)?;
                 }
+                self.write_chunk(chunk, &line_range)?;
+            },
+        }
+
+        self.bufwriter.write_all(self.column_suffixes[col].as_bytes())?;
+
+        self.end_row()
+    }
+
+    /// Whether any rows have been printed.
+    fn has_output(&self) -> bool {
+        self.has_output
+    }
+
+    /// Print an empty row spanning all columns.
+    fn print_blank_row(&mut self, line_number: u64) -> Result<()> {
+        self.has_output = true;
+        if self.column_widths.is_empty() {
+            self.line_number = line_number;
+            self.line_number_printed = false;
+            self.start_row()?;
+            self.write_line_number()?;
+            self.write_offset()?;
+            self.bufwriter.write_all(self.line_ending.as_bytes())?;
+            return self.end_row();
+        }
+
+        self.print_in_column(b"", 0, None, line_number)
+    }
+
+    /// Print the header row, which is repeated at the start of each output
+    /// chunk when splitting the output.
+    fn print_header(&mut self, cells: &[Vec<u8>]) -> Result<()> {
+        self.header = Some(cells.to_vec());
+        self.print_row(cells)
+    }
+
+    /// Print the footer row, after all lines have been printed.
+    fn print_footer(&mut self, cells: &[Vec<u8>]) -> Result<()> {
+        self.print_row(cells)
+    }
+
+    /// Print a row with the specified contents for each column, in column
+    /// order. Contents that don't fit in their column are truncated. Nothing
+    /// is printed if there are no columns.
+    fn print_row(&mut self, cells: &[Vec<u8>]) -> Result<()> {
+        if cells.is_empty() {
+            return Ok(());
+        }
+
+        let mut row = Vec::new();
+
+        if self.line_numbers != UnweaveLineNumbers::Disabled {
+            row.resize(self.line_number_width + 1, b' ');
+        }
+        if self.show_offset {
+            row.resize(row.len() + self.offset_width + 1, b' ');
+        }
+
+        for (col, cell) in cells.iter().enumerate() {
+            if col > 0 {
+                row.extend_from_slice(self.sep(col - 1).as_bytes());
+            } else if self.outer_separator {
+                row.extend_from_slice(self.sep(0).trim_start().as_bytes());
+            }
+
+            let mut untabbed_cell = Vec::new();
+            grapheme_count_tab_expanded(cell, self.tab, self.char_width, Some(&mut untabbed_cell));
+            let column_width = self.column_widths[col];
+            let (cell, grapheme_count) = truncate_to_width(&untabbed_cell, column_width, self.char_width);
+
+            let fill = self.fill_char.repeat((column_width - grapheme_count) as usize);
+            match self.align {
+                UnweaveAlign::Left => {
+                    row.extend_from_slice(cell);
+                    row.extend_from_slice(fill.as_bytes());
+                },
+                UnweaveAlign::Right => {
+                    row.extend_from_slice(fill.as_bytes());
+                    row.extend_from_slice(cell);
+                },
+            }
+        }
+
+        if self.outer_separator {
+            row.extend_from_slice(self.sep(cells.len() - 1).trim_end().as_bytes());
+        } else {
+            // Avoid trailing whitespace
+            while row.last() == Some(&b' ') {
+                row.pop();
             }
         }
+        row.extend_from_slice(self.line_ending.as_bytes());
+
+        self.bufwriter.write_all(&row)?;
+
+        self.end_row()
+    }
+
+    /// Print data in a column, truncating it if it doesn't fit. The end of
+    /// truncated data is replaced with the specified marker, or with as much
+    /// of the marker as fits if the column is narrower than the marker.
+    fn print_in_column_truncated(&mut self, line: &[u8], col: u32, marker: &[u8]) -> Result<()> {
+        let column_width = self.column_widths[col as usize];
+
+        let (prefix, prefix_count) = truncate_to_width(line, column_width, self.char_width);
+        if prefix.len() == line.len() {
+            return self.print_in_column_unwrapped(line, col, NonZeroU32::new(prefix_count),
+                                                  0..line.len());
+        }
+
+        let (marker, marker_count) = truncate_to_width(marker, column_width, self.char_width);
+        let (prefix, prefix_count) = truncate_to_width(line, column_width - marker_count,
+                                                       self.char_width);
+        let mut chunk = prefix.to_vec();
+        chunk.extend_from_slice(marker);
+
+        self.print_in_column_unwrapped(&chunk, col, NonZeroU32::new(prefix_count + marker_count),
+                                       0..prefix.len())
+    }
 
-        self.bufwriter.write(self.column_suffixes[col].as_bytes())?;
+    /// Print data in a column, wrapping it at the last space that fits in each
+    /// column chunk in word wrap mode, dropping the space. Otherwise, or if no
+    /// space fits, data is wrapped at the grapheme that fills the column,
+    /// followed by the wrap marker, if one has been set.
+    fn print_in_column_wrapped_at_breaks(&mut self, line: &[u8], col: u32) -> Result<()> {
+        let column_width = self.column_widths[col as usize];
+        let char_width = self.char_width;
+        let wrap_marker = self.wrap_marker.clone().unwrap_or_default();
+        let wrap_marker_count = grapheme_count_tab_expanded(&wrap_marker, UnweaveTab::NoExpand,
+                                                            char_width, None);
+
+        // The byte offset, grapheme count and whether it's a space, for each
+        // grapheme of the line.
+        let mut graphemes = Vec::new();
+        let mut offset = 0;
+        for_each_grapheme(line,
+            |g| {
+                let (len, count, is_space) = match g {
+                    Grapheme::Unicode(s) => (s.len(), str_grapheme_count(s, char_width), false),
+                    Grapheme::Ascii(b) => (1, ascii_grapheme_count(b), b == b' '),
+                };
+                graphemes.push((offset, count, is_space));
+                offset += len;
+                Ok(())
+            }
+        )?;
+        graphemes.push((line.len(), 0, false));
+
+        let ngraphemes = graphemes.len() - 1;
+        // Returns the end of the graphemes starting at start that fit in the
+        // width, always including at least one grapheme.
+        let fitting_end = |start: usize, width: u32| {
+            let mut end = start;
+            let mut count = 0;
+            while end < ngraphemes && (end == start || count + graphemes[end].1 <= width) {
+                count += graphemes[end].1;
+                end += 1;
+            }
+            end
+        };
+
+        let mut start = 0;
+
+        while start < ngraphemes {
+            let end = fitting_end(start, column_width);
+
+            // In word mode, break at the last space, which may also be the
+            // grapheme right after the ones that fit.
+            let space = if end < ngraphemes && self.wrap == UnweaveWrap::Word {
+                (start + 1..=end).rev().find(|g| graphemes[*g].2)
+            } else {
+                None
+            };
+
+            let (chunk_end, next_start, marker) = match space {
+                Some(s) => (s, s + 1, &b""[..]),
+                None if end < ngraphemes && !wrap_marker.is_empty() => {
+                    // Reserve space for the marker, printing as much of the
+                    // marker as fits if the column is too narrow.
+                    let end = fitting_end(start, column_width.saturating_sub(wrap_marker_count));
+                    (end, end, &wrap_marker[..])
+                },
+                None => (end, end, &b""[..]),
+            };
+
+            let chunk_count = graphemes[start..chunk_end].iter().map(|g| g.1).sum();
+            let chunk_range = graphemes[start].0..graphemes[chunk_end].0;
+            let chunk = &line[chunk_range.clone()];
+
+            if marker.is_empty() {
+                self.print_in_column_unwrapped(chunk, col, NonZeroU32::new(chunk_count),
+                                               chunk_range)?;
+            } else {
+                let (marker, marker_count) =
+                    truncate_to_width(marker, column_width.saturating_sub(chunk_count), char_width);
+                let mut chunk = chunk.to_vec();
+                chunk.extend_from_slice(marker);
+                self.print_in_column_unwrapped(&chunk, col,
+                                               NonZeroU32::new(chunk_count + marker_count),
+                                               chunk_range)?;
+            }
+
+            start = next_start;
+        }
 
         Ok(())
     }
 
-    /// Print data in a column, will wrap if needed.
+    /// Print data in a column, will wrap (or truncate, if a truncation marker
+    /// has been set) if needed. The line number is the 1-based number of the
+    /// line across all inputs.
     fn print_in_column(&mut self, line: &[u8], col: u32,
-                       mut grapheme_count: Option<NonZeroU32>) -> Result<()> {
+                       grapheme_count: Option<NonZeroU32>, line_number: u64) -> Result<()> {
+        self.has_output = true;
+        self.line_number = line_number;
+        self.line_number_printed = false;
+
+        self.print_in_column_continued(line, col, grapheme_count)
+    }
+
+    /// Print a record in a column, with each of its lines in a row of its own,
+    /// so that line breaks within the record don't break the alignment of the
+    /// columns. The rows after the first continue the record, like the rows of
+    /// a wrapped line.
+    fn print_record_in_column(&mut self, record: &[u8], col: u32, line_number: u64,
+                              terminator: LineTerminator) -> Result<()> {
+        let highlight_range = self.highlight_range.take();
+
+        for (start, line) in record_lines(record, terminator) {
+            self.highlight_range = highlight_range.as_ref()
+                .filter(|h| h.start < start + line.len() && h.end > start)
+                .map(|h| h.start.saturating_sub(start)..h.end - start);
+            if start == 0 {
+                self.print_in_column(line, col, None, line_number)?;
+            } else {
+                self.print_in_column_continued(line, col, None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print data in a column like print_in_column, continuing the line
+    /// printed last.
+    fn print_in_column_continued(&mut self, line: &[u8], col: u32,
+                                 mut grapheme_count: Option<NonZeroU32>) -> Result<()> {
         let column_width = self.column_widths[col as usize];
         let mut chunk_graphemes = 0;
         let mut chunk_start = 0;
@@ -144,9 +691,24 @@ impl ColumnPrinter {
         let mut untabbed_line = Vec::new();
 
         let line = if self.tab.is_expand() && line.contains(&b'\t') {
+            let origin = match self.tab_origin {
+                UnweaveTabOrigin::Column => 0,
+                UnweaveTabOrigin::Line => self.line_start_offset(col),
+            };
             grapheme_count = NonZeroU32::new(
-                grapheme_count_tab_expanded(line, self.tab, Some(&mut untabbed_line))
+                grapheme_count_tab_expanded_at(line, self.tab, self.char_width, origin,
+                                               Some(&mut untabbed_line))
             );
+            // Move the highlighted range to its position in the expanded line
+            if let Some(range) = self.highlight_range.clone() {
+                let expanded_len = |end: usize| {
+                    let mut expanded = Vec::new();
+                    grapheme_count_tab_expanded_at(&line[..end], self.tab, self.char_width,
+                                                   origin, Some(&mut expanded));
+                    expanded.len()
+                };
+                self.highlight_range = Some(expanded_len(range.start)..expanded_len(range.end));
+            }
             &untabbed_line
         } else {
             line
@@ -159,22 +721,40 @@ impl ColumnPrinter {
 
         // Fast path for when we are sure the line doesn't need to be split.
         if max_grapheme_count <= column_width {
-            return self.print_in_column_unwrapped(line, col, grapheme_count);
+            return self.print_in_column_unwrapped(line, col, grapheme_count, 0..line.len());
+        }
+
+        if let Some(marker) = self.truncate_marker.clone() {
+            return self.print_in_column_truncated(line, col, &marker);
+        }
+
+        if self.wrap == UnweaveWrap::Word || self.wrap_marker.is_some() {
+            return self.print_in_column_wrapped_at_breaks(line, col);
         }
 
+        let char_width = self.char_width;
+
         for_each_grapheme(line,
             |g| {
-                match g {
-                    Grapheme::Unicode(s) => {
-                        chunk_graphemes += str_grapheme_count(s);
-                        chunk_end += s.len();
-                    },
-                    Grapheme::Ascii(b) => {
-                        chunk_graphemes += ascii_grapheme_count(b);
-                        chunk_end += 1;
-                    }
+                let (len, count) = match g {
+                    Grapheme::Unicode(s) => (s.len(), str_grapheme_count(s, char_width)),
+                    Grapheme::Ascii(b) => (1, ascii_grapheme_count(b)),
                 };
 
+                // A wide grapheme may not fit in the remaining space of the
+                // column chunk, in which case it starts the next chunk.
+                if chunk_graphemes > 0 && chunk_graphemes + count > column_width {
+                    let chunk = &line[chunk_start..chunk_end];
+                    self.print_in_column_unwrapped(chunk, col,
+                                                   NonZeroU32::new(chunk_graphemes),
+                                                   chunk_start..chunk_end)?;
+                    chunk_start = chunk_end;
+                    chunk_graphemes = 0;
+                }
+
+                chunk_graphemes += count;
+                chunk_end += len;
+
                 // If this is not the end of the column chunk, continue.
                 if chunk_end < line.len() && chunk_graphemes < column_width {
                     return Ok(());
@@ -183,7 +763,8 @@ impl ColumnPrinter {
                 let chunk = &line[chunk_start..chunk_end];
 
                 self.print_in_column_unwrapped(chunk, col,
-                                               NonZeroU32::new(chunk_graphemes))?;
+                                               NonZeroU32::new(chunk_graphemes),
+                                               chunk_start..chunk_end)?;
 
                 chunk_start = chunk_end;
                 chunk_graphemes = 0;
@@ -195,778 +776,4607 @@ impl ColumnPrinter {
     }
 }
 
+/// Splits a comma separated list of column separators. A separator consisting
+/// only of commas is used as is, so that a comma can still separate columns.
+fn split_column_separators(separators: &str) -> Vec<String> {
+    if !separators.is_empty() && separators.bytes().all(|b| b == b',') {
+        return vec![separators.to_string()];
+    }
+
+    separators.split(',').map(|s| s.to_string()).collect()
+}
+
+/// Returns the longest prefix of a line that fits in the specified width, along
+/// with the width of that prefix.
+fn truncate_to_width(line: &[u8], width: u32, char_width: UnweaveCharWidth) -> (&[u8], u32) {
+    let mut end = 0;
+    let mut grapheme_count = 0;
+    let mut done = false;
+
+    for_each_grapheme(line,
+        |g| {
+            let (len, count) = match g {
+                Grapheme::Unicode(s) => (s.len(), str_grapheme_count(s, char_width)),
+                Grapheme::Ascii(b) => (1, ascii_grapheme_count(b)),
+            };
+
+            if done || grapheme_count + count > width {
+                done = true;
+            } else {
+                end += len;
+                grapheme_count += count;
+            }
+
+            Ok(())
+        }
+    ).unwrap();
+
+    (&line[..end], grapheme_count)
+}
+
+/// Returns the grapheme count of a line, if it is needed to calculate column
+/// widths. The grapheme count of a record is that of its longest line.
+fn line_grapheme_count(opts: &UnweaveOptionsColumns, line: &[u8]) -> Option<NonZeroU32> {
+    if opts.width != UnweaveWidth::Undefined {
+        return None;
+    }
+
+    let shown_line = show_control_chars(line, opts.show_control);
+    let count = |line| grapheme_count_tab_expanded(line, opts.tab, opts.char_width, None);
+
+    NonZeroU32::new(
+        match opts.record_separator {
+            Some(_) => record_lines(&shown_line, opts.line_terminator())
+                .map(|(_, line)| count(line)).max().unwrap_or(0),
+            None => count(&shown_line),
+        }
+    )
+}
+
+/// Reads a column map file, in which each line contains a tag and a column
+/// index separated by whitespace, returning the tags ordered by column index.
+fn read_column_map(path: &Path, ignore_case: bool) -> Result<Vec<Vec<u8>>> {
+    let contents = std::fs::read_to_string(path).with_context(
+        || format!("Failed to read column map file {}", path.display())
+    )?;
+    let mut entries = Vec::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let entry = line.rsplit_once(char::is_whitespace)
+            .and_then(|(tag, index)| Some((tag.trim_end(), index.parse::<u32>().ok()?)));
+        let (tag, index) = match entry {
+            Some(e) => e,
+            None => bail!(UnweaveError::InvalidColumnMapEntry(line.to_string())),
+        };
+
+        let tag = if ignore_case { tag.to_lowercase() } else { tag.to_string() };
+        entries.push((index, tag.into_bytes()));
+    }
+
+    entries.sort_by_key(|(index, _)| *index);
+
+    Ok(entries.into_iter().map(|(_, tag)| tag).collect())
+}
+
+/// The pseudo-column of empty lines printed as an empty row spanning all
+/// columns.
+const BLANK_ROW_COLUMN: u32 = u32::MAX;
+
+/// The maximum number of invalid UTF-8 sequences whose position is reported
+/// with --warn-invalid-utf8.
+const MAX_INVALID_UTF8_REPORTED: usize = 5;
+
 /// Tracks the number of columns and their widths.
+///
+/// Columns are identified internally by the order in which they were
+/// created, which may differ from the position the column is printed at (e.g.,
+/// when sorting columns by tag). Use position_for_column to get the printed
+/// position of a column.
 struct ColumnTracker<'a> {
     opts: &'a UnweaveOptionsColumns,
     tag_finder: TagFinder,
     column_for_tag: AHashMap<Vec<u8>, u32>,
+    column_tags: Vec<Vec<u8>>,
+    column_inputs: Vec<usize>,
     column_widths: Vec<u32>,
+    column_positions: Vec<u32>,
+    unmatched_column: Option<u32>,
+    overflow_column: Option<u32>,
+    tag_columns: u32,
+    mapped_columns: usize,
+    column_line_counts: Vec<u64>,
+    column_last_lines: Vec<Option<Vec<u8>>>,
+    last_column: Option<u32>,
+    late_column_tag: Option<Vec<u8>>,
+    current_input: usize,
+    width_override_for_tag: AHashMap<Vec<u8>, u32>,
+    column_width_overrides: Vec<Option<u32>>,
+    invalid_utf8_count: u64,
+    invalid_utf8_first: Vec<(u64, usize)>,
 }
 
 impl<'a> ColumnTracker<'a> {
     /// Creates a new ColumnTracker.
     fn new(opts: &'a UnweaveOptionsColumns) -> Result<Self> {
-        Ok(
-            Self {
-                opts,
-                tag_finder: TagFinder::new(&opts.pattern)?,
-                column_for_tag: AHashMap::new(),
-                column_widths: Vec::new(),
-            }
-        )
-    }
+        let mut column_tracker = Self {
+            opts,
+            tag_finder: TagFinder::new(&opts.tag)?,
+            column_for_tag: AHashMap::new(),
+            column_tags: Vec::new(),
+            column_inputs: Vec::new(),
+            column_widths: Vec::new(),
+            column_positions: Vec::new(),
+            unmatched_column: None,
+            overflow_column: None,
+            tag_columns: 0,
+            mapped_columns: 0,
+            column_line_counts: Vec::new(),
+            column_last_lines: Vec::new(),
+            last_column: None,
+            late_column_tag: None,
+            current_input: 0,
+            width_override_for_tag: opts.column_width_overrides.iter()
+                .map(|(t, w)| {
+                    let t = if opts.tag.ignore_case { t.to_lowercase() } else { t.clone() };
+                    (t.into_bytes(), *w)
+                })
+                .collect(),
+            column_width_overrides: Vec::new(),
+            invalid_utf8_count: 0,
+            invalid_utf8_first: Vec::new(),
+        };
 
-    /// Convenience function to process a line, without updating any ColumnPrinter
-    /// instance.
-    fn process_line(&mut self, line: &[u8]) -> Option<(u32,Option<NonZeroU32>)> {
-        self.process_line_with_column_printer(line, None)
-    }
+        if opts.unmatched != UnweaveUnmatched::Drop {
+            column_tracker.unmatched_column = Some(column_tracker.add_column(Vec::new(), 0));
+        }
 
-    /// Process a line, updating tracking information about the required columns
-    /// and their width.
-    ///
-    /// Optionally, if a ColumnPrinter instance is provided, the instance
-    /// updated with any new column information.
-    ///
-    /// Returns the column the line belongs in, or None, if the line should be
-    /// ignored.
-    fn process_line_with_column_printer(&mut self, line: &[u8],
-                                        lp: Option<&mut ColumnPrinter>) -> Option<(u32,Option<NonZeroU32>)> {
-        let tag = match self.tag_finder.find_in(&line) {
-            Some(tag_range) => &line[tag_range],
-            None => return None,
-        };
+        if let Some(column_map) = &opts.column_map {
+            column_tracker.add_mapped_columns(column_map)?;
+        }
 
-        let grapheme_count = match self.opts.width { 
-            UnweaveWidth::Undefined => NonZeroU32::new(
-                grapheme_count_tab_expanded(line, self.opts.tab, None)
-            ),
-            _ => None
-        };
+        Ok(column_tracker)
+    }
 
-        let column_width = match self.opts.width { 
-            UnweaveWidth::Undefined => grapheme_count.map_or(0, |g| g.get()),
+    /// Adds a new column with the specified tag and width, returning its index.
+    /// Tags with a column width override always use the overridden width.
+    fn add_column(&mut self, tag: Vec<u8>, column_width: u32) -> u32 {
+        let width_override = self.width_override_for_tag.get(&tag).copied();
+        self.column_tags.push(tag);
+        self.column_inputs.push(self.current_input);
+        self.column_widths.push(width_override.unwrap_or(column_width));
+        self.column_width_overrides.push(width_override);
+        self.update_column_positions();
+        (self.column_widths.len() - 1) as u32
+    }
+
+    /// Adds the columns for the tags in a column map file, in the order
+    /// specified by the map, before any other tag columns.
+    fn add_mapped_columns(&mut self, path: &Path) -> Result<()> {
+        let column_width = match self.opts.width {
             UnweaveWidth::Column(w) => w,
             _ => 0
         };
 
-        let column = match self.column_for_tag.get(tag) {
-            Some(c) => {
-                self.column_widths[*c as usize] =
-                    std::cmp::max(self.column_widths[*c as usize], column_width);
-                *c
-            }
-            None => {
-                let c = self.column_for_tag.len() as u32;
-                self.column_for_tag.insert(tag.to_vec(), c);
-                self.column_widths.push(column_width);
-                if let Some(lp) = lp {
-                    lp.set_column_widths(&self.column_widths);
-                }
-                c
+        for tag in read_column_map(path, self.opts.tag.ignore_case)? {
+            if self.column_for_tag.contains_key(&tag) {
+                continue;
             }
-        };
+            self.tag_columns += 1;
+            self.mapped_columns += 1;
+            let c = self.add_column(tag.clone(), column_width);
+            self.column_for_tag.insert(tag, c);
+        }
 
-        Some((column, grapheme_count))
+        Ok(())
     }
 
-    /// Returns the final column widths, in case they need to be adjusted
-    /// due to options.
-    fn final_column_widths(&mut self) -> &[u32] {
-        match self.opts.width {
-            UnweaveWidth::Line(w) => {
-                let ncolumns = self.column_widths.len() as u32;
-                for cw in self.column_widths.iter_mut() { *cw = w / ncolumns; }
-            },
-            _ => {},
-        };
+    /// Updates the printed position of each column.
+    fn update_column_positions(&mut self) {
+        let ncolumns = self.column_widths.len() as u32;
+        let mut order: Vec<u32> = (0..ncolumns)
+            .filter(|c| Some(*c) != self.unmatched_column && Some(*c) != self.overflow_column)
+            .collect();
+
+        // Mapped columns are created first and keep their order.
+        if self.opts.sort_columns {
+            order[self.mapped_columns..].sort_by(
+                |a, b| self.column_tags[*a as usize].cmp(&self.column_tags[*b as usize])
+            );
+        }
 
-        &self.column_widths
-    }
-}
+        // Per-input columns are grouped by input, keeping their order within
+        // each input.
+        if self.opts.per_input_columns {
+            order.sort_by_key(|c| self.column_inputs[*c as usize]);
+        }
 
-/// Perform the unweave operation into columns using a single pass of the data.
-///
+        if self.opts.reverse_columns {
+            order.reverse();
+        }
+
+        if let Some(o) = self.overflow_column {
+            order.push(o);
+        }
+
+        match (self.unmatched_column, self.opts.unmatched) {
+            (Some(u), UnweaveUnmatched::First) => order.insert(0, u),
+            (Some(u), UnweaveUnmatched::Last) => order.push(u),
+            _ => {},
+        }
+
+        self.column_positions = vec![0; order.len()];
+        for (pos, c) in order.iter().enumerate() {
+            self.column_positions[*c as usize] = pos as u32;
+        }
+    }
+
+    /// Sets the index of the input the following lines are read from.
+    fn set_input(&mut self, input: usize) {
+        self.current_input = input;
+    }
+
+    /// Returns the key identifying the column of a tag. With per-input columns
+    /// the key includes the current input, so each input gets its own columns.
+    fn column_key<'t>(&self, tag: &'t [u8]) -> Cow<'t, [u8]> {
+        if self.opts.per_input_columns {
+            let mut key = self.current_input.to_le_bytes().to_vec();
+            key.extend_from_slice(tag);
+            Cow::Owned(key)
+        } else {
+            Cow::Borrowed(tag)
+        }
+    }
+
+    /// Returns the position a column is printed at.
+    fn position_for_column(&self, column: u32) -> u32 {
+        self.column_positions[column as usize]
+    }
+
+    /// Records the last line printed in a column, returning whether the line
+    /// is identical to the previous line printed in the column.
+    fn is_repeated_line(&mut self, column: u32, line: &[u8]) -> bool {
+        let column = column as usize;
+        if self.column_last_lines.len() <= column {
+            self.column_last_lines.resize(column + 1, None);
+        }
+
+        match &mut self.column_last_lines[column] {
+            Some(last) if last == line => true,
+            last => {
+                *last = Some(line.to_vec());
+                false
+            },
+        }
+    }
+
+    /// Records that a line is about to be printed in a column, returning
+    /// whether the line should actually be printed, based on the maximum
+    /// number of lines allowed for each stream.
+    fn count_printed_line(&mut self, column: u32) -> bool {
+        let column = column as usize;
+        if self.column_line_counts.len() <= column {
+            self.column_line_counts.resize(column + 1, 0);
+        }
+        self.column_line_counts[column] += 1;
+
+        match self.opts.max_lines_per_stream {
+            Some(m) => self.column_line_counts[column] <= m,
+            None => true,
+        }
+    }
+
+    /// Returns the column widths, ordered by printed position, taking into
+    /// account the minimum column width.
+    fn positioned_column_widths(&self) -> Vec<u32> {
+        let mut widths = vec![0; self.column_widths.len()];
+        for (c, w) in self.column_widths.iter().enumerate() {
+            // The minimum width doesn't apply to explicit per tag widths.
+            let min_width = match self.column_width_overrides[c] {
+                Some(_) => 0,
+                None => self.opts.min_column_width.unwrap_or(0),
+            };
+            widths[self.column_positions[c] as usize] = std::cmp::max(*w, min_width);
+        }
+        widths
+    }
+
+    /// Returns the tag (or a label, for the unmatched and overflow columns) and
+    /// the number of lines of each column, ordered by printed position.
+    fn stream_line_counts(&self) -> Vec<(Vec<u8>, u64)> {
+        let mut counts = vec![(Vec::new(), 0); self.column_tags.len()];
+        for (c, tag) in self.column_tags.iter().enumerate() {
+            let label = if Some(c as u32) == self.unmatched_column {
+                b"(unmatched)".to_vec()
+            } else if Some(c as u32) == self.overflow_column {
+                b"(overflow)".to_vec()
+            } else {
+                tag.clone()
+            };
+            let count = self.column_line_counts.get(c).copied().unwrap_or(0);
+            counts[self.column_positions[c] as usize] = (label, count);
+        }
+        counts
+    }
+
+    /// Returns the number of lines of all columns for the footer row, ordered
+    /// by printed position.
+    fn positioned_column_footers(&self) -> Vec<Vec<u8>> {
+        self.stream_line_counts().iter()
+            .map(|(_, count)| count.to_string().into_bytes())
+            .collect()
+    }
+
+    /// Returns the number of lines that matched the pattern, including lines
+    /// beyond the maximum number of lines allowed for each stream.
+    fn matched_line_count(&self) -> u64 {
+        self.column_line_counts.iter().enumerate()
+            .filter(|(c, _)| Some(*c as u32) != self.unmatched_column)
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    /// Counts the invalid UTF-8 sequences in a line, remembering the line
+    /// number and offset of the first few.
+    fn count_invalid_utf8(&mut self, line: &[u8], line_number: u64) {
+        let count = &mut self.invalid_utf8_count;
+        let first = &mut self.invalid_utf8_first;
+        for_each_invalid_utf8(line, |offset| {
+            *count += 1;
+            if first.len() < MAX_INVALID_UTF8_REPORTED {
+                first.push((line_number, offset));
+            }
+        });
+    }
+
+    /// Returns the tags of all columns, ordered by printed position. The tag of
+    /// the column for unmatched lines is empty.
+    fn positioned_column_tags(&self) -> Vec<Vec<u8>> {
+        let mut tags = vec![Vec::new(); self.column_tags.len()];
+        for (c, tag) in self.column_tags.iter().enumerate() {
+            tags[self.column_positions[c] as usize] = tag.clone();
+        }
+        tags
+    }
+
+    /// Returns the labels of all columns for the header row, ordered by printed
+    /// position.
+    fn positioned_column_labels(&self) -> Vec<Vec<u8>> {
+        match self.opts.column_labels {
+            UnweaveColumnLabels::Tag => self.positioned_column_tags(),
+            UnweaveColumnLabels::Index => (0..self.column_tags.len())
+                .map(|p| p.to_string().into_bytes())
+                .collect(),
+            UnweaveColumnLabels::None => Vec::new(),
+        }
+    }
+
+    /// Convenience function to process a line, without updating any ColumnPrinter
+    /// instance.
+    fn process_line(&mut self, line: &[u8]) -> Option<(u32,Option<NonZeroU32>)> {
+        self.process_line_with_column_printer(line, None)
+    }
+
+    /// Process a line, updating tracking information about the required columns
+    /// and their width.
+    ///
+    /// Optionally, if a ColumnPrinter instance is provided, the instance
+    /// updated with any new column information.
+    ///
+    /// Returns the column the line belongs in, or None, if the line should be
+    /// ignored.
+    fn process_line_with_column_printer(&mut self, line: &[u8],
+                                        lp: Option<&mut ColumnPrinter>) -> Option<(u32,Option<NonZeroU32>)> {
+        let tag = match self.tag_finder.find_tag_in(line) {
+            Some(tag) => Some(tag),
+            None if line.is_empty() && self.opts.blank != UnweaveBlank::Drop =>
+                return self.process_blank_line(),
+            None if self.unmatched_column.is_some() => None,
+            None => return None,
+        };
+
+        let grapheme_count = line_grapheme_count(self.opts, &self.tag_finder.strip_found_tag(line));
+
+        self.process_tag(tag, grapheme_count, lp)
+    }
+
+    /// Process the tag (or None for an unmatched line) and grapheme count of a
+    /// line, updating tracking information about the required columns and
+    /// their width.
+    ///
+    /// Optionally, if a ColumnPrinter instance is provided, the instance
+    /// updated with any new column information.
+    ///
+    /// Returns the column the line belongs in, or None, if the line should be
+    /// ignored.
+    fn process_tag(&mut self, tag: Option<Cow<[u8]>>, grapheme_count: Option<NonZeroU32>,
+                   lp: Option<&mut ColumnPrinter>) -> Option<(u32,Option<NonZeroU32>)> {
+        if tag.is_none() && self.unmatched_column.is_none() {
+            return None;
+        }
+
+        let column_width = match self.opts.width { 
+            UnweaveWidth::Undefined => grapheme_count.map_or(0, |g| g.get()),
+            UnweaveWidth::Column(w) => w,
+            _ => 0
+        };
+
+        let existing_column = match &tag {
+            Some(tag) => self.column_for_tag.get(&*self.column_key(tag)).copied(),
+            None => self.unmatched_column,
+        };
+
+        let column = match existing_column {
+            Some(c) => {
+                if self.column_width_overrides[c as usize].is_none() {
+                    self.column_widths[c as usize] =
+                        std::cmp::max(self.column_widths[c as usize], column_width);
+                }
+                c
+            }
+            None => {
+                let tag = tag.unwrap().into_owned();
+                let ncolumns = self.column_widths.len();
+                let c = match self.opts.max_columns {
+                    Some(m) if self.tag_columns >= m => {
+                        // Tags beyond the maximum number of columns share
+                        // a single overflow column.
+                        match self.overflow_column {
+                            Some(o) => {
+                                self.column_widths[o as usize] =
+                                    std::cmp::max(self.column_widths[o as usize], column_width);
+                                o
+                            },
+                            None => {
+                                let o = self.add_column(Vec::new(), column_width);
+                                self.overflow_column = Some(o);
+                                self.update_column_positions();
+                                o
+                            },
+                        }
+                    },
+                    _ => {
+                        self.tag_columns += 1;
+                        self.add_column(tag.clone(), column_width)
+                    },
+                };
+                if let Some(lp) = lp {
+                    if self.column_widths.len() > ncolumns {
+                        // Rows printed before the column was added don't
+                        // account for it.
+                        if lp.has_output() && self.late_column_tag.is_none() {
+                            self.late_column_tag = Some(tag.clone());
+                        }
+                        lp.set_column_widths(&self.positioned_column_widths());
+                    }
+                }
+                let key = self.column_key(&tag).into_owned();
+                self.column_for_tag.insert(key, c);
+                c
+            }
+        };
+
+        self.last_column = Some(column);
+
+        Some((column, grapheme_count))
+    }
+
+    /// Process an empty line that didn't match the pattern.
+    ///
+    /// Returns the column of the previous line, or BLANK_ROW_COLUMN for an
+    /// empty row spanning all columns, depending on the options, or None if
+    /// the line should be ignored.
+    fn process_blank_line(&self) -> Option<(u32,Option<NonZeroU32>)> {
+        match self.opts.blank {
+            UnweaveBlank::Drop => None,
+            UnweaveBlank::LastColumn => self.last_column.map(|c| (c, None)),
+            UnweaveBlank::AllColumns => Some((BLANK_ROW_COLUMN, None)),
+        }
+    }
+
+    /// Returns the final column widths, ordered by printed position, in case
+    /// they need to be adjusted due to options. With a line width, the width
+    /// left after the separators of the column printer and the columns with a
+    /// width override is divided equally between the other columns.
+    fn final_column_widths(&mut self, column_printer: &ColumnPrinter) -> Vec<u32> {
+        match self.opts.width {
+            UnweaveWidth::Line(w) => {
+                let overrides = &self.column_width_overrides;
+                let w = w.saturating_sub(column_printer.separators_width(overrides.len()))
+                    .saturating_sub(overrides.iter().flatten().sum());
+                let ncolumns = overrides.iter().filter(|o| o.is_none()).count() as u32;
+                for (cw, o) in self.column_widths.iter_mut().zip(overrides) {
+                    if o.is_none() { *cw = w / ncolumns; }
+                }
+            },
+            // Columns with a width override keep their width
+            UnweaveWidth::Percent(p, Some(w)) => {
+                let w = std::cmp::max((w as u64 * p as u64 / 100) as u32, 1);
+                for (cw, o) in self.column_widths.iter_mut().zip(&self.column_width_overrides) {
+                    if o.is_none() { *cw = w; }
+                }
+            },
+            _ => {},
+        }
+
+        self.positioned_column_widths()
+    }
+}
+
+/// Prints a line in its column, unless its stream has already reached the
+/// maximum number of lines, or, with the uniq option, the line is identical to
+/// the previous line printed in the column. Empty rows spanning all columns are
+/// always printed.
+fn print_line(column_tracker: &mut ColumnTracker, column_printer: &mut ColumnPrinter, line: &[u8],
+              column: u32, grapheme_count: Option<NonZeroU32>, line_number: u64) -> Result<()> {
+    if column == BLANK_ROW_COLUMN {
+        return column_printer.print_blank_row(line_number);
+    }
+
+    let stripped_line = column_tracker.tag_finder.strip_tag(line);
+    let shown_line = show_control_chars(&stripped_line, column_tracker.opts.show_control);
+    let line = &shown_line[..];
+
+    if column_tracker.opts.uniq && column_tracker.is_repeated_line(column, line) {
+        return Ok(());
+    }
+
+    if column_tracker.count_printed_line(column) {
+        if column_tracker.opts.warn_invalid_utf8 {
+            column_tracker.count_invalid_utf8(line, line_number);
+        }
+        let pos = column_tracker.position_for_column(column);
+        let highlight_range = if column_printer.highlights() && !column_tracker.opts.tag.strip {
+            column_tracker.tag_finder.tag_range_in(line)
+        } else {
+            None
+        };
+        column_printer.set_highlight_range(highlight_range);
+        if column_tracker.opts.record_separator.is_some() {
+            column_printer.print_record_in_column(line, pos, line_number,
+                                                  column_tracker.opts.line_terminator())?;
+        } else {
+            column_printer.print_in_column(line, pos, grapheme_count, line_number)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The order in which the items of multiple inputs are taken, as specified by
+/// the interleave option. With round-robin interleaving and padding (see
+/// UnweaveRrFill), exhausted inputs keep their turn, each producing an empty
+/// padding row, until all inputs are exhausted.
+struct InterleaveOrder {
+    active: Vec<usize>,
+    exhausted: Vec<bool>,
+    nexhausted: usize,
+    pos: usize,
+    padding: u32,
+    interleave: UnweaveInterleave,
+    rr_fill: UnweaveRrFill,
+}
+
+impl InterleaveOrder {
+    fn new(ninputs: usize, interleave: UnweaveInterleave, rr_fill: UnweaveRrFill) -> Self {
+        InterleaveOrder {
+            active: (0..ninputs).collect(),
+            exhausted: vec![false; ninputs],
+            nexhausted: 0,
+            pos: 0,
+            padding: 0,
+            interleave,
+            rr_fill,
+        }
+    }
+
+    /// Whether exhausted inputs keep their turn, producing padding rows.
+    fn pads(&self) -> bool {
+        self.interleave == UnweaveInterleave::RoundRobin && self.rr_fill == UnweaveRrFill::Pad
+    }
+
+    /// Returns the index of the input whose turn it is to provide an item, or
+    /// None if all inputs are exhausted.
+    fn current(&mut self) -> Option<usize> {
+        while self.active.len() > self.nexhausted {
+            if self.pos >= self.active.len() {
+                self.pos = 0;
+            }
+            let i = self.active[self.pos];
+            if !self.exhausted[i] {
+                return Some(i);
+            }
+            self.padding += 1;
+            self.pos += 1;
+        }
+
+        None
+    }
+
+    /// Moves past an item provided by the current input. Returns the number
+    /// of padding rows to print before the item.
+    fn advance(&mut self) -> u32 {
+        if self.interleave == UnweaveInterleave::RoundRobin {
+            self.pos += 1;
+        }
+        std::mem::take(&mut self.padding)
+    }
+
+    /// Marks the current input as having no more items.
+    fn exhaust(&mut self) {
+        if self.pads() {
+            self.exhausted[self.active[self.pos]] = true;
+            self.nexhausted += 1;
+            self.padding += 1;
+            self.pos += 1;
+        } else {
+            self.active.remove(self.pos);
+        }
+    }
+}
+
+/// Calls "f" with each line of the inputs, in the order specified by the
+/// interleave option (see InterleaveOrder), counting each line in "progress".
+/// Inputs are opened with "open", all at once with round-robin interleaving,
+/// or otherwise when they are first needed, and are closed when they have no
+/// more lines. Along with each line, "f" gets the number of empty padding rows
+/// to print before it.
+fn for_each_input_line<O, F>(opts: &UnweaveOptionsColumns, open: O, progress: &mut Progress,
+                             mut f: F) -> Result<()>
+where
+    O: Fn(usize, &Path) -> Result<FileLines>,
+    F: FnMut(usize, u32, u64, &[u8]) -> Result<()>,
+{
+    let mut order = InterleaveOrder::new(opts.inputs.len(), opts.interleave, opts.rr_fill);
+    let mut files = match opts.interleave {
+        UnweaveInterleave::Input => opts.inputs.iter().map(|_| None).collect(),
+        UnweaveInterleave::RoundRobin => opts.inputs.iter().enumerate()
+            .map(|(i, input)| Ok(Some(open(i, input)?)))
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    while let Some(i) = order.current() {
+        let file_lines = match &mut files[i] {
+            Some(file_lines) => file_lines,
+            slot => slot.insert(open(i, &opts.inputs[i])?),
+        };
+        let offset = file_lines.offset();
+        match file_lines.next() {
+            Some(line) => {
+                progress.count_line(line);
+                f(i, order.advance(), offset, line)?;
+            },
+            None => {
+                file_lines.check_line_length(&opts.inputs[i])?;
+                files[i] = None;
+                order.exhaust();
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Iterator over the items of multiple iterators, in the order specified by
+/// the interleave option, along with the index of the iterator each item
+/// came from and the number of padding rows before it (see InterleaveOrder).
+struct Interleaved<I> {
+    iters: Vec<I>,
+    order: InterleaveOrder,
+}
+
+impl<I: Iterator> Interleaved<I> {
+    fn new(iters: Vec<I>, interleave: UnweaveInterleave, rr_fill: UnweaveRrFill) -> Self {
+        let order = InterleaveOrder::new(iters.len(), interleave, rr_fill);
+        Interleaved { iters, order }
+    }
+}
+
+impl<I: Iterator> Iterator for Interleaved<I> {
+    type Item = (usize, u32, I::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(i) = self.order.current() {
+            match self.iters[i].next() {
+                Some(item) => return Some((i, self.order.advance(), item)),
+                None => self.order.exhaust(),
+            }
+        }
+
+        None
+    }
+}
+
+/// Returns the cached lines of all inputs ordered by line number, i.e., in
+/// the order they were processed, along with the index of their input.
+fn cached_lines_by_line_number(lines_vec: &[Vec<CachedLine>])
+    -> impl Iterator<Item = (usize, &CachedLine)>
+{
+    let mut cursors = vec![0; lines_vec.len()];
+
+    std::iter::from_fn(move || {
+        let i = (0..lines_vec.len())
+            .filter(|&i| cursors[i] < lines_vec[i].len())
+            .min_by_key(|&i| lines_vec[i][cursors[i]].3)?;
+        cursors[i] += 1;
+        Some((i, &lines_vec[i][cursors[i] - 1]))
+    })
+}
+
+/// Writes the layout of the columns to the layout file, if one is specified,
+/// with the index, tag, width and start offset, in terminal cells from the
+/// start of the printed line, of each column in printed order.
+fn write_layout(opts: &UnweaveOptionsColumns, column_tracker: &ColumnTracker,
+                column_printer: &ColumnPrinter) -> Result<()> {
+    let layout = match &opts.layout {
+        Some(l) => l,
+        None => return Ok(()),
+    };
+
+    let write_layout = || -> Result<()> {
+        let mut out = BufWriter::new(File::create(layout)?);
+        let tags = column_tracker.positioned_column_tags();
+        writeln!(out, "INDEX\tTAG\tSTART\tWIDTH")?;
+        for (pos, (tag, width)) in tags.iter().zip(&column_printer.column_widths).enumerate() {
+            write!(out, "{}\t", pos)?;
+            out.write_all(tag)?;
+            writeln!(out, "\t{}\t{}", column_printer.line_start_offset(pos as u32), width)?;
+        }
+        out.flush()?;
+        Ok(())
+    };
+
+    write_layout().with_context(
+        || format!("Failed to write layout file {}", layout.display())
+    )
+}
+
+/// Returns the cached lines of all inputs, along with the index of their
+/// input, in the order specified by the interleave option.
+fn cached_lines_in_order<'a>(opts: &UnweaveOptionsColumns, lines_vec: &'a [Vec<CachedLine>])
+    -> Box<dyn Iterator<Item = (usize, &'a CachedLine)> + 'a>
+{
+    match opts.interleave {
+        UnweaveInterleave::Input => Box::new(
+            lines_vec.iter().enumerate().flat_map(|(i, lines)| lines.iter().map(move |l| (i, l)))
+        ),
+        UnweaveInterleave::RoundRobin => Box::new(cached_lines_by_line_number(lines_vec)),
+    }
+}
+
+/// Sorts the lines of each column by the sort key. The lines of a column
+/// move between the rows of that column, while the line numbers stay with
+/// the rows, so the layout of the rows doesn't change.
+fn sort_cached_lines(lines: &mut [(usize, CachedLine)], file_contents_vec: &[FileContents],
+                     sort_key: &regex::bytes::Regex) {
+    let mut rows_for_column: AHashMap<u32, Vec<usize>> = AHashMap::new();
+    for (row, (_, (_, column, ..))) in lines.iter().enumerate() {
+        if *column != BLANK_ROW_COLUMN {
+            rows_for_column.entry(*column).or_default().push(row);
+        }
+    }
+
+    for rows in rows_for_column.values() {
+        let mut contents: Vec<_> = rows.iter()
+            .map(|&r| (lines[r].0, lines[r].1.0.clone(), lines[r].1.2))
+            .collect();
+        sort_by_line_key(&mut contents, sort_key,
+                         |(i, line_range, _)| &file_contents_vec[*i].contents()[line_range.clone()]);
+        for (&r, (i, line_range, grapheme_count)) in rows.iter().zip(contents) {
+            lines[r].0 = i;
+            lines[r].1.0 = line_range;
+            lines[r].1.2 = grapheme_count;
+        }
+    }
+}
+
+/// Perform the unweave operation into columns using a single pass of the data.
+///
 /// Note that single pass is only possible in limited circumstances (see
 /// unweave_into_columns where the decision is made).
-fn unweave_into_columns_single_pass(opts: &UnweaveOptionsColumns) -> Result<()> {
-    let mut column_printer = ColumnPrinter::new(&opts)?;
-    let mut column_tracker = ColumnTracker::new(&opts)?;
-
-    for input in &opts.inputs {
-        let mut file_lines = FileLines::new(input, opts.mmap)?;
-        while let Some(line) = file_lines.next() {
-            match column_tracker.process_line_with_column_printer(line, Some(&mut column_printer)) {
-                Some((column, grapheme_count)) => column_printer.print_in_column(line, column, grapheme_count)?,
-                None => continue,
+fn unweave_into_columns_single_pass(opts: &UnweaveOptionsColumns, writer: OutputWriter,
+                                    column_tracker: &mut ColumnTracker) -> Result<()>
+{
+    let mut column_printer = ColumnPrinter::new(opts, writer)?;
+    column_printer.set_column_widths(&column_tracker.positioned_column_widths());
+    let mut progress = Progress::new(opts.progress);
+    let mut line_number = 0;
+    let split_on = opts.split_on.as_deref().map(str::as_bytes);
+
+    let open = |i, input: &Path| {
+        let mut file_lines = if opts.follow && i == opts.inputs.len() - 1 {
+            FileLines::new_following(input, opts.buffer_size, opts.line_terminator())?
+        } else {
+            FileLines::new(input, opts.mmap, opts.buffer_size, opts.line_terminator())?
+        };
+        file_lines.check_binary(input, opts.binary)?;
+        file_lines.set_max_line(opts.max_line());
+        Ok(file_lines)
+    };
+
+    for_each_input_line(opts, open, &mut progress, |i, padding, offset, line| {
+        for _ in 0..padding {
+            column_printer.print_blank_row(line_number)?;
+        }
+        line_number += 1;
+        column_tracker.set_input(i);
+        for (start, line) in sub_records(line, split_on) {
+            column_printer.set_line_offset(offset + start as u64);
+            let processed =
+                column_tracker.process_line_with_column_printer(line, Some(&mut column_printer));
+            if opts.strict_single_pass {
+                if let Some(tag) = &column_tracker.late_column_tag {
+                    bail!(UnweaveError::ColumnAfterOutput(String::from_utf8_lossy(tag).into_owned()));
+                }
+            }
+            if let Some((column, grapheme_count)) = processed {
+                print_line(column_tracker, &mut column_printer, line, column, grapheme_count,
+                           line_number)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    column_printer.flush()?;
+    write_layout(opts, column_tracker, &column_printer)?;
+
+    Ok(())
+}
+
+/// Perform the unweave operation into columns using two passes, using cached
+/// data from the first pass (including loaded file contents), to speed up
+/// the second pass.
+fn unweave_into_columns_two_pass_cached(opts: &UnweaveOptionsColumns, writer: OutputWriter,
+                                        column_tracker: &mut ColumnTracker) -> Result<()>
+{
+    // First pass gets file contents and lines/column info
+    let CachedInputs { file_contents_vec, lines_vec, line_number, padding_rows } =
+        cache_inputs_with_jobs(opts, column_tracker)?;
+
+    let mut column_printer = ColumnPrinter::new(opts, writer)?;
+    column_printer.set_column_widths(&column_tracker.final_column_widths(&column_printer));
+    column_printer.set_max_line_number(line_number);
+    column_printer.set_max_offset(
+        lines_vec.iter().flatten().map(|(line_range, ..)| line_range.start as u64).max().unwrap_or(0)
+    );
+    if opts.prints_header() {
+        column_printer.print_header(&column_tracker.positioned_column_labels())?;
+    }
+
+    // Second pass, which now has all the line and column information, prints
+    // out the data.
+    let mut next_padding_row = 0;
+    let mut print_cached_line = |i: usize, (line_range, col, grapheme_count, line_number): &CachedLine| -> Result<()> {
+        // Padding rows may precede lines that aren't printed, so print all
+        // padding rows up to this line.
+        while padding_rows.get(next_padding_row).is_some_and(|n| n <= line_number) {
+            column_printer.print_blank_row(padding_rows[next_padding_row] - 1)?;
+            next_padding_row += 1;
+        }
+        let line = &file_contents_vec[i].contents()[line_range.clone()];
+        column_printer.set_line_offset(line_range.start as u64);
+        print_line(column_tracker, &mut column_printer, line, *col, *grapheme_count,
+                   *line_number)
+    };
+
+    match opts.sort_key_regex()? {
+        Some(sort_key) => {
+            let mut lines: Vec<_> = cached_lines_in_order(opts, &lines_vec)
+                .map(|(i, line)| (i, line.clone()))
+                .collect();
+            sort_cached_lines(&mut lines, &file_contents_vec, &sort_key);
+            for (i, line) in &lines {
+                print_cached_line(*i, line)?;
             }
+        },
+        None => {
+            for (i, line) in cached_lines_in_order(opts, &lines_vec) {
+                print_cached_line(i, line)?;
+            }
+        },
+    }
+
+    for n in &padding_rows[next_padding_row..] {
+        column_printer.print_blank_row(n - 1)?;
+    }
+
+    if opts.footer {
+        column_printer.print_footer(&column_tracker.positioned_column_footers())?;
+    }
+
+    column_printer.flush()?;
+    write_layout(opts, column_tracker, &column_printer)?;
+
+    Ok(())
+}
+
+/// The line range within the file contents, column, grapheme count and line
+/// number of a line.
+type CachedLine = (Range<usize>, u32, Option<NonZeroU32>, u64);
+
+/// The contents and line information of all inputs, along with the total
+/// number of lines, and the line number that each padding row precedes.
+struct CachedInputs {
+    file_contents_vec: Vec<FileContents>,
+    lines_vec: Vec<Vec<CachedLine>>,
+    line_number: u64,
+    padding_rows: Vec<u64>,
+}
+
+/// Reads all inputs and processes their lines, in parallel if multiple jobs
+/// are allowed and the lines of each input are processed together.
+fn cache_inputs_with_jobs(opts: &UnweaveOptionsColumns, column_tracker: &mut ColumnTracker)
+    -> Result<CachedInputs>
+{
+    if opts.jobs > 1 && opts.inputs.len() > 1 && opts.interleave == UnweaveInterleave::Input {
+        cache_inputs_parallel(opts, column_tracker)
+    } else {
+        cache_inputs(opts, column_tracker)
+    }
+}
+
+/// Reads all inputs and processes their lines.
+fn cache_inputs(opts: &UnweaveOptionsColumns, column_tracker: &mut ColumnTracker)
+    -> Result<CachedInputs>
+{
+    let file_contents_vec = opts.inputs.iter()
+        .map(|input| {
+            let file_contents = FileContents::new(input, opts.mmap)?;
+            file_contents.check_binary(input, opts.line_terminator(), opts.binary)?;
+            Ok(file_contents)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut lines_vec = vec![Vec::new(); file_contents_vec.len()];
+    let mut curs = vec![0; file_contents_vec.len()];
+    let mut progress = Progress::new(opts.progress);
+    let mut line_number = 0;
+    let mut padding_rows = Vec::new();
+    let split_on = opts.split_on.as_deref().map(str::as_bytes);
+
+    let record_separator = opts.record_separator_regex()?;
+    let input_lines = file_contents_vec.iter()
+        .map(|file_contents| SliceTrimmedLines::new(file_contents.contents(), opts.line_terminator(),
+                                                    record_separator.as_ref()))
+        .collect();
+
+    for (i, padding, (trimmed_line, len)) in Interleaved::new(input_lines, opts.interleave,
+                                                              opts.rr_fill) {
+        let trimmed_line = limit_line_length(trimmed_line, opts.max_line(), &opts.inputs[i])?;
+        progress.count_line(trimmed_line);
+        line_number += 1;
+        padding_rows.extend(std::iter::repeat_n(line_number, padding as usize));
+
+        column_tracker.set_input(i);
+        for (start, sub_record) in sub_records(trimmed_line, split_on) {
+            if let Some((column, grapheme_count)) = column_tracker.process_line(sub_record) {
+                let sub_start = curs[i] + start;
+                lines_vec[i].push((sub_start..sub_start+sub_record.len(), column, grapheme_count,
+                                   line_number));
+            }
+        }
+
+        curs[i] += len;
+    }
+
+    Ok(CachedInputs { file_contents_vec, lines_vec, line_number, padding_rows })
+}
+
+/// The line range within the file contents, index of tag (or None for
+/// unmatched lines), grapheme count and line number within the input of a
+/// scanned line.
+type ScannedLine = (Range<usize>, Option<u32>, Option<NonZeroU32>, u64);
+
+/// The contents of an input, along with the tags and lines found in it, and
+/// the total number of lines and of bytes in them.
+struct ScannedInput {
+    file_contents: FileContents,
+    tags: Vec<Vec<u8>>,
+    lines: Vec<ScannedLine>,
+    nlines: u64,
+    nbytes: u64,
+}
+
+/// Reads an input and finds the tag and grapheme count of each line,
+/// independently of any other input.
+fn scan_input(opts: &UnweaveOptionsColumns, input: &Path) -> Result<ScannedInput> {
+    let file_contents = FileContents::new(input, opts.mmap)?;
+    file_contents.check_binary(input, opts.line_terminator(), opts.binary)?;
+    let record_separator = opts.record_separator_regex()?;
+    let mut tag_finder = TagFinder::new(&opts.tag)?;
+    let mut tag_index = AHashMap::new();
+    let mut tags = Vec::new();
+    let mut lines = Vec::new();
+    let mut nlines = 0;
+    let mut nbytes = 0;
+    let mut cur = 0;
+    let split_on = opts.split_on.as_deref().map(str::as_bytes);
+
+    for (trimmed_line, len) in SliceTrimmedLines::new(file_contents.contents(), opts.line_terminator(),
+                                                      record_separator.as_ref()) {
+        let trimmed_line = limit_line_length(trimmed_line, opts.max_line(), input)?;
+        nlines += 1;
+        nbytes += trimmed_line.len() as u64;
+
+        for (start, sub_record) in sub_records(trimmed_line, split_on) {
+            let tag = match tag_finder.find_tag_in(sub_record) {
+                Some(tag) => Some(*tag_index.entry(tag.into_owned()).or_insert_with_key(|t: &Vec<u8>| {
+                    tags.push(t.clone());
+                    (tags.len() - 1) as u32
+                })),
+                None if opts.unmatched != UnweaveUnmatched::Drop => None,
+                None if sub_record.is_empty() && opts.blank != UnweaveBlank::Drop => None,
+                None => continue,
+            };
+
+            let grapheme_count = line_grapheme_count(opts, &tag_finder.strip_found_tag(sub_record));
+            let sub_start = cur + start;
+            lines.push((sub_start..sub_start+sub_record.len(), tag, grapheme_count, nlines));
+        }
+
+        cur += len;
+    }
+
+    Ok(ScannedInput { file_contents, tags, lines, nlines, nbytes })
+}
+
+/// Reads all inputs and processes their lines, scanning the inputs in
+/// parallel. The scan results are then processed in input order, so the
+/// column information is identical to the one produced by cache_inputs.
+fn cache_inputs_parallel(opts: &UnweaveOptionsColumns, column_tracker: &mut ColumnTracker)
+    -> Result<CachedInputs>
+{
+    let ninputs = opts.inputs.len();
+    let jobs = std::cmp::min(opts.jobs as usize, ninputs);
+
+    let mut scanned: Vec<Option<Result<ScannedInput>>> = (0..ninputs).map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..jobs).map(|job| {
+            scope.spawn(move || {
+                opts.inputs.iter().enumerate().skip(job).step_by(jobs)
+                    .map(|(i, input)| (i, scan_input(opts, input)))
+                    .collect::<Vec<_>>()
+            })
+        }).collect();
+
+        for handle in handles {
+            for (i, result) in handle.join().unwrap() {
+                scanned[i] = Some(result);
+            }
+        }
+    });
+
+    let mut file_contents_vec = Vec::new();
+    let mut lines_vec = Vec::new();
+    let mut progress = Progress::new(opts.progress);
+    let mut line_number = 0;
+
+    for (i, scanned_input) in scanned.into_iter().enumerate() {
+        let ScannedInput { file_contents, tags, lines: scanned_lines, nlines, nbytes } =
+            scanned_input.unwrap()?;
+        progress.count_lines(nlines, nbytes);
+        column_tracker.set_input(i);
+        let mut lines = Vec::new();
+
+        for (line_range, tag, grapheme_count, n) in scanned_lines {
+            let tag = tag.map(|t| Cow::Borrowed(&tags[t as usize][..]));
+            let processed = match tag {
+                None if line_range.is_empty() && opts.blank != UnweaveBlank::Drop =>
+                    column_tracker.process_blank_line(),
+                tag => column_tracker.process_tag(tag, grapheme_count, None),
+            };
+            if let Some((column, grapheme_count)) = processed {
+                lines.push((line_range, column, grapheme_count, line_number + n));
+            }
+        }
+
+        line_number += nlines;
+        file_contents_vec.push(file_contents);
+        lines_vec.push(lines);
+    }
+
+    Ok(CachedInputs { file_contents_vec, lines_vec, line_number, padding_rows: Vec::new() })
+}
+
+/// Perform the unweave operation into columns using two passes, maintaining
+/// only very limited information between passes, requiring a reread
+/// of the data during the second pass.
+fn unweave_into_columns_two_pass_reread(opts: &UnweaveOptionsColumns, writer: OutputWriter,
+                                        column_tracker: &mut ColumnTracker) -> Result<()>
+{
+    let mut progress = Progress::new(opts.progress);
+
+    let mut line_number = 0;
+
+    let open = |_, input: &Path| {
+        let mut file_lines = FileLines::new(input, opts.mmap, opts.buffer_size, opts.line_terminator())?;
+        file_lines.set_max_line(opts.max_line());
+        Ok(file_lines)
+    };
+    // Inputs are only checked for binary data once, in the first pass.
+    let open_checked = |i, input: &Path| {
+        let mut file_lines = open(i, input)?;
+        file_lines.check_binary(input, opts.binary)?;
+        Ok(file_lines)
+    };
+
+    let mut max_offset = 0;
+    let split_on = opts.split_on.as_deref().map(str::as_bytes);
+
+    // First pass populates column info
+    for_each_input_line(opts, open_checked, &mut progress, |i, _, offset, line| {
+        line_number += 1;
+        column_tracker.set_input(i);
+        for (start, line) in sub_records(line, split_on) {
+            max_offset = max_offset.max(offset + start as u64);
+            column_tracker.process_line(line);
+        }
+        Ok(())
+    })?;
+
+    let mut column_printer = ColumnPrinter::new(opts, writer)?;
+    column_printer.set_column_widths(&column_tracker.final_column_widths(&column_printer));
+    column_printer.set_max_line_number(line_number);
+    column_printer.set_max_offset(max_offset);
+    if opts.prints_header() {
+        column_printer.print_header(&column_tracker.positioned_column_labels())?;
+    }
+
+    line_number = 0;
+    // Blank lines in the second pass follow the columns of the second pass
+    column_tracker.last_column = None;
+
+    // Second pass prints the columns
+    for_each_input_line(opts, open, &mut progress, |i, padding, offset, line| {
+        for _ in 0..padding {
+            column_printer.print_blank_row(line_number)?;
+        }
+        line_number += 1;
+        column_tracker.set_input(i);
+        for (start, line) in sub_records(line, split_on) {
+            column_printer.set_line_offset(offset + start as u64);
+            if let Some((column, grapheme_count)) = column_tracker.process_line(line) {
+                print_line(column_tracker, &mut column_printer, line, column, grapheme_count,
+                           line_number)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    if opts.footer {
+        column_printer.print_footer(&column_tracker.positioned_column_footers())?;
+    }
+
+    column_printer.flush()?;
+    write_layout(opts, column_tracker, &column_printer)?;
+
+    Ok(())
+}
+
+/// Perform the unweave operation into multiple columns, one column per matched stream.
+///
+/// The output is written to the output file specified in the options, or to
+/// the standard output. Returns the number of lines that matched the pattern.
+pub fn unweave_into_columns(opts: &UnweaveOptionsColumns) -> Result<u64> {
+    unweave_into_columns_with_output(opts, None)
+}
+
+/// Perform the unweave operation into multiple columns, one column per matched
+/// stream, writing the output to the specified writer. The output file
+/// specified in the options is ignored. Returns the number of lines that
+/// matched the pattern.
+pub fn unweave_into_columns_with_writer<'w>(opts: &UnweaveOptionsColumns,
+                                            writer: Box<dyn Write + 'w>) -> Result<u64> {
+    unweave_into_columns_with_output(opts, Some(writer))
+}
+
+/// Perform the unweave operation into groups of lines, one group per column
+/// in column order, each containing all the lines of the column. Groups are
+/// separated by a line with the group separator. The output is written to the
+/// output file specified in the options, or to the standard output. Returns
+/// the number of lines that matched the pattern.
+pub fn unweave_into_grouped(opts: &UnweaveOptionsColumns) -> Result<u64> {
+    let mut writer: Box<dyn Write> = match &opts.output {
+        Some(o) => Box::new(
+            BufWriter::with_capacity(
+                opts.buffer_size,
+                File::create(o).with_context(
+                    || format!("Failed to create output file {}", o.display())
+                )?
+            )
+        ),
+        None if opts.stats => Box::new(io::sink()),
+        None => Box::new(BufWriter::with_capacity(opts.buffer_size, io::stdout())),
+    };
+    let mut column_tracker = ColumnTracker::new(opts)?;
+
+    match unweave_into_grouped_with_tracker(opts, &mut writer, &mut column_tracker) {
+        Err(e) if !is_broken_pipe(&e) => return Err(e),
+        _ => {},
+    }
+
+    if opts.stats {
+        write_stats(&mut io::stderr().lock(), &column_tracker.stream_line_counts())?;
+    }
+
+    Ok(column_tracker.matched_line_count())
+}
+
+/// Perform the unweave operation into groups of lines, using the cached
+/// lines of the first pass of the two-pass cached mode.
+fn unweave_into_grouped_with_tracker(opts: &UnweaveOptionsColumns, writer: &mut dyn Write,
+                                     column_tracker: &mut ColumnTracker) -> Result<()> {
+    let CachedInputs { file_contents_vec, lines_vec, .. } = cache_inputs_with_jobs(opts, column_tracker)?;
+    let line_ending = opts.output_line_ending().as_bytes();
+    let highlight = opts.highlights() && !opts.tag.strip;
+
+    // Rows of blank lines in all columns don't belong to any group
+    let mut groups = vec![Vec::new(); column_tracker.positioned_column_tags().len()];
+    for (i, (line_range, column, ..)) in
+        cached_lines_in_order(opts, &lines_vec).filter(|(_, l)| l.1 != BLANK_ROW_COLUMN)
+    {
+        groups[column_tracker.position_for_column(*column) as usize].push((i, line_range, *column));
+    }
+
+    if let Some(sort_key) = opts.sort_key_regex()? {
+        for group in groups.iter_mut() {
+            sort_by_line_key(group, &sort_key,
+                             |(i, line_range, _)| &file_contents_vec[*i].contents()[(*line_range).clone()]);
+        }
+    }
+
+    // The separator and header of a group are written before its first printed
+    // line, so that groups without printed lines are omitted entirely, and no
+    // separator follows the last group.
+    let labels = if opts.header { column_tracker.positioned_column_labels() } else { Vec::new() };
+    let mut empty = true;
+    for (position, group) in groups.into_iter().enumerate() {
+        let mut group_empty = true;
+        for (i, line_range, column) in group {
+            let line = &file_contents_vec[i].contents()[line_range.clone()];
+            let stripped_line = column_tracker.tag_finder.strip_tag(line);
+            let shown_line = show_control_chars(&stripped_line, opts.show_control);
+            let line = &shown_line[..];
+            if opts.uniq && column_tracker.is_repeated_line(column, line) {
+                continue;
+            }
+            if !column_tracker.count_printed_line(column) {
+                continue;
+            }
+            if group_empty {
+                if !empty {
+                    writer.write_all(opts.group_separator.as_bytes())?;
+                    writer.write_all(line_ending)?;
+                }
+                if let Some(label) = labels.get(position) {
+                    writer.write_all(label)?;
+                    writer.write_all(line_ending)?;
+                }
+                group_empty = false;
+                empty = false;
+            }
+            let highlight_range = if highlight { column_tracker.tag_finder.tag_range_in(line) } else { None };
+            write_highlighted(writer, line, highlight_range)?;
+            writer.write_all(line_ending)?;
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Perform the unweave operation into columns, writing either to the specified
+/// writer, or to the output specified in the options.
+fn unweave_into_columns_with_output(opts: &UnweaveOptionsColumns, writer: OutputWriter) -> Result<u64> {
+    let to_stdout = writer.is_none() && opts.output.is_none();
+    let resolved = resolve_line_width_auto(opts, if to_stdout { terminal_width() } else { None })?;
+    let opts = resolved.as_ref().unwrap_or(opts);
+
+    let spilled = spill_stdin(opts)?;
+    let opts = spilled.as_ref().map_or(opts, |(o, _)| o);
+
+    let column_tracker = unweave_into_columns_with_tracker(opts, writer)?;
+
+    if opts.stats {
+        write_stats(&mut io::stderr().lock(), &column_tracker.stream_line_counts())?;
+    }
+
+    if opts.warn_invalid_utf8 && column_tracker.invalid_utf8_count > 0 {
+        write_invalid_utf8_summary(&mut io::stderr().lock(), column_tracker.invalid_utf8_count,
+                                   &column_tracker.invalid_utf8_first)?;
+    }
+
+    Ok(column_tracker.matched_line_count())
+}
+
+/// Perform the unweave operation into columns, returning the ColumnTracker
+/// with the final column information. The output being closed early (e.g.,
+/// when piping to "head") stops the operation without an error.
+fn unweave_into_columns_with_tracker<'a>(opts: &'a UnweaveOptionsColumns, writer: OutputWriter)
+    -> Result<ColumnTracker<'a>>
+{
+    let mut column_tracker = ColumnTracker::new(opts)?;
+
+    let result = if opts.allows_single_pass() {
+        unweave_into_columns_single_pass(opts, writer, &mut column_tracker)
+    } else {
+        match two_pass_mode(opts) {
+            UnweaveTwoPass::Cached =>
+                unweave_into_columns_two_pass_cached(opts, writer, &mut column_tracker),
+            UnweaveTwoPass::Reread =>
+                unweave_into_columns_two_pass_reread(opts, writer, &mut column_tracker),
+        }
+    };
+
+    match result {
+        Err(e) if !is_broken_pipe(&e) => Err(e),
+        _ => Ok(column_tracker),
+    }
+}
+
+/// If the line width is automatic, returns options that use the specified
+/// terminal width as the line width, or an undefined width if there is no
+/// terminal. Percentage column widths without a line width are resolved
+/// against the terminal width, and fail if there is no terminal, since they
+/// can't be calculated.
+fn resolve_line_width_auto(opts: &UnweaveOptionsColumns, terminal_width: Option<u32>)
+    -> Result<Option<UnweaveOptionsColumns>>
+{
+    let mut resolved = opts.clone();
+    resolved.width = match (opts.width, terminal_width) {
+        (UnweaveWidth::LineAuto, Some(w)) => UnweaveWidth::Line(w),
+        (UnweaveWidth::LineAuto, None) => UnweaveWidth::Undefined,
+        (UnweaveWidth::Percent(p, None), Some(w)) => UnweaveWidth::Percent(p, Some(w)),
+        (UnweaveWidth::Percent(_, None), None) => bail!(UnweaveError::PercentWithoutLineWidth),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(resolved))
+}
+
+/// If spilling is enabled and two passes are needed, copies a non-rereadable
+/// standard input to a temporary file. Returns options that refer to the
+/// temporary file, and use the "reread" two-pass mode if all inputs can now be
+/// reread, along with the SpilledInput, which must be kept alive while the
+/// options are used.
+fn spill_stdin(opts: &UnweaveOptionsColumns) -> Result<Option<(UnweaveOptionsColumns, SpilledInput)>> {
+    if !opts.spill_stdin || opts.allows_single_pass() {
+        return Ok(None);
+    }
+
+    let stdin = match opts.inputs.iter().find(|i| path_is_stdin(i)) {
+        Some(stdin) if !path_contents_can_be_reread(stdin) => stdin,
+        _ => return Ok(None),
+    };
+
+    let spilled = SpilledInput::from_path(stdin)
+        .context("Failed to copy standard input to a temporary file")?;
+
+    let mut spilled_opts = opts.clone();
+    for input in spilled_opts.inputs.iter_mut().filter(|i| path_is_stdin(i)) {
+        *input = spilled.path().to_path_buf();
+    }
+
+    if spilled_opts.inputs.iter().all(|i| path_contents_can_be_reread(i)) {
+        spilled_opts.two_pass = UnweaveTwoPass::Reread;
+    }
+
+    Ok(Some((spilled_opts, spilled)))
+}
+
+/// Returns the two-pass mode to use. The cached mode falls back to the reread
+/// mode if the combined size of the inputs exceeds the maximum cache size, as
+/// long as all inputs can be reread.
+fn two_pass_mode(opts: &UnweaveOptionsColumns) -> UnweaveTwoPass {
+    if opts.record_separator.is_some() || opts.sort_key.is_some() {
+        return UnweaveTwoPass::Cached;
+    }
+
+    match (opts.two_pass, opts.max_cache_bytes) {
+        (UnweaveTwoPass::Cached, Some(max)) => {
+            let size: u64 = opts.inputs.iter().map(|i| path_size(i)).sum();
+            if size > max && opts.inputs.iter().all(|i| path_contents_can_be_reread(i)) {
+                UnweaveTwoPass::Reread
+            } else {
+                UnweaveTwoPass::Cached
+            }
+        },
+        (two_pass, _) => two_pass,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use std::fs::{self};
+    use crate::{UnweaveMmap, UnweaveTwoPass, UnweaveOptionsTag, UnweaveLineEnding, UnweaveInterleave,
+                UnweaveBlank, UnweaveHighlight, UnweaveBinary, UnweaveRrFill,
+                UnweaveShowControl, UnweaveMaxLineAction};
+    use crate::util::test_writers::{FailingFlush, FlushRecorder};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    struct TestParams {
+        mmap: UnweaveMmap,
+        two_pass: UnweaveTwoPass,
+    }
+
+    const TEST_PARAMS: &[TestParams] = &[
+        TestParams{ mmap: UnweaveMmap::Allow, two_pass: UnweaveTwoPass::Cached },
+        TestParams{ mmap: UnweaveMmap::Allow, two_pass: UnweaveTwoPass::Reread },
+        TestParams{ mmap: UnweaveMmap::Disallow, two_pass: UnweaveTwoPass::Cached },
+        TestParams{ mmap: UnweaveMmap::Disallow, two_pass: UnweaveTwoPass::Reread },
+    ];
+
+    fn unweave_columns_simple_pattern_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: None,
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "     B:1\n",
+                        "A:2\n",
+                        "          C:1\n",
+                        "     B:2\n",
+                        "          C:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_simple_pattern() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_simple_pattern_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_separator_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1  |     |\n",
+                        "     |B:1  |\n",
+                        "A:2  |     |\n",
+                        "     |     |C:1\n",
+                        "     |B:2  |\n",
+                        "     |     |C:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_separator() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_separator_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_auto_width_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:11\nB:1111\nA:2\nZ:1\nC:1\nB:2\nC:222").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Undefined,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:11|      |\n",
+                        "    |B:1111|\n",
+                        "A:2 |      |\n",
+                        "    |      |C:1\n",
+                        "    |B:2   |\n",
+                        "    |      |C:222\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_auto_width() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_auto_width_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_line_width_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:11\nB:111\nA:2\nZ:1\nC:1\nB:2\nC:222").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Line(15),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:11|    |\n",
+                        "    |B:11|\n",
+                        "    |1   |\n",
+                        "A:2 |    |\n",
+                        "    |    |C:1\n",
+                        "    |B:2 |\n",
+                        "    |    |C:22\n",
+                        "    |    |2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_line_width() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_line_width_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_percent_width_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nC:1").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Percent(50, Some(20)),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1       |          |\n",
+                        "          |B:1       |\n",
+                        "A:2       |          |\n",
+                        "          |          |C:1\n").as_bytes());
+
+        // Columns with a width override keep their width
+        opts.column_width_overrides = vec![("B".to_string(), 4)];
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1       |    |\n",
+                        "          |B:1 |\n",
+                        "A:2       |    |\n",
+                        "          |    |C:1\n").as_bytes());
+
+        // Without a line width, the terminal width is used, if any
+        opts.width = UnweaveWidth::Percent(25, None);
+        let resolved = resolve_line_width_auto(&opts, Some(16)).unwrap().unwrap();
+        assert!(resolved.width == UnweaveWidth::Percent(25, Some(16)));
+        let resolved = resolve_line_width_auto(&opts, None);
+        assert!(matches!(resolved.err().unwrap().downcast_ref(),
+                         Some(UnweaveError::PercentWithoutLineWidth)));
+    }
+
+    #[test]
+    fn unweave_columns_percent_width() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_percent_width_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_line_width_multi_char_separator_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nC:222").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Line(13),
+            column_separator: Some("##".to_string()),
+            two_pass: test_params.two_pass,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        let output = fs::read_to_string(&output).unwrap();
+        assert!(output ==
+                concat!("A:1##   ##\n",
+                        "   ##B:1##\n",
+                        "   ##   ##C:2\n",
+                        "   ##   ##22\n"));
+        assert!(output.lines().map(|l| l.len()).max() == Some(13));
+    }
+
+    #[test]
+    fn unweave_columns_line_width_multi_char_separator() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_line_width_multi_char_separator_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_line_width_auto_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:11\nB:111\nA:2\nZ:1\nC:1\nB:2\nC:222").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::LineAuto,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        let resolved = resolve_line_width_auto(&opts, Some(12)).unwrap().unwrap();
+        assert!(resolved.width == UnweaveWidth::Line(12));
+        unweave_into_columns(&resolved).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1|   |\n",
+                        "1  |   |\n",
+                        "   |B:1|\n",
+                        "   |11 |\n",
+                        "A:2|   |\n",
+                        "   |   |C:1\n",
+                        "   |B:2|\n",
+                        "   |   |C:2\n",
+                        "   |   |22\n").as_bytes());
+
+        let resolved = resolve_line_width_auto(&opts, None).unwrap().unwrap();
+        assert!(resolved.width == UnweaveWidth::Undefined);
+
+        // Output to a file is not a terminal
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:11|     |\n",
+                        "    |B:111|\n",
+                        "A:2 |     |\n",
+                        "    |     |C:1\n",
+                        "    |B:2  |\n",
+                        "    |     |C:222\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_line_width_auto() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_line_width_auto_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_complex_regex_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"1ACx\n2BAy\n3AC\nZAC\n4CBz\n5BAz\n6CCy").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new(r"[1-6](A|B|C)(?:A|B|C)"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Undefined,
+            column_separator: None,
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("1ACx\n",
+                        "    2BAy\n",
+                        "3AC\n",
+                        "        4CBz\n",
+                        "    5BAz\n",
+                        "        6CCy\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_complex_regex() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_complex_regex_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_multiple_unicode_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "άλφα1\nβήτα1\nδέλτα1\nβήτα2\nγάμμα1\n".as_bytes()).unwrap();
+        fs::write(&inputs[1], "γάμμα2\nάλφα2\n".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("άλφα|βήτα|γάμμα"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Undefined,
+            column_separator: None,
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("άλφα1\n",
+                        "     βήτα1\n",
+                        "     βήτα2\n",
+                        "          γάμμα1\n",
+                        "          γάμμα2\n",
+                        "άλφα2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_multiple_unicode() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_multiple_unicode_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_wrapping_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "άλφα-1\nβήτα1\nδέλτα1\nβήτα-22\nγάμμα1\n".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("άλφα|βήτα|γάμμα"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: None,
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("άλφα-\n",
+                        "1\n",
+                        "     βήτα1\n",
+                        "     βήτα-\n",
+                        "     22\n",
+                        "          γάμμα\n",
+                        "          1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_wrapping() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_wrapping_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_wrapping_with_separator_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "άλφα-1\nβήτα1\nδέλτα1\nβήτα-1234567\nγάμμα1\n".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("άλφα|βήτα|γάμμα"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("##".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("άλφα-##     ##\n",
+                        "1    ##     ##\n",
+                        "     ##βήτα1##\n",
+                        "     ##βήτα-##\n",
+                        "     ##12345##\n",
+                        "     ##67   ##\n",
+                        "     ##     ##γάμμα\n",
+                        "     ##     ##1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_wrapping_with_separator() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_wrapping_with_separator_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_unicode_fill_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "α:Α\nβ:ΒΒ\nγ:ΓΓΓ".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β|γ"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("α:Α  |     |\n",
+                        "     |β:ΒΒ |\n",
+                        "     |     |γ:ΓΓΓ\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_unicode_fill() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_unicode_fill_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_invalid_unicode_fill_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"\xce\xb1\xce\x79\n\xce\xb2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                b"\xce\xb1\xce\x79  |\n     |\xce\xb2\n");
+    }
+
+    #[test]
+    fn unweave_columns_invalid_unicode_fill() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_invalid_unicode_fill_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_invalid_unicode_wrap_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"\xce\xb1\xce\x79\n\xce\xb2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(1),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                b"\xce\xb1|\n\xce|\n\x79|\n |\xce\xb2\n");
+    }
+
+    #[test]
+    fn unweave_columns_invalid_unicode_wrap() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_invalid_unicode_wrap_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_warn_invalid_utf8_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"\xce\xb1\xce\x79\n\xce\xb2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            two_pass: test_params.two_pass,
+            warn_invalid_utf8: true,
+            ..Default::default()
+        };
+
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
+
+        assert!(column_tracker.invalid_utf8_count == 1);
+        assert!(column_tracker.invalid_utf8_first == vec![(1, 2)]);
+    }
+
+    #[test]
+    fn unweave_columns_warn_invalid_utf8() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_warn_invalid_utf8_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_invalid_unicode_not_printable_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"\xce\xb1\xce\x13\n\xce\xb2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                b"\xce\xb1\xce\x13   |\n     |\xce\xb2\n");
+    }
+
+    #[test]
+    fn unweave_columns_invalid_unicode_not_printable_wrap() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_invalid_unicode_not_printable_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_invalid_unicode_end_of_line_fill_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"\xce\xb1\xce\n\xce\xb2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                b"\xce\xb1\xce   |\n     |\xce\xb2\n");
+    }
+
+    #[test]
+    fn unweave_columns_invalid_unicode_end_of_line_fill() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_invalid_unicode_end_of_line_fill_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_invalid_unicode_end_of_line_wrap_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"\xce\xb1\xce\n\xce\xb2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("α|β"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(1),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                b"\xce\xb1|\n\xce|\n |\xce\xb2\n");
+    }
+
+    #[test]
+    fn unweave_columns_invalid_unicode_end_of_line_wrap() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_invalid_unicode_end_of_line_wrap_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_tab_fill_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "αb\tc\nd".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("b|d"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(10),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                "αb      c |\n          |d\n".as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_tab_fill() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_tab_fill_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_tab_wrap_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "αb\tc\nd".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("b|d"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                "αb   |\n   c |\n     |d\n".as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_tab_wrap() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_tab_wrap_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_tab_no_expand_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "αb\tc\nd".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("b|d"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::NoExpand,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                "αb\tc  |\n     |d\n".as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_tab_no_expand() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_tab_no_expand_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_tab_origin_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB\ty").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some(" | ".to_string()),
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(4),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        // The tab stop is at 4 cells from the start of the second column
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1 |\n",
+                        "    | B   y\n"));
+
+        let opts = UnweaveOptionsColumns { tab_origin: UnweaveTabOrigin::Line, ..opts };
+
+        unweave_into_columns(&opts).unwrap();
+
+        // The tab stop is at 8 cells from the start of the line
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1 |\n",
+                        "    | B y\n"));
+    }
+
+    #[test]
+    fn unweave_columns_tab_origin() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_tab_origin_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_ignore_case_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\na:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag { ignore_case: true, ..UnweaveOptionsTag::new("a|b") },
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Undefined,
+            column_separator: None,
+            two_pass: test_params.two_pass,
+            tab: UnweaveTab::Expand(8),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "   B:1\n",
+                        "a:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_ignore_case() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_ignore_case_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_unmatched_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nZ:11\nB:1\nA:2\nZ:2").unwrap();
+
+        for (unmatched, expected) in &[
+            (UnweaveUnmatched::First,
+             concat!("    |A:1|\n",
+                     "Z:11|   |\n",
+                     "    |   |B:1\n",
+                     "    |A:2|\n",
+                     "Z:2 |   |\n")),
+            (UnweaveUnmatched::Last,
+             concat!("A:1|   |\n",
+                     "   |   |Z:11\n",
+                     "   |B:1|\n",
+                     "A:2|   |\n",
+                     "   |   |Z:2\n")),
+        ] {
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: test_params.mmap,
+                column_separator: Some("|".to_string()),
+                two_pass: test_params.two_pass,
+                unmatched: *unmatched,
+                ..Default::default()
+            };
+
+            unweave_into_columns(&opts).unwrap();
+
+            assert!(fs::read(&output).unwrap() == expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn unweave_columns_unmatched() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_unmatched_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_header_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "x:A:1\nx:βήτα:1\nx:A:2\nx:C:1".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new(r"x:(\w+)"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A    |βήτα |C\n",
+                        "x:A:1|     |\n",
+                        "     |x:βήτ|\n",
+                        "     |α:1  |\n",
+                        "x:A:2|     |\n",
+                        "     |     |x:C:1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_header() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_header_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_footer_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nA:3\nC:1").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(3),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            footer: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A  |B  |C\n",
+                        "A:1|   |\n",
+                        "   |B:1|\n",
+                        "A:2|   |\n",
+                        "A:3|   |\n",
+                        "   |   |C:1\n",
+                        "3  |1  |1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_footer() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_footer_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_column_labels_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "x:A:1\nx:βήτα:1\nx:A:2\nx:C:1".as_bytes()).unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new(r"x:(\w+)"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            column_labels: UnweaveColumnLabels::Index,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("0    |1    |2\n",
+                        "x:A:1|     |\n",
+                        "     |x:βήτ|\n",
+                        "     |α:1  |\n",
+                        "x:A:2|     |\n",
+                        "     |     |x:C:1\n").as_bytes());
+
+        opts.column_labels = UnweaveColumnLabels::None;
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("x:A:1|     |\n",
+                        "     |x:βήτ|\n",
+                        "     |α:1  |\n",
+                        "x:A:2|     |\n",
+                        "     |     |x:C:1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_column_labels() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_column_labels_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_truncate_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "Aabcdef\nBbc\nAabcd\nBάλφαβήτα".as_bytes()).unwrap();
+
+        for (marker, width, expected) in &[
+            ("…", 5,
+             concat!("Aabc…|\n",
+                     "     |Bbc\n",
+                     "Aabcd|\n",
+                     "     |Bάλφ…\n")),
+            ("", 5,
+             concat!("Aabcd|\n",
+                     "     |Bbc\n",
+                     "Aabcd|\n",
+                     "     |Bάλφα\n")),
+            ("...", 2,
+             concat!("..|\n",
+                     "  |..\n",
+                     "..|\n",
+                     "  |..\n")),
+        ] {
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: test_params.mmap,
+                width: UnweaveWidth::Column(*width),
+                column_separator: Some("|".to_string()),
+                two_pass: test_params.two_pass,
+                truncate_marker: Some(marker.to_string()),
+                ..Default::default()
+            };
+
+            unweave_into_columns(&opts).unwrap();
+
+            assert!(fs::read(&output).unwrap() == expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn unweave_columns_truncate() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_truncate_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_align_right_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "A:1\nB:1\nA:2222\nB:βήτα12".as_bytes()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            two_pass: test_params.two_pass,
+            align: UnweaveAlign::Right,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("  A:1\n",
+                        "       B:1\n",
+                        "A:222\n",
+                        "    2\n",
+                        "     B:βήτ\n",
+                        "       α12\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_align_right() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_align_right_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_gzip_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1.gz"),
+                          tmpdir.path().join("input2"),
+                          tmpdir.path().join("input3")];
+        let output = tmpdir.path().join("output");
+        for (input, contents) in inputs.iter().zip(&[&b"A:1\nB:1\n"[..], b"A:2\nZ:1\n"]) {
+            let mut encoder = GzEncoder::new(fs::File::create(input).unwrap(),
+                                             Compression::default());
+            encoder.write_all(contents).unwrap();
+            encoder.finish().unwrap();
+        }
+        fs::write(&inputs[2], b"B:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(4),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1 |\n",
+                        "    |B:1\n",
+                        "A:2 |\n",
+                        "    |B:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_gzip() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_gzip_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_char_width_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], "A:日本語\nB:x\nA:ab".as_bytes()).unwrap();
+
+        for (width, char_width, expected) in &[
+            (UnweaveWidth::Undefined, UnweaveCharWidth::Display,
+             concat!("A:日本語|\n",
+                     "        |B:x\n",
+                     "A:ab    |\n")),
+            (UnweaveWidth::Undefined, UnweaveCharWidth::Mono,
+             concat!("A:日本語|\n",
+                     "     |B:x\n",
+                     "A:ab |\n")),
+            (UnweaveWidth::Column(5), UnweaveCharWidth::Display,
+             concat!("A:日 |\n",
+                     "本語 |\n",
+                     "     |B:x\n",
+                     "A:ab |\n")),
+        ] {
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: test_params.mmap,
+                width: *width,
+                column_separator: Some("|".to_string()),
+                two_pass: test_params.two_pass,
+                char_width: *char_width,
+                ..Default::default()
+            };
+
+            unweave_into_columns(&opts).unwrap();
+
+            assert!(fs::read(&output).unwrap() == expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn unweave_columns_char_width() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_char_width_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_show_control_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:\x01\nB:1\nA:22").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            show_control: UnweaveShowControl::Caret,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        // The caret notation occupies two cells, making the first column wider
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:^A|\n",
+                        "    |B:1\n",
+                        "A:22|\n").as_bytes());
+
+        opts.show_control = UnweaveShowControl::Raw;
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:\x01  |\n",
+                        "    |B:1\n",
+                        "A:22|\n").as_bytes());
+
+        // Single pass
+        opts.show_control = UnweaveShowControl::Caret;
+        opts.column_separator = None;
+        opts.width = UnweaveWidth::Column(3);
+        opts.truncate_marker = Some("".to_string());
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:^\n",
+                        "   B:1\n",
+                        "A:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_show_control() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_show_control_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_split_on_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1;B:1\nA:2").unwrap();
+        fs::write(&inputs[1], b"B:3;;A:3").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs: inputs[..1].to_vec(),
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            split_on: Some(";".to_string()),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:1\n",
+                        "A:2|\n").as_bytes());
+
+        // Parallel scanning of inputs, with an empty part that isn't matched
+        opts.inputs = inputs.clone();
+        opts.jobs = 2;
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:1\n",
+                        "A:2|\n",
+                        "   |B:3\n",
+                        "A:3|\n").as_bytes());
+
+        // Single pass
+        opts.inputs = inputs[..1].to_vec();
+        opts.jobs = 1;
+        opts.column_separator = None;
+        opts.width = UnweaveWidth::Column(3);
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "   B:1\n",
+                        "A:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_split_on() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_split_on_with_params(test_params);
+        }
+    }
+
+    fn unweave_grouped_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let input = tmpdir.path().join("input");
+        let output = tmpdir.path().join("output");
+        fs::write(&input, b"A:1\nB:1\nA:2\nC:1\nX\nB:2\nC:2").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs: vec![input],
+            mmap: test_params.mmap,
+            two_pass: test_params.two_pass,
+            ..Default::default()
+        };
+
+        assert!(unweave_into_grouped(&opts).unwrap() == 6);
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n",
+                        "\n",
+                        "B:1\n",
+                        "B:2\n",
+                        "\n",
+                        "C:1\n",
+                        "C:2\n").as_bytes());
+
+        opts.group_separator = "--".to_string();
+        opts.tag.strip = true;
+        unweave_into_grouped(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!(":1\n",
+                        ":2\n",
+                        "--\n",
+                        ":1\n",
+                        ":2\n",
+                        "--\n",
+                        ":1\n",
+                        ":2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_grouped() {
+        for test_params in TEST_PARAMS {
+            unweave_grouped_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_layout_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        let layout = tmpdir.path().join("layout");
+        fs::write(&inputs[0], b"A:1\nB:22\nA:333").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some(" | ".to_string()),
+            two_pass: test_params.two_pass,
+            layout: Some(layout.clone()),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&layout).unwrap() ==
+                concat!("INDEX\tTAG\tSTART\tWIDTH\n",
+                        "0\tA\t0\t5\n",
+                        "1\tB\t8\t4\n").as_bytes());
+
+        // Single pass
+        opts.column_separator = None;
+        opts.width = UnweaveWidth::Column(6);
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&layout).unwrap() ==
+                concat!("INDEX\tTAG\tSTART\tWIDTH\n",
+                        "0\tA\t0\t6\n",
+                        "1\tB\t6\t6\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_layout() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_layout_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_sort_key_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:10\nB:2\nA:9\nX\nB:1").unwrap();
+        fs::write(&inputs[1], b"A:1\nB:0").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs: inputs[..1].to_vec(),
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            sort_key: Some(":(\\d+)".to_string()),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:9 |\n",
+                        "    |B:1\n",
+                        "A:10|\n",
+                        "    |B:2\n").as_bytes());
+
+        // Lines move between inputs, and in grouped mode within their group
+        opts.inputs = inputs.clone();
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1 |\n",
+                        "    |B:0\n",
+                        "A:9 |\n",
+                        "    |B:1\n",
+                        "A:10|\n",
+                        "    |B:2\n").as_bytes());
+
+        unweave_into_grouped(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "A:9\n",
+                        "A:10\n",
+                        "\n",
+                        "B:0\n",
+                        "B:1\n",
+                        "B:2\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_sort_key() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_sort_key_with_params(test_params);
+        }
+    }
+
+    fn unweave_grouped_separator_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let input = tmpdir.path().join("input");
+        let output = tmpdir.path().join("output");
+        fs::write(&input, b"A:1\nB:1\nC:1\nA:2\nC:1\nB:2").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs: vec![input],
+            mmap: test_params.mmap,
+            two_pass: test_params.two_pass,
+            group_separator: "---".to_string(),
+            ..Default::default()
+        };
+
+        unweave_into_grouped(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n",
+                        "---\n",
+                        "B:1\n",
+                        "B:2\n",
+                        "---\n",
+                        "C:1\n",
+                        "C:1\n").as_bytes());
+
+        // A header with the tag of each group
+        opts.header = true;
+        opts.tag.strip = true;
+        unweave_into_grouped(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A\n",
+                        ":1\n",
+                        ":2\n",
+                        "---\n",
+                        "B\n",
+                        ":1\n",
+                        ":2\n",
+                        "---\n",
+                        "C\n",
+                        ":1\n",
+                        ":1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_grouped_separator() {
+        for test_params in TEST_PARAMS {
+            unweave_grouped_separator_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_max_line_bytes_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        let mut data = b"A:1\nB:".to_vec();
+        data.extend(std::iter::repeat_n(b'2', 100_000));
+        data.extend_from_slice(b"\nA:3");
+        fs::write(&inputs[0], &data).unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            max_line_bytes: Some(5),
+            max_line_action: UnweaveMaxLineAction::Truncate,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:222\n",
+                        "A:3|\n").as_bytes());
+
+        opts.max_line_action = UnweaveMaxLineAction::Error;
+        let err = unweave_into_columns(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref::<UnweaveError>(), Some(UnweaveError::LineTooLong(_, 5))));
+    }
+
+    #[test]
+    fn unweave_columns_max_line_bytes() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_max_line_bytes_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_line_ending_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\r\nB:123\nA:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(3),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            line_ending: UnweaveLineEnding::Crlf,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A  |B\r\n",
+                        "A:1|\r\n",
+                        "   |B:1\r\n",
+                        "   |23\r\n",
+                        "A:2|\r\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_line_ending() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_line_ending_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_line_ending_detect_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\rB:123\rA:2\r").unwrap();
+        fs::write(&inputs[1], b"B:2\r\nA:3").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            line_ending_detect: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1  |\n",
+                        "     |B:123\n",
+                        "A:2  |\n",
+                        "     |B:2\n",
+                        "A:3  |\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_line_ending_detect() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_line_ending_detect_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_null_data_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\0B:123\0A:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            null_data: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1  |\0",
+                        "     |B:123\0",
+                        "A:2  |\0").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_null_data() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_null_data_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_record_separator_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nfoo\n\nB:1\n\nA:2\nbar\n").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("^(A|B)"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(8),
+            two_pass: test_params.two_pass,
+            record_separator: Some("\n\n".to_string()),
+            ..Default::default()
+        };
+
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
+
+        assert!(column_tracker.stream_line_counts() ==
+                vec![(b"A".to_vec(), 2), (b"B".to_vec(), 1)]);
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "foo\n",
+                        "        B:1\n",
+                        "A:2\n",
+                        "bar\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_record_separator() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_record_separator_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_stats_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            two_pass: test_params.two_pass,
+            unmatched: UnweaveUnmatched::Last,
+            stats: true,
+            ..Default::default()
+        };
+
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
+
+        assert!(column_tracker.stream_line_counts() ==
+                vec![(b"A".to_vec(), 2), (b"B".to_vec(), 1), (b"(unmatched)".to_vec(), 1)]);
+    }
+
+    #[test]
+    fn unweave_columns_stats() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_stats_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_column_map_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        let column_map = tmpdir.path().join("column-map");
+        fs::write(&inputs[0], b"A:1\nC:1\nB:1\nD:1\nA:2").unwrap();
+        fs::write(&column_map, b"A 2\nB 0\n\nX 1\n").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C|D"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(4),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            column_map: Some(column_map),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("B   |X   |A   |C   |D\n",
+                        "    |    |A:1 |    |\n",
+                        "    |    |    |C:1 |\n",
+                        "B:1 |    |    |    |\n",
+                        "    |    |    |    |D:1\n",
+                        "    |    |A:2 |    |\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_column_map() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_column_map_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn unweave_columns_invalid_column_map() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let column_map = tmpdir.path().join("column-map");
+        fs::write(&column_map, b"A 2\nB\n").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            column_map: Some(column_map),
+            ..Default::default()
+        };
+
+        assert!(ColumnTracker::new(&opts).is_err());
+    }
+
+    fn unweave_columns_tag_replace_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"thread-1 a\nmain b\nthread-2 c\nworker-3 d").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag {
+                replace: vec![("-[0-9]+$".to_string(), "".to_string()),
+                              ("^worker$".to_string(), "thread".to_string())],
+                ..UnweaveOptionsTag::new("^[^ ]+")
+            },
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(10),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("thread    |main\n",
+                        "thread-1 a|\n",
+                        "          |main b\n",
+                        "thread-2 c|\n",
+                        "worker-3 d|\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_columns_tag_replace() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_tag_replace_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_jobs_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs: Vec<_> = (0..5).map(|i| tmpdir.path().join(format!("input{}", i))).collect();
+        for (i, input) in inputs.iter().enumerate() {
+            let contents: String = (0..100)
+                .map(|n| format!("{}:{}{}\n", ["A", "B", "C", "Z"][(n * (i + 1)) % 4], i, "-".repeat(n % 7)))
+                .collect();
+            fs::write(input, contents).unwrap();
+        }
+
+        let mut outputs = Vec::new();
+
+        for jobs in &[1, 3] {
+            let output = tmpdir.path().join(format!("output{}", jobs));
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B|C"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: test_params.mmap,
+                column_separator: Some("|".to_string()),
+                two_pass: test_params.two_pass,
+                unmatched: UnweaveUnmatched::Last,
+                header: true,
+                line_numbers: UnweaveLineNumbers::Blank,
+                jobs: *jobs,
+                ..Default::default()
+            };
+
+            unweave_into_columns(&opts).unwrap();
+
+            outputs.push(fs::read(&output).unwrap());
+        }
+
+        assert!(!outputs[0].is_empty());
+        assert!(outputs[0] == outputs[1]);
+    }
+
+    #[test]
+    fn unweave_columns_jobs() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_jobs_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn column_printer_flush_error() {
+        let opts = UnweaveOptionsColumns::default();
+
+        let mut column_printer = ColumnPrinter::with_writer(&opts, Box::new(FailingFlush));
+        column_printer.set_column_widths(&[4]);
+        column_printer.print_in_column(b"A:1", 0, None, 1).unwrap();
+
+        assert!(column_printer.flush().is_err());
+    }
+
+    /// A Write sink whose reader has gone away.
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    fn unweave_columns_broken_pipe_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let lines: Vec<String> = (0..10000).map(|i| format!("A:{}\nB:{}\n", i, i)).collect();
+        fs::write(&inputs[0], lines.concat()).unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            two_pass: test_params.two_pass,
+            ..Default::default()
+        };
+
+        assert!(unweave_into_columns_with_writer(&opts, Box::new(BrokenPipe)).is_ok());
+    }
+
+    #[test]
+    fn unweave_columns_broken_pipe() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_broken_pipe_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn column_printer_line_buffered() {
+        let opts = UnweaveOptionsColumns {
+            column_separator: Some("|".to_string()),
+            line_buffered: true,
+            ..Default::default()
+        };
+        let recorder = FlushRecorder::default();
+
+        {
+            let mut column_printer = ColumnPrinter::with_writer(&opts, Box::new(recorder.clone()));
+            column_printer.set_column_widths(&[3, 3]);
+            column_printer.print_row(&[b"A".to_vec(), b"B".to_vec()]).unwrap();
+            column_printer.print_in_column(b"A:12", 0, None, 1).unwrap();
+            column_printer.print_in_column(b"B:1", 1, None, 2).unwrap();
+        }
+
+        let data = recorder.data.borrow();
+        assert!(*data ==
+                concat!("A  |B\n",
+                        "A:1|\n",
+                        "2  |\n",
+                        "   |B:1\n").as_bytes());
+
+        let row_ends: Vec<_> = data.iter().enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .map(|(i, _)| i + 1)
+            .collect();
+        assert!(*recorder.flushes.borrow() == row_ends);
+    }
+
+    #[test]
+    fn unweave_columns_max_cache_bytes() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
+        fs::write(&inputs[1], b"B:2\nC:1").unwrap();
+
+        let mut outputs = Vec::new();
+
+        for max_cache_bytes in &[None, Some(1000), Some(10)] {
+            let output = tmpdir.path().join("output");
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B|C"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                column_separator: Some("|".to_string()),
+                max_cache_bytes: *max_cache_bytes,
+                ..Default::default()
+            };
+
+            let expected_two_pass = match max_cache_bytes {
+                Some(10) => UnweaveTwoPass::Reread,
+                _ => UnweaveTwoPass::Cached,
+            };
+            assert!(two_pass_mode(&opts) == expected_two_pass);
+
+            unweave_into_columns(&opts).unwrap();
+            outputs.push(fs::read(&output).unwrap());
+        }
+
+        assert!(outputs[0] == outputs[1]);
+        assert!(outputs[0] == outputs[2]);
+    }
+
+    fn unweave_columns_fill_char_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:22").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            width: UnweaveWidth::Column(5),
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            fill_char: ".".to_string(),
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1..|.....\n",
+                        ".....|B:1..\n",
+                        "A:22.|.....\n"));
+    }
+
+    #[test]
+    fn unweave_columns_fill_char() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_fill_char_with_params(test_params);
         }
     }
 
-    Ok(())
-}
+    fn unweave_columns_outer_separator_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:22").unwrap();
 
-/// Perform the unweave operation into columns using two passes, using cached
-/// data from the first pass (including loaded file contents), to speed up
-/// the second pass.
-fn unweave_into_columns_two_pass_cached(opts: &UnweaveOptionsColumns) -> Result<()> {
-    let mut column_tracker = ColumnTracker::new(&opts)?;
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            outer_separator: true,
+            ..Default::default()
+        };
 
-    let mut file_contents_vec = Vec::new();
-    let mut lines_vec = Vec::new();
+        unweave_into_columns(&opts).unwrap();
 
-    // First pass gets file contents and lines/column info
-    for input in &opts.inputs {
-        let file_contents = FileContents::new(input, opts.mmap)?;
-        let mut lines = Vec::new();
-        let mut cur = 0;
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("|A   |B  |\n",
+                        "|A:1 |   |\n",
+                        "|    |B:1|\n",
+                        "|A:22|   |\n"));
+    }
 
-        for line in SliceFullLines::new(file_contents.contents()) {
-            let trimmed_line = trim_newline(line);
+    #[test]
+    fn unweave_columns_outer_separator() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_outer_separator_with_params(test_params);
+        }
+    }
 
-            match column_tracker.process_line(trimmed_line) {
-                Some((column, grapheme_count)) => lines.push((cur..cur+trimmed_line.len(), column, grapheme_count)),
-                None => {},
-            }
+    fn unweave_columns_outer_separator_no_columns_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"Z\n").unwrap();
 
-            cur += line.len();
-        }
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            outer_separator: true,
+            header: true,
+            ..Default::default()
+        };
 
-        file_contents_vec.push(file_contents);
-        lines_vec.push(lines);
+        unweave_into_columns(&opts).unwrap();
+        assert!(fs::read(&output).unwrap().is_empty());
+
+        opts.header = false;
+        opts.footer = true;
+        unweave_into_columns(&opts).unwrap();
+        assert!(fs::read(&output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unweave_columns_outer_separator_no_columns() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_outer_separator_no_columns_with_params(test_params);
+        }
     }
 
-    let mut column_printer = ColumnPrinter::new(&opts)?;
-    column_printer.set_column_widths(column_tracker.final_column_widths());
+    fn unweave_columns_sparse_separators_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nC:1").unwrap();
 
-    // Second pass, which now has all the line and column information, prints
-    // out the data.
-    for (file_contents, lines) in file_contents_vec.iter().zip(lines_vec.iter()) {
-        let contents = file_contents.contents();
-        for (line_range, col, grapheme_count) in lines {
-            column_printer.print_in_column(&contents[line_range.clone()], *col, *grapheme_count)?;
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1|   |\n",
+                        "   |B:1|\n",
+                        "   |   |C:1\n"));
+
+        let opts = UnweaveOptionsColumns { sparse_separators: true, ..opts };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:1|\n",
+                        "       |C:1\n"));
+
+        let opts = UnweaveOptionsColumns { outer_separator: true, header: true, ..opts };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("|A  |B  |C  |\n",
+                        "|A:1|\n",
+                        "    |B:1|\n",
+                        "        |C:1|\n"));
+    }
+
+    #[test]
+    fn unweave_columns_sparse_separators() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_sparse_separators_with_params(test_params);
         }
     }
 
-    Ok(())
-}
+    fn unweave_columns_split_lines_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nB:2\nA:3").unwrap();
 
-/// Perform the unweave operation into columns using two passes, maintaining
-/// only very limited information between passes, requiring a reread
-/// of the data during the second pass.
-fn unweave_into_columns_two_pass_reread(opts: &UnweaveOptionsColumns) -> Result<()> {
-    let mut column_tracker = ColumnTracker::new(&opts)?;
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            split_lines: Some(2),
+            ..Default::default()
+        };
 
-    // First pass populates column info
-    for input in &opts.inputs {
-        let mut file_lines = FileLines::new(input, opts.mmap)?;
-        while let Some(line) = file_lines.next() {
-            column_tracker.process_line(line);
+        unweave_into_columns(&opts).unwrap();
+
+        let chunk = |n| fs::read_to_string(tmpdir.path().join(format!("output.{:03}", n))).unwrap();
+        assert!(chunk(1) == concat!("A  |B\n",
+                                    "A:1|\n",
+                                    "   |B:1\n"));
+        assert!(chunk(2) == concat!("A  |B\n",
+                                    "A:2|\n",
+                                    "   |B:2\n"));
+        assert!(chunk(3) == concat!("A  |B\n",
+                                    "A:3|\n"));
+        assert!(!output.exists());
+        assert!(!tmpdir.path().join("output.004").exists());
+    }
+
+    #[test]
+    fn unweave_columns_split_lines() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_split_lines_with_params(test_params);
         }
     }
 
-    let mut column_printer = ColumnPrinter::new(&opts)?;
-    column_printer.set_column_widths(column_tracker.final_column_widths());
+    fn unweave_columns_uniq_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nA:1\nB:1\nA:1\nA:2\nA:1").unwrap();
 
-    // Second pass prints the columns
-    for input in &opts.inputs {
-        let mut file_lines = FileLines::new(input, opts.mmap)?;
-        while let Some(line) = file_lines.next() {
-            match column_tracker.process_line(line) {
-                Some((column, grapheme_count)) =>
-                    column_printer.print_in_column(&line, column, grapheme_count)?,
-                None => continue,
-            }
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            uniq: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:1\n",
+                        "A:2|\n",
+                        "A:1|\n"));
+    }
+
+    #[test]
+    fn unweave_columns_uniq() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_uniq_with_params(test_params);
         }
     }
 
-    Ok(())
-}
+    fn unweave_columns_strip_tag_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A: one\nB: two\nA: three\nC\n").unwrap();
 
-/// Perform the unweave operation into multiple columns, one column per matched stream.
-pub(crate) fn unweave_into_columns(opts: &UnweaveOptionsColumns) -> Result<()> {
-    if opts.column_separator.is_none() && opts.width.is_column() {
-        return unweave_into_columns_single_pass(&opts);
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag { strip: true, ..UnweaveOptionsTag::new(r"^\w+: ") },
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            unmatched: UnweaveUnmatched::Last,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("one  |   |\n",
+                        "     |two|\n",
+                        "three|   |\n",
+                        "     |   |C\n"));
     }
 
-    match opts.two_pass {
-        UnweaveTwoPass::Cached => unweave_into_columns_two_pass_cached(&opts),
-        UnweaveTwoPass::Reread => unweave_into_columns_two_pass_reread(&opts),
+    #[test]
+    fn unweave_columns_strip_tag() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_strip_tag_with_params(test_params);
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempdir::TempDir;
-    use std::fs::{self};
-    use crate::{UnweaveMmap, UnweaveTwoPass};
+    fn unweave_columns_highlight_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"x A:1\nB:22\n").unwrap();
 
-    struct TestParams {
-        mmap: UnweaveMmap,
-        two_pass: UnweaveTwoPass,
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("[AB]"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            highlight: UnweaveHighlight::Always,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("x \x1b[1;7mA\x1b[0m:1|\n",
+                        "     |\x1b[1;7mB\x1b[0m:22\n"));
+
+        // Only the chunk of a wrapped line containing the tag is highlighted
+        let opts = UnweaveOptionsColumns { width: UnweaveWidth::Column(3), ..opts };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("x \x1b[1;7mA\x1b[0m|\n",
+                        ":1 |\n",
+                        "   |\x1b[1;7mB\x1b[0m:2\n",
+                        "   |2\n"));
     }
 
-    const TEST_PARAMS: &[TestParams] = &[
-        TestParams{ mmap: UnweaveMmap::Allow, two_pass: UnweaveTwoPass::Cached },
-        TestParams{ mmap: UnweaveMmap::Allow, two_pass: UnweaveTwoPass::Reread },
-        TestParams{ mmap: UnweaveMmap::Disallow, two_pass: UnweaveTwoPass::Cached },
-        TestParams{ mmap: UnweaveMmap::Disallow, two_pass: UnweaveTwoPass::Reread },
-    ];
+    #[test]
+    fn unweave_columns_highlight() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_highlight_with_params(test_params);
+        }
+    }
 
-    fn unweave_columns_simple_pattern_with_params(test_params: &TestParams) {
+    fn unweave_columns_per_column_separators_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2").unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nC:1").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "A|B|C".to_string(),
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
-            column_separator: None,
+            column_separator: Some("|,||".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            header: true,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
-        assert!(fs::read(&output).unwrap() ==
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A  |B  ||C\n",
+                        "A:1|   ||\n",
+                        "   |B:1||\n",
+                        "   |   ||C:1\n"));
+    }
+
+    #[test]
+    fn unweave_columns_per_column_separators() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_per_column_separators_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn split_column_separators_lists() {
+        assert!(split_column_separators("|") == ["|"]);
+        assert!(split_column_separators("|,||") == ["|", "||"]);
+        assert!(split_column_separators(",") == [","]);
+        assert!(split_column_separators("") == [""]);
+    }
+
+    fn unweave_columns_interleave_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
+        fs::write(&inputs[1], b"C:1\nZ:1\nC:2\nC:3").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            line_numbers: UnweaveLineNumbers::Blank,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("1 A:1|   |\n",
+                        "2    |B:1|\n",
+                        "3 A:2|   |\n",
+                        "4    |   |C:1\n",
+                        "6    |   |C:2\n",
+                        "7    |   |C:3\n"));
+
+        opts.interleave = UnweaveInterleave::RoundRobin;
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("1 A:1|   |\n",
+                        "2    |C:1|\n",
+                        "3    |   |B:1\n",
+                        "5 A:2|   |\n",
+                        "6    |C:2|\n",
+                        "7    |C:3|\n"));
+
+        // Single pass
+        opts.column_separator = None;
+        opts.width = UnweaveWidth::Column(3);
+        opts.line_numbers = UnweaveLineNumbers::Disabled;
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
                 concat!("A:1\n",
-                        "     B:1\n",
+                        "   C:1\n",
+                        "      B:1\n",
                         "A:2\n",
-                        "          C:1\n",
-                        "     B:2\n",
-                        "          C:2\n").as_bytes());
+                        "   C:2\n",
+                        "   C:3\n"));
+    }
+
+    #[test]
+    fn unweave_columns_interleave() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_interleave_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_rr_fill_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nA:2").unwrap();
+        fs::write(&inputs[1], b"B:1\nZ:1\nB:2").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            interleave: UnweaveInterleave::RoundRobin,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:1\n",
+                        "A:2|\n",
+                        "   |B:2\n"));
+
+        // The exhausted first input gets an empty row in its turn
+        opts.rr_fill = UnweaveRrFill::Pad;
+        let expected = concat!("A:1|\n",
+                               "   |B:1\n",
+                               "A:2|\n",
+                               "   |\n",
+                               "   |B:2\n");
+        unweave_into_columns(&opts).unwrap();
+        assert!(fs::read_to_string(&output).unwrap() == expected);
+
+        opts.per_input_columns = true;
+        unweave_into_columns(&opts).unwrap();
+        assert!(fs::read_to_string(&output).unwrap() == expected);
+
+        // Single pass
+        opts.per_input_columns = false;
+        opts.column_separator = None;
+        opts.width = UnweaveWidth::Column(3);
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1\n",
+                        "   B:1\n",
+                        "A:2\n",
+                        "\n",
+                        "   B:2\n"));
+    }
+
+    #[test]
+    fn unweave_columns_rr_fill() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_rr_fill_with_params(test_params);
+        }
+    }
+
+    fn unweave_columns_multiple_patterns_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"ERROR disk\nINFO x\nWARN cpu\nERROR: WARN net").unwrap();
+
+        let opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag {
+                patterns: vec!["ERROR".to_string(), "WARN".to_string()],
+                ..UnweaveOptionsTag::new("")
+            },
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            column_separator: Some("|".to_string()),
+            two_pass: test_params.two_pass,
+            header: true,
+            ..Default::default()
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("ERROR          |WARN\n",
+                        "ERROR disk     |\n",
+                        "               |WARN cpu\n",
+                        "ERROR: WARN net|\n"));
     }
 
     #[test]
-    fn unweave_columns_simple_pattern() {
+    fn unweave_columns_multiple_patterns() {
         for test_params in TEST_PARAMS {
-            unweave_columns_simple_pattern_with_params(test_params);
+            unweave_columns_multiple_patterns_with_params(test_params);
         }
     }
 
-    fn unweave_columns_separator_with_params(test_params: &TestParams) {
+    fn unweave_columns_invert_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2").unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nC:1").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "A|B|C".to_string(),
+            tag: UnweaveOptionsTag { invert: true, ..UnweaveOptionsTag::new("A") },
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
-            column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            ..Default::default()
         };
 
-        unweave_into_columns(&opts).unwrap();
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
 
-        assert!(fs::read(&output).unwrap() ==
-                concat!("A:1  |     |\n",
-                        "     |B:1  |\n",
-                        "A:2  |     |\n",
-                        "     |     |C:1\n",
-                        "     |B:2  |\n",
-                        "     |     |C:2\n").as_bytes());
+        assert!(fs::read_to_string(&output).unwrap() == "B:1\nC:1\n");
+        assert!(column_tracker.stream_line_counts() == [(b"unmatched".to_vec(), 2)]);
     }
 
     #[test]
-    fn unweave_columns_separator() {
+    fn unweave_columns_invert() {
         for test_params in TEST_PARAMS {
-            unweave_columns_separator_with_params(test_params);
+            unweave_columns_invert_with_params(test_params);
         }
     }
 
-    fn unweave_columns_auto_width_with_params(test_params: &TestParams) {
+    fn unweave_columns_trim_tag_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"A:11\nB:1111\nA:2\nZ:1\nC:1\nB:2\nC:222").unwrap();
+        fs::write(&inputs[0], b"[ A ] 1\n[B] 1\n[A] 2").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "A|B|C".to_string(),
+            tag: UnweaveOptionsTag { trim: true, ..UnweaveOptionsTag::new(r"\[([^]]*)\]") },
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Undefined,
             column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            header: true,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
-        assert!(fs::read(&output).unwrap() ==
-                concat!("A:11|      |\n",
-                        "    |B:1111|\n",
-                        "A:2 |      |\n",
-                        "    |      |C:1\n",
-                        "    |B:2   |\n",
-                        "    |      |C:222\n").as_bytes());
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A      |B\n",
+                        "[ A ] 1|\n",
+                        "       |[B] 1\n",
+                        "[A] 2  |\n"));
     }
 
     #[test]
-    fn unweave_columns_auto_width() {
+    fn unweave_columns_trim_tag() {
         for test_params in TEST_PARAMS {
-            unweave_columns_auto_width_with_params(test_params);
+            unweave_columns_trim_tag_with_params(test_params);
         }
     }
 
-    fn unweave_columns_line_width_with_params(test_params: &TestParams) {
+    fn unweave_columns_blank_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"A:11\nB:111\nA:2\nZ:1\nC:1\nB:2\nC:222").unwrap();
+        fs::write(&inputs[0], b"\nA:1\n\nB:1\n\n\nA:2").unwrap();
 
-        let opts = UnweaveOptionsColumns {
-            pattern: "A|B|C".to_string(),
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Line(15),
             column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            ..Default::default()
         };
 
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |B:1\n",
+                        "A:2|\n"));
+        assert!(column_tracker.stream_line_counts() == [(b"A".to_vec(), 2), (b"B".to_vec(), 1)]);
+
+        opts.blank = UnweaveBlank::LastColumn;
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("A:1|\n",
+                        "   |\n",
+                        "   |B:1\n",
+                        "   |\n",
+                        "   |\n",
+                        "A:2|\n"));
+        assert!(column_tracker.stream_line_counts() == [(b"A".to_vec(), 3), (b"B".to_vec(), 3)]);
+
+        opts.blank = UnweaveBlank::AllColumns;
+        let column_tracker = unweave_into_columns_with_tracker(&opts, None).unwrap();
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("   |\n",
+                        "A:1|\n",
+                        "   |\n",
+                        "   |B:1\n",
+                        "   |\n",
+                        "   |\n",
+                        "A:2|\n"));
+        assert!(column_tracker.stream_line_counts() == [(b"A".to_vec(), 2), (b"B".to_vec(), 1)]);
+
+        // Single pass, with no columns known when the first blank line is printed
+        opts.column_separator = None;
+        opts.width = UnweaveWidth::Column(3);
         unweave_into_columns(&opts).unwrap();
-
-        assert!(fs::read(&output).unwrap() ==
-                concat!("A:11 |     |\n",
-                        "     |B:111|\n",
-                        "A:2  |     |\n",
-                        "     |     |C:1\n",
-                        "     |B:2  |\n",
-                        "     |     |C:222\n").as_bytes());
+        assert!(fs::read_to_string(&output).unwrap() ==
+                concat!("\n",
+                        "A:1\n",
+                        "\n",
+                        "   B:1\n",
+                        "\n",
+                        "\n",
+                        "A:2\n"));
     }
 
     #[test]
-    fn unweave_columns_line_width() {
+    fn unweave_columns_blank() {
         for test_params in TEST_PARAMS {
-            unweave_columns_line_width_with_params(test_params);
+            unweave_columns_blank_with_params(test_params);
         }
     }
 
-    fn unweave_columns_complex_regex_with_params(test_params: &TestParams) {
+    fn unweave_columns_with_writer_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"1ACx\n2BAy\n3AC\nZAC\n4CBz\n5BAz\n6CCy").unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: r"[1-6](A|B|C)(?:A|B|C)".to_string(),
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Undefined,
-            column_separator: None,
+            width: UnweaveWidth::Column(4),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            ..Default::default()
         };
 
-        unweave_into_columns(&opts).unwrap();
+        let mut buf = Vec::new();
+        unweave_into_columns_with_writer(&opts, Box::new(&mut buf)).unwrap();
 
-        assert!(fs::read(&output).unwrap() ==
-                concat!("1ACx\n",
-                        "    2BAy\n",
-                        "3AC\n",
-                        "        4CBz\n",
-                        "    5BAz\n",
-                        "        6CCy\n").as_bytes());
+        assert!(buf ==
+                concat!("A:1\n",
+                        "    B:1\n",
+                        "A:2\n").as_bytes());
+        assert!(!output.exists());
     }
 
     #[test]
-    fn unweave_columns_complex_regex() {
+    fn unweave_columns_with_writer() {
         for test_params in TEST_PARAMS {
-            unweave_columns_complex_regex_with_params(test_params);
+            unweave_columns_with_writer_with_params(test_params);
         }
     }
 
-    fn unweave_columns_multiple_unicode_with_params(test_params: &TestParams) {
+    fn unweave_columns_buffer_size_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
-        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "άλφα1\nβήτα1\nδέλτα1\nβήτα2\nγάμμα1\n".as_bytes()).unwrap();
-        fs::write(&inputs[1], "γάμμα2\nάλφα2\n".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nA:2\nC:1").unwrap();
+
+        let mut outputs = Vec::new();
+
+        for buffer_size in &[1, crate::DEFAULT_BUFFER_SIZE, 1 << 20] {
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B|C"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: test_params.mmap,
+                width: UnweaveWidth::Column(4),
+                two_pass: test_params.two_pass,
+                buffer_size: *buffer_size,
+                ..Default::default()
+            };
 
-        let opts = UnweaveOptionsColumns {
-            pattern: "άλφα|βήτα|γάμμα".to_string(),
+            unweave_into_columns(&opts).unwrap();
+            outputs.push(fs::read(&output).unwrap());
+        }
+
+        assert!(outputs[0] ==
+                concat!("A:1\n",
+                        "    B:1\n",
+                        "A:2\n",
+                        "        C:1\n").as_bytes());
+        assert!(outputs.iter().all(|o| *o == outputs[0]));
+    }
+
+    #[test]
+    fn unweave_columns_buffer_size() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_buffer_size_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn unweave_columns_strict_single_pass() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nC:1").unwrap();
+
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
-            mmap: test_params.mmap,
-            width: UnweaveWidth::Undefined,
-            column_separator: None,
-            two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            inputs,
+            width: UnweaveWidth::Column(4),
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1\n",
+                        "    B:1\n",
+                        "A:2\n",
+                        "        C:1\n").as_bytes());
+
+        opts.strict_single_pass = true;
+        let err = unweave_into_columns(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::ColumnAfterOutput(t)) if t == "B"));
 
+        // Streams in the column map have columns before any output begins
+        let column_map = tmpdir.path().join("column-map");
+        fs::write(&column_map, b"A 0\nB 1\n").unwrap();
+        opts.column_map = Some(column_map);
+        let err = unweave_into_columns(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::ColumnAfterOutput(t)) if t == "C"));
+
+        fs::write(&opts.inputs[0], b"A:1\nB:1\nA:2").unwrap();
+        unweave_into_columns(&opts).unwrap();
         assert!(fs::read(&output).unwrap() ==
-                concat!("άλφα1\n",
-                        "     βήτα1\n",
-                        "     βήτα2\n",
-                        "          γάμμα1\n",
-                        "          γάμμα2\n",
-                        "άλφα2\n").as_bytes());
-    }
+                concat!("A:1\n",
+                        "    B:1\n",
+                        "A:2\n").as_bytes());
 
-    #[test]
-    fn unweave_columns_multiple_unicode() {
-        for test_params in TEST_PARAMS {
-            unweave_columns_multiple_unicode_with_params(test_params);
-        }
+        // Only single pass output can be misaligned
+        opts.column_map = None;
+        opts.column_separator = Some("|".to_string());
+        unweave_into_columns(&opts).unwrap();
     }
 
-    fn unweave_columns_wrapping_with_params(test_params: &TestParams) {
+    fn unweave_columns_column_width_overrides_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "άλφα-1\nβήτα1\nδέλτα1\nβήτα-22\nγάμμα1\n".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"A:12\nB:1234\nA:1").unwrap();
 
-        let opts = UnweaveOptionsColumns {
-            pattern: "άλφα|βήτα|γάμμα".to_string(),
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
-            column_separator: None,
+            column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            column_width_overrides: vec![("B".to_string(), 3)],
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                concat!("άλφα-\n",
-                        "1\n",
-                        "     βήτα1\n",
-                        "     βήτα-\n",
-                        "     22\n",
-                        "          γάμμα\n",
-                        "          1\n").as_bytes());
+                concat!("A:12|\n",
+                        "    |B:1\n",
+                        "    |234\n",
+                        "A:1 |\n").as_bytes());
+
+        // With a line width, the other columns share the rest of the line
+        opts.width = UnweaveWidth::Line(10);
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:12  |\n",
+                        "      |B:1\n",
+                        "      |234\n",
+                        "A:1   |\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_wrapping() {
+    fn unweave_columns_column_width_overrides() {
         for test_params in TEST_PARAMS {
-            unweave_columns_wrapping_with_params(test_params);
+            unweave_columns_column_width_overrides_with_params(test_params);
         }
     }
 
-    fn unweave_columns_wrapping_with_separator_with_params(test_params: &TestParams) {
+    fn unweave_columns_min_column_width_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "άλφα-1\nβήτα1\nδέλτα1\nβήτα-1234567\nγάμμα1\n".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
 
-        let opts = UnweaveOptionsColumns {
-            pattern: "άλφα|βήτα|γάμμα".to_string(),
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
-            column_separator: Some("##".to_string()),
+            column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            min_column_width: Some(6),
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                concat!("άλφα-##     ##\n",
-                        "1    ##     ##\n",
-                        "     ##βήτα1##\n",
-                        "     ##βήτα-##\n",
-                        "     ##12345##\n",
-                        "     ##67   ##\n",
-                        "     ##     ##γάμμα\n",
-                        "     ##     ##1\n").as_bytes());
+                concat!("A:1   |\n",
+                        "      |B:1\n",
+                        "A:2   |\n").as_bytes());
+
+        // Wrapping uses the minimum width
+        fs::write(&opts.inputs[0], b"A:1\nB:1\nA:2345678").unwrap();
+        opts.width = UnweaveWidth::Line(8);
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1   |\n",
+                        "      |B:1\n",
+                        "A:2345|\n",
+                        "678   |\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_wrapping_with_separator() {
+    fn unweave_columns_min_column_width() {
         for test_params in TEST_PARAMS {
-            unweave_columns_wrapping_with_separator_with_params(test_params);
+            unweave_columns_min_column_width_with_params(test_params);
         }
     }
 
-    fn unweave_columns_unicode_fill_with_params(test_params: &TestParams) {
+    fn unweave_columns_wrap_word_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "α:Α\nβ:ΒΒ\nγ:ΓΓΓ".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"hello world\nB:1\nabcdefghij k").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "α|β|γ".to_string(),
+            tag: UnweaveOptionsTag::new("^(hello|abc|B)"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
             column_separator: Some("|".to_string()),
+            width: UnweaveWidth::Column(7),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            wrap: UnweaveWrap::Word,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                concat!("α:Α  |     |\n",
-                        "     |β:ΒΒ |\n",
-                        "     |     |γ:ΓΓΓ\n").as_bytes());
+                concat!("hello  |       |\n",
+                        "world  |       |\n",
+                        "       |B:1    |\n",
+                        "       |       |abcdefg\n",
+                        "       |       |hij k\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_unicode_fill() {
+    fn unweave_columns_wrap_word() {
         for test_params in TEST_PARAMS {
-            unweave_columns_unicode_fill_with_params(test_params);
+            unweave_columns_wrap_word_with_params(test_params);
         }
     }
 
-    fn unweave_columns_invalid_unicode_fill_with_params(test_params: &TestParams) {
+    fn unweave_columns_wrap_marker_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"\xce\xb1\xce\x79\n\xce\xb2").unwrap();
+        fs::write(&inputs[0], b"abcdefgh\nB:1").unwrap();
 
-        let opts = UnweaveOptionsColumns {
-            pattern: "α|β".to_string(),
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("^(a|B)"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
             column_separator: Some("|".to_string()),
+            width: UnweaveWidth::Column(4),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            wrap_marker: Some("-".to_string()),
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                b"\xce\xb1\xce\x79  |\n     |\xce\xb2\n");
+                concat!("abc-|\n",
+                        "def-|\n",
+                        "gh  |\n",
+                        "    |B:1\n").as_bytes());
+
+        // At least one grapheme is printed in narrow columns
+        opts.width = UnweaveWidth::Column(1);
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("a|\n", "b|\n", "c|\n", "d|\n", "e|\n", "f|\n", "g|\n", "h|\n",
+                        " |B\n",
+                        " |:\n",
+                        " |1\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_invalid_unicode_fill() {
+    fn unweave_columns_wrap_marker() {
         for test_params in TEST_PARAMS {
-            unweave_columns_invalid_unicode_fill_with_params(test_params);
+            unweave_columns_wrap_marker_with_params(test_params);
         }
     }
 
-    fn unweave_columns_invalid_unicode_wrap_with_params(test_params: &TestParams) {
+    fn unweave_columns_sort_columns_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"\xce\xb1\xce\x79\n\xce\xb2").unwrap();
+        fs::write(&inputs[0], b"C:1\nA:1\nZ:1\nB:1\nC:2").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "α|β".to_string(),
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(1),
-            column_separator: Some("|".to_string()),
+            width: UnweaveWidth::Column(4),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            unmatched: UnweaveUnmatched::Last,
+            sort_columns: true,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                b"\xce\xb1|\n\xce|\n\x79|\n |\xce\xb2\n");
+                concat!("        C:1\n",
+                        "A:1\n",
+                        "            Z:1\n",
+                        "    B:1\n",
+                        "        C:2\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_invalid_unicode_wrap() {
+    fn unweave_columns_sort_columns() {
         for test_params in TEST_PARAMS {
-            unweave_columns_invalid_unicode_wrap_with_params(test_params);
+            unweave_columns_sort_columns_with_params(test_params);
         }
     }
 
-    fn unweave_columns_invalid_unicode_not_printable_with_params(test_params: &TestParams) {
+    fn unweave_columns_reverse_columns_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"\xce\xb1\xce\x13\n\xce\xb2").unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nC:1\nA:2").unwrap();
 
-        let opts = UnweaveOptionsColumns {
-            pattern: "α|β".to_string(),
+        let mut opts = UnweaveOptionsColumns {
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
-            column_separator: Some("|".to_string()),
+            width: UnweaveWidth::Column(4),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            unmatched: UnweaveUnmatched::Last,
+            reverse_columns: true,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                b"\xce\xb1\xce\x13   |\n     |\xce\xb2\n");
+                concat!("        A:1\n",
+                        "    B:1\n",
+                        "            Z:1\n",
+                        "C:1\n",
+                        "        A:2\n").as_bytes());
+
+        // Reversed sorted order
+        opts.tag = UnweaveOptionsTag::new("A|B|C");
+        opts.unmatched = UnweaveUnmatched::Drop;
+        opts.sort_columns = true;
+        opts.column_separator = Some("|".to_string());
+        opts.header = true;
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("C   |B   |A\n",
+                        "    |    |A:1\n",
+                        "    |B:1 |\n",
+                        "C:1 |    |\n",
+                        "    |    |A:2\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_invalid_unicode_not_printable_wrap() {
+    fn unweave_columns_reverse_columns() {
         for test_params in TEST_PARAMS {
-            unweave_columns_invalid_unicode_not_printable_with_params(test_params);
+            unweave_columns_reverse_columns_with_params(test_params);
         }
     }
 
-    fn unweave_columns_invalid_unicode_end_of_line_fill_with_params(test_params: &TestParams) {
+    fn unweave_columns_per_input_columns_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
-        let inputs = vec![tmpdir.path().join("input1")];
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"\xce\xb1\xce\n\xce\xb2").unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
+        fs::write(&inputs[1], b"A:3\nA:4").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "α|β".to_string(),
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
             column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            per_input_columns: true,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                b"\xce\xb1\xce   |\n     |\xce\xb2\n");
+                concat!("A:1|   |\n",
+                        "   |B:1|\n",
+                        "A:2|   |\n",
+                        "   |   |A:3\n",
+                        "   |   |A:4\n").as_bytes());
+
+        let opts = UnweaveOptionsColumns {
+            interleave: UnweaveInterleave::RoundRobin,
+            ..opts
+        };
+
+        unweave_into_columns(&opts).unwrap();
+
+        assert!(fs::read(&output).unwrap() ==
+                concat!("A:1|   |\n",
+                        "   |   |A:3\n",
+                        "   |B:1|\n",
+                        "   |   |A:4\n",
+                        "A:2|   |\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_invalid_unicode_end_of_line_fill() {
+    fn unweave_columns_per_input_columns() {
         for test_params in TEST_PARAMS {
-            unweave_columns_invalid_unicode_end_of_line_fill_with_params(test_params);
+            unweave_columns_per_input_columns_with_params(test_params);
         }
     }
 
-    fn unweave_columns_invalid_unicode_end_of_line_wrap_with_params(test_params: &TestParams) {
+    fn unweave_columns_binary_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
-        let inputs = vec![tmpdir.path().join("input1")];
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], b"\xce\xb1\xce\n\xce\xb2").unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1").unwrap();
+        fs::write(&inputs[1], b"A:2\0\0\0\0\0\0\0\0\nB:2\0\0\0\0").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "α|β".to_string(),
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(1),
             column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            binary: UnweaveBinary::Refuse,
+            ..Default::default()
+        };
+
+        let err = unweave_into_columns(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::BinaryInput(p)) if p == &opts.inputs[1]));
+
+        let opts = UnweaveOptionsColumns {
+            binary: UnweaveBinary::Process,
+            ..opts
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                b"\xce\xb1|\n\xce|\n |\xce\xb2\n");
+                concat!("A:1|\n",
+                        "   |B:1\n",
+                        "A:2\0\0\0\0\0\0\0\0|\n",
+                        "   |B:2\0\0\0\0\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_invalid_unicode_end_of_line_wrap() {
+    fn unweave_columns_binary() {
         for test_params in TEST_PARAMS {
-            unweave_columns_invalid_unicode_end_of_line_wrap_with_params(test_params);
+            unweave_columns_binary_with_params(test_params);
         }
     }
 
-    fn unweave_columns_tab_fill_with_params(test_params: &TestParams) {
+    fn unweave_columns_max_columns_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "αb\tc\nd".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"A:1\nB:1\nC:1\nA:2\nD:11\nB:2").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "b|d".to_string(),
+            tag: UnweaveOptionsTag::new("A|B|C|D"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(10),
             column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            max_columns: Some(2),
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                "αb      c |\n          |d\n".as_bytes());
+                concat!("A:1|   |\n",
+                        "   |B:1|\n",
+                        "   |   |C:1\n",
+                        "A:2|   |\n",
+                        "   |   |D:11\n",
+                        "   |B:2|\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_tab_fill() {
+    fn unweave_columns_max_columns() {
         for test_params in TEST_PARAMS {
-            unweave_columns_tab_fill_with_params(test_params);
+            unweave_columns_max_columns_with_params(test_params);
         }
     }
 
-    fn unweave_columns_tab_wrap_with_params(test_params: &TestParams) {
+    fn unweave_columns_max_lines_per_stream_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
         let inputs = vec![tmpdir.path().join("input1")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "αb\tc\nd".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"A:1\nA:2\nB:1\nA:3\nA:4\nB:2\nA:5").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "b|d".to_string(),
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
-            width: UnweaveWidth::Column(5),
-            column_separator: Some("|".to_string()),
+            width: UnweaveWidth::Column(4),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::Expand(8),
+            max_lines_per_stream: Some(2),
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                "αb   |\n   c |\n     |d\n".as_bytes());
+                concat!("A:1\n",
+                        "A:2\n",
+                        "    B:1\n",
+                        "    B:2\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_tab_wrap() {
+    fn unweave_columns_max_lines_per_stream() {
         for test_params in TEST_PARAMS {
-            unweave_columns_tab_wrap_with_params(test_params);
+            unweave_columns_max_lines_per_stream_with_params(test_params);
         }
     }
 
-    fn unweave_columns_tab_no_expand_with_params(test_params: &TestParams) {
+    fn unweave_columns_line_numbers_with_params(test_params: &TestParams) {
         let tmpdir = TempDir::new("unweave-test").unwrap();
-        let inputs = vec![tmpdir.path().join("input1")];
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
         let output = tmpdir.path().join("output");
-        fs::write(&inputs[0], "αb\tc\nd".as_bytes()).unwrap();
+        fs::write(&inputs[0], b"A:1\nZ:1\nB:123456\nZ:2\nZ:3\nZ:4\nZ:5\nZ:6").unwrap();
+        fs::write(&inputs[1], b"Z:7\nA:2").unwrap();
+
+        for (line_numbers, expected) in &[
+            (UnweaveLineNumbers::Blank,
+             concat!("   A     |B\n",
+                     " 1 A:1   |\n",
+                     " 3       |B:1234\n",
+                     "         |56\n",
+                     "10 A:2   |\n")),
+            (UnweaveLineNumbers::Repeat,
+             concat!("   A     |B\n",
+                     " 1 A:1   |\n",
+                     " 3       |B:1234\n",
+                     " 3       |56\n",
+                     "10 A:2   |\n")),
+        ] {
+            let opts = UnweaveOptionsColumns {
+                tag: UnweaveOptionsTag::new("A|B"),
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: test_params.mmap,
+                width: UnweaveWidth::Column(6),
+                column_separator: Some("|".to_string()),
+                two_pass: test_params.two_pass,
+                header: true,
+                line_numbers: *line_numbers,
+                ..Default::default()
+            };
+
+            unweave_into_columns(&opts).unwrap();
+
+            assert!(fs::read(&output).unwrap() == expected.as_bytes());
+        }
+    }
+
+    fn unweave_columns_show_offset_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output");
+        fs::write(&inputs[0], b"A:1\nB:1").unwrap();
+        fs::write(&inputs[1], b"Z:1\r\nA:22222\n").unwrap();
 
         let opts = UnweaveOptionsColumns {
-            pattern: "b|d".to_string(),
+            tag: UnweaveOptionsTag::new("A|B"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
             width: UnweaveWidth::Column(5),
             column_separator: Some("|".to_string()),
             two_pass: test_params.two_pass,
-            tab: UnweaveTab::NoExpand,
+            line_numbers: UnweaveLineNumbers::Blank,
+            show_offset: true,
+            ..Default::default()
         };
 
         unweave_into_columns(&opts).unwrap();
 
         assert!(fs::read(&output).unwrap() ==
-                "αb\tc  |\n     |d\n".as_bytes());
+                concat!("1 0 A:1  |\n",
+                        "2 4      |B:1\n",
+                        "4 5 A:222|\n",
+                        "    22   |\n").as_bytes());
     }
 
     #[test]
-    fn unweave_columns_tab_no_expand() {
+    fn unweave_columns_show_offset() {
         for test_params in TEST_PARAMS {
-            unweave_columns_tab_no_expand_with_params(test_params);
+            unweave_columns_show_offset_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn unweave_columns_line_numbers() {
+        for test_params in TEST_PARAMS {
+            unweave_columns_line_numbers_with_params(test_params);
         }
     }
 }
@@ -15,51 +15,408 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{UnweaveOptionsFiles, UnweaveError};
-use crate::util::{TagFinder, FileLines};
+use crate::{UnweaveOptionsFiles, UnweaveFilenameCase, UnweaveError};
+use crate::util::{TagFinder, FileLines, write_stats, default_max_open_files, Progress,
+                  write_highlighted, is_broken_pipe};
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use anyhow::{Result, Context, bail};
 
-use std::io::{Write, BufWriter};
+use std::io::{Write, BufWriter, self};
 use std::fmt::Write as IoWrite;
-use std::fs::File;
-use std::path::Path;
-use std::collections::hash_map::Entry;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::collections::hash_map::{Entry, DefaultHasher};
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+/// A factory for the Write objects of output files, called with the name of
+/// each output file, and whether to append to any existing output instead of
+/// overwriting it.
+pub type WriterFactory = Box<dyn FnMut(&str, bool) -> io::Result<Box<dyn Write>>>;
+
+/// An output file, which may be temporarily closed to limit the number of
+/// open files.
+struct OutputWrite {
+    write: Option<Box<dyn Write>>,
+    filename: String,
+    tag: Vec<u8>,
+    last_used: u64,
+    line_count: u64,
+}
+
+/// The tag, output file name and number of lines of a stream and output file
+/// pair, as listed in the manifest.
+type ManifestEntry = (Vec<u8>, String, u64);
 
 /// Helper that creates and provides access to the output files.
 ///
 /// The output files are created based on a template path provided during
 /// OutputFiles creation. The template supports '%t' which is replaced by the
-/// tag name and '%Nd' which is replaced with the stream number (starting from
-/// 0) zero-padded to a length of N digits.
+/// tag name, '%Nd' which is replaced with the stream number (starting from
+/// 0) zero-padded to a length of N digits, and '%i' and '%I' which are
+/// replaced with the basename and full path of the input file respectively.
+///
+/// Optionally, an additional output file that isn't associated with any tag
+/// can be used for lines that don't match the pattern.
+///
+/// Existing output files are overwritten, unless appending is enabled.
+///
+/// The special template "-" writes the lines of all tags to standard output.
+///
+/// The Write objects for the output files are created by a writer factory,
+/// which is called with the name of each output file. The default factory
+/// creates files, treating the name "-" as standard output.
+///
+/// Optionally, the number of output files that are open at the same time can
+/// be limited, in which case the least recently used output file is closed
+/// when another one needs to be opened, and is reopened for appending when
+/// used again.
 struct OutputFiles {
     template: String,
-    writes: Vec<Box<dyn Write>>,
+    open: WriterFactory,
+    writes: Vec<OutputWrite>,
+    // Maps tags to the entries of tag_writes for their current output files
     write_for_tag_map: AHashMap<Vec<u8>, usize>,
+    // The tag, output file and number of lines of each stream and output
+    // file pair
+    tag_writes: Vec<(Vec<u8>, usize, u64)>,
+    // Manifest entries of other OutputFiles for the same template
+    added_manifest: Vec<ManifestEntry>,
     write_for_filename_map: AHashMap<String, usize>,
+    stream_for_tag_map: AHashMap<Vec<u8>, usize>,
+    stream_line_counts: Vec<(Vec<u8>, u64)>,
+    last_line_for_tag: AHashMap<Vec<u8>, Vec<u8>>,
+    unmatched: Option<(Box<dyn Write>, PathBuf)>,
+    unmatched_line_count: u64,
+    template_uses_input: bool,
+    template_uses_tag: bool,
+    template_uses_number: bool,
+    // The buffer size of files created by the default writer factory, which
+    // allows creating OutputFiles for the same template in other threads
+    file_buffer_size: Option<usize>,
+    stream_numbers: Option<AHashMap<Vec<u8>, usize>>,
+    filename_case: UnweaveFilenameCase,
+    sanitize_chars: Option<String>,
+    no_merge: bool,
+    current_input: PathBuf,
+    to_stdout: bool,
+    append: bool,
+    max_open_writes: Option<usize>,
+    open_writes: usize,
+    use_count: u64,
 }
 
 impl OutputFiles {
     /// Create a new OutputFiles struct with the specified output path template.
-    fn new_for_template(template: &Path) -> Result<Self> {
-        let output_files = OutputFiles {
+    fn new_for_template(template: &Path, append: bool, buffer_size: usize) -> Result<Self> {
+        let mut output_files = Self::with_writer_factory(
+            template,
+            append,
+            Box::new(move |name, append| Self::open_file(name, append, buffer_size))
+        )?;
+        output_files.file_buffer_size = Some(buffer_size);
+        Ok(output_files)
+    }
+
+    /// Create a new OutputFiles struct with the specified output path
+    /// template, using the specified factory to create the Write objects for
+    /// the output files.
+    fn with_writer_factory(template: &Path, append: bool, open: WriterFactory) -> Result<Self> {
+        let mut output_files = OutputFiles {
             template: template.to_string_lossy().into_owned(),
+            open,
             writes: Vec::new(),
             write_for_tag_map: AHashMap::new(),
+            tag_writes: Vec::new(),
+            added_manifest: Vec::new(),
             write_for_filename_map: AHashMap::new(),
+            stream_for_tag_map: AHashMap::new(),
+            stream_line_counts: Vec::new(),
+            last_line_for_tag: AHashMap::new(),
+            unmatched: None,
+            unmatched_line_count: 0,
+            template_uses_input: false,
+            template_uses_tag: false,
+            template_uses_number: false,
+            file_buffer_size: None,
+            stream_numbers: None,
+            filename_case: UnweaveFilenameCase::Preserve,
+            sanitize_chars: None,
+            no_merge: false,
+            current_input: PathBuf::new(),
+            to_stdout: template == Path::new("-"),
+            append,
+            max_open_writes: None,
+            open_writes: 0,
+            use_count: 0,
         };
 
-        // Create a dummy filename to catch invalid patterns early
-        output_files.filename_for_tag("".as_bytes())?;
+        if output_files.to_stdout {
+            let write = output_files.create_write("-")?;
+            output_files.writes.push(
+                OutputWrite { write: Some(write), filename: "-".to_string(), tag: Vec::new(),
+                              last_used: 0, line_count: 0 }
+            );
+            return Ok(output_files);
+        }
+
+        // Create dummy filenames to catch invalid patterns early, and to find
+        // out whether filenames depend on the input file, the tag and the
+        // stream number.
+        let filename = output_files.filename_for(b"", Path::new("a"), 0)?;
+        output_files.template_uses_input =
+            filename != output_files.filename_for(b"", Path::new("b"), 0)?;
+        output_files.template_uses_tag =
+            filename != output_files.filename_for(b"a", Path::new("a"), 0)?;
+        output_files.template_uses_number =
+            filename != output_files.filename_for(b"", Path::new("a"), 1)?;
 
         Ok(output_files)
     }
 
-    /// Gets the filename for a tag based on the path template
+    /// Creates an output file, or opens it for appending, buffering writes with
+    /// the specified buffer size. The name "-" refers to standard output.
+    fn open_file(name: &str, append: bool, buffer_size: usize) -> io::Result<Box<dyn Write>> {
+        if name == "-" {
+            return Ok(Box::new(BufWriter::with_capacity(buffer_size, io::stdout())));
+        }
+
+        let file = if append {
+            OpenOptions::new().append(true).create(true).open(name)?
+        } else {
+            File::create(name)?
+        };
+
+        Ok(Box::new(BufWriter::with_capacity(buffer_size, file)))
+    }
+
+    /// A writer factory which discards all output, without creating any files.
+    fn dry_run_writer_factory() -> WriterFactory {
+        Box::new(|_, _| Ok(Box::new(io::sink())))
+    }
+
+    /// Creates the Write object for an output file using the writer factory.
+    fn create_write(&mut self, name: &str) -> Result<Box<dyn Write>> {
+        (self.open)(name, self.append)
+            .with_context(|| format!("Failed to create output file {}", name))
+    }
+
+    /// Sets the maximum number of output files to keep open at the same time.
+    fn set_max_open_files(&mut self, max_open_files: Option<usize>) {
+        self.max_open_writes = max_open_files;
+    }
+
+    /// Sets the file to write unmatched lines to.
+    fn set_unmatched_file(&mut self, path: &Path) -> Result<()> {
+        let write = self.create_write(&path.to_string_lossy())?;
+        self.unmatched = Some((write, path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Gets the Write object for unmatched lines along with its path, or None
+    /// if unmatched lines should be dropped. Each call counts a line for the
+    /// unmatched file.
+    fn write_for_unmatched(&mut self) -> Option<(&mut dyn Write, &Path)> {
+        if self.unmatched.is_some() {
+            self.unmatched_line_count += 1;
+        }
+        self.unmatched.as_mut().map(|(w, p)| (w as &mut dyn Write, p.as_path()))
+    }
+
+    /// Sets the tags of all streams, so that streams are numbered in sorted
+    /// tag order, instead of in order of appearance.
+    fn set_stream_tags(&mut self, mut tags: Vec<Vec<u8>>) {
+        tags.sort();
+        self.stream_numbers = Some(tags.into_iter().enumerate().map(|(i, t)| (t, i)).collect());
+    }
+
+    /// Whether the streams can be split between multiple OutputFiles for the
+    /// same template, without them sharing any output file, as long as
+    /// streams with the same tag in file names (see shard_key) go to the same
+    /// OutputFiles. Numbering streams in order of appearance requires seeing
+    /// all streams.
+    fn shardable(&self) -> bool {
+        !self.to_stdout && self.template_uses_tag &&
+            (!self.template_uses_number || self.stream_numbers.is_some())
+    }
+
+    /// Returns the key determining the OutputFiles a stream goes to when
+    /// splitting streams, i.e., the tag as it appears in file names.
+    fn shard_key<'a>(&self, tag: &'a [u8]) -> Cow<'a, [u8]> {
+        match std::str::from_utf8(tag) {
+            Ok(tag) => match self.filename_tag(tag) {
+                Cow::Borrowed(t) => Cow::Borrowed(t.as_bytes()),
+                Cow::Owned(t) => Cow::Owned(t.into_bytes()),
+            },
+            Err(_) => Cow::Borrowed(tag),
+        }
+    }
+
+    /// Sets the case to change stream tags to in file names.
+    fn set_filename_case(&mut self, filename_case: UnweaveFilenameCase) {
+        self.filename_case = filename_case;
+    }
+
+    /// Sets the characters of stream tags to replace with '_' in file names.
+    fn set_sanitize_chars(&mut self, sanitize_chars: Option<String>) {
+        self.sanitize_chars = sanitize_chars;
+    }
+
+    /// Sets whether streams with different tags which have the same output
+    /// file are an error, instead of sharing the file.
+    fn set_no_merge(&mut self, no_merge: bool) {
+        self.no_merge = no_merge;
+    }
+
+    /// Records a line for a tag, returning the number of lines recorded for
+    /// the tag so far.
+    fn count_line_for_tag(&mut self, tag: &[u8]) -> u64 {
+        if let Some(s) = self.stream_for_tag_map.get(tag) {
+            let count = &mut self.stream_line_counts[*s].1;
+            *count += 1;
+            return *count;
+        }
+
+        self.stream_for_tag_map.insert(tag.to_vec(), self.stream_line_counts.len());
+        self.stream_line_counts.push((tag.to_vec(), 1));
+        1
+    }
+
+    /// Records the last line of a tag, returning whether the line is identical
+    /// to the previous line recorded for the tag.
+    fn is_repeated_line(&mut self, tag: &[u8], line: &[u8]) -> bool {
+        match self.last_line_for_tag.get_mut(tag) {
+            Some(last) if last == line => true,
+            Some(last) => {
+                last.clear();
+                last.extend_from_slice(line);
+                false
+            },
+            None => {
+                self.last_line_for_tag.insert(tag.to_vec(), line.to_vec());
+                false
+            },
+        }
+    }
+
+    /// Returns the tag and the number of lines recorded for each stream, in
+    /// order of appearance.
+    fn stream_line_counts(&self) -> &[(Vec<u8>, u64)] {
+        &self.stream_line_counts
+    }
+
+    /// Returns the name and the number of lines written of each output file,
+    /// in order of creation, followed by the unmatched file, if any.
+    fn output_file_line_counts(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<_> = self.writes.iter()
+            .map(|o| (o.filename.clone(), o.line_count))
+            .collect();
+        if let Some((_, path)) = &self.unmatched {
+            counts.push((path.to_string_lossy().into_owned(), self.unmatched_line_count));
+        }
+        counts
+    }
+
+    /// Adds the manifest entries of other OutputFiles for the same template.
+    fn add_manifest(&mut self, entries: Vec<ManifestEntry>) {
+        self.added_manifest.extend(entries);
+    }
+
+    /// Returns the tag, output file name and number of lines of each stream
+    /// and output file pair, including the added entries, in order of stream
+    /// appearance.
+    fn manifest(&self) -> Vec<ManifestEntry> {
+        let own = self.tag_writes.iter()
+            .map(|(tag, w, count)| (tag, &self.writes[*w].filename, *count));
+        let added = self.added_manifest.iter().map(|(tag, filename, count)| (tag, filename, *count));
+        let mut index: AHashMap<_, usize> = AHashMap::new();
+        let mut entries: Vec<ManifestEntry> = Vec::new();
+
+        // A stream may return to an output file after the tag to file
+        // mapping is reset for another input.
+        for (tag, filename, count) in own.chain(added) {
+            match index.entry((tag, filename)) {
+                Entry::Occupied(o) => entries[*o.get()].2 += count,
+                Entry::Vacant(v) => {
+                    v.insert(entries.len());
+                    entries.push((tag.clone(), filename.clone(), count));
+                }
+            }
+        }
+
+        entries.sort_by_key(|(tag, ..)| self.stream_for_tag_map.get(tag).copied());
+        entries
+    }
+
+    /// Flushes all output files. All files are flushed even if some of them
+    /// fail, with the first error returned.
+    fn flush(&mut self) -> Result<()> {
+        let mut result = Ok(());
+
+        for output_write in self.writes.iter_mut() {
+            let write = match &mut output_write.write {
+                Some(w) => w,
+                None => continue,
+            };
+            if let Err(e) = write.flush() {
+                if result.is_ok() {
+                    let filename = if self.to_stdout {
+                        "standard output"
+                    } else {
+                        &output_write.filename
+                    };
+                    result = Err(e).with_context(
+                        || format!("Failed to write to output file {}", filename)
+                    );
+                }
+            }
+        }
+
+        if let Some((write, path)) = &mut self.unmatched {
+            if let Err(e) = write.flush() {
+                if result.is_ok() {
+                    result = Err(e).with_context(
+                        || format!("Failed to write to output file {}", path.display())
+                    );
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Gets the filename for a tag and input file based on the path template
     /// this struct was created with.
-    fn filename_for_tag(&self, tag: &[u8]) -> Result<String> {
-        let count = self.writes.len().to_string();
+    fn filename_for_tag(&self, tag: &[u8], input: &Path) -> Result<String> {
+        let number = self.stream_numbers.as_ref()
+            .and_then(|n| n.get(tag))
+            .copied()
+            .unwrap_or(self.writes.len());
+        self.filename_for(tag, input, number)
+    }
+
+    /// Returns the tag as it appears in file names.
+    fn filename_tag<'a>(&self, tag: &'a str) -> Cow<'a, str> {
+        let tag = match self.filename_case {
+            UnweaveFilenameCase::Preserve => Cow::Borrowed(tag),
+            UnweaveFilenameCase::Lower => Cow::Owned(tag.to_lowercase()),
+            UnweaveFilenameCase::Upper => Cow::Owned(tag.to_uppercase()),
+        };
+        match &self.sanitize_chars {
+            Some(chars) => Cow::Owned(
+                tag.chars().map(|c| if chars.contains(c) { '_' } else { c }).collect()
+            ),
+            None => tag,
+        }
+    }
+
+    /// Gets the filename for a tag, input file and stream number based on the
+    /// path template this struct was created with.
+    fn filename_for(&self, tag: &[u8], input: &Path, number: usize) -> Result<String> {
+        let count = number.to_string();
         let mut fname = String::new();
         let mut inspecial = false;
         let mut width = 0;
@@ -70,7 +427,20 @@ impl OutputFiles {
                 (false, '%') => {inspecial = true; width = 0; }
                 (false, _) => fname.push(c),
                 (true, '%') => { fname.push(c); inspecial = false; },
-                (true, 't') => { fname.push_str(tag); inspecial = false; },
+                (true, 't') => {
+                    fname.push_str(&self.filename_tag(tag));
+                    inspecial = false;
+                },
+                (true, 'i') => {
+                    if let Some(name) = input.file_name() {
+                        fname.push_str(&name.to_string_lossy());
+                    }
+                    inspecial = false;
+                },
+                (true, 'I') => {
+                    fname.push_str(&input.to_string_lossy());
+                    inspecial = false;
+                },
                 (true, 'd') => {
                     write!(&mut fname, "{:0>1$}", count, width)?;
                     inspecial = false;
@@ -89,67 +459,595 @@ impl OutputFiles {
         Ok(fname)
     }
 
-    /// Gets the Write objects for a tag, based on the path template
-    /// this struct was created with.
-    fn write_for_tag(&mut self, tag: &[u8]) -> Result<&mut dyn Write> {
-        if let Some(w) = self.write_for_tag_map.get_mut(tag) {
-            return Ok(&mut self.writes[*w]);
+    /// Gets the Write objects for a tag and input file, based on the path
+    /// template this struct was created with. Each call counts a line for the
+    /// output file.
+    fn write_for_tag(&mut self, tag: &[u8], input: &Path) -> Result<&mut dyn Write> {
+        // If filenames depend on the input, the tag to file mapping is only
+        // valid for the input it was created for.
+        if self.template_uses_input && self.current_input != input {
+            self.write_for_tag_map.clear();
+            self.current_input = input.to_path_buf();
+        }
+
+        if let Some(&t) = self.write_for_tag_map.get(tag) {
+            let (_, w, count) = &mut self.tag_writes[t];
+            *count += 1;
+            let w = *w;
+            self.writes[w].line_count += 1;
+            return self.use_write(w, "");
+        }
+
+        if self.to_stdout {
+            self.write_for_tag_map.insert(tag.to_vec(), self.tag_writes.len());
+            self.tag_writes.push((tag.to_vec(), 0, 1));
+            self.writes[0].line_count += 1;
+            return Ok(self.writes[0].write.as_mut().unwrap());
         }
 
-        let filename = self.filename_for_tag(tag)?;
+        let filename = self.filename_for_tag(tag, input)?;
+        // Point out the likely cause of failing to create files for tags with
+        // characters that are not allowed in file names.
+        let hint = if self.sanitize_chars.is_none() && tag.iter().any(|b| b"/\0".contains(b)) {
+            " (the stream tag contains characters not allowed in file names, \
+             see --sanitize-filenames)"
+        } else {
+            ""
+        };
         let w = match self.write_for_filename_map.entry(filename.clone()) {
-            Entry::Occupied(o) => *o.get(),
+            Entry::Occupied(o) => {
+                let w = *o.get();
+                let other_tag = &self.writes[w].tag;
+                // Templates without '%t' share output files between streams
+                // by design.
+                if self.no_merge && self.template_uses_tag && other_tag != tag {
+                    bail!(UnweaveError::OutputFileCollision(
+                        String::from_utf8_lossy(other_tag).into_owned(),
+                        String::from_utf8_lossy(tag).into_owned(),
+                        filename
+                    ));
+                }
+                w
+            },
             Entry::Vacant(v) => {
-                self.writes.push(Box::new(
-                    BufWriter::new(
-                        File::create(&filename).with_context(
-                            || format!("Failed to create output file {}", filename)
-                        )?
-                    )
-                ));
+                self.writes.push(OutputWrite { write: None, filename, tag: tag.to_vec(),
+                                               last_used: 0, line_count: 0 });
                 *v.insert(self.writes.len() - 1)
             }
         };
 
-        self.write_for_tag_map.insert(tag.to_vec(), w);
+        self.write_for_tag_map.insert(tag.to_vec(), self.tag_writes.len());
+        self.tag_writes.push((tag.to_vec(), w, 1));
+        self.writes[w].line_count += 1;
+
+        self.use_write(w, hint)
+    }
+
+    /// Gets the Write object of an output file, opening the file if needed,
+    /// and marks it as the most recently used. The hint is appended to the
+    /// error message if the file fails to open.
+    fn use_write(&mut self, w: usize, hint: &str) -> Result<&mut dyn Write> {
+        self.use_count += 1;
+
+        if self.writes[w].write.is_none() {
+            if self.max_open_writes.is_some_and(|m| self.open_writes >= m) {
+                self.close_least_recently_used()?;
+            }
+            // Files that were used before have been closed to limit the number
+            // of open files, and must be reopened for appending.
+            let output_write = &mut self.writes[w];
+            let append = self.append || output_write.last_used != 0;
+            let filename = &output_write.filename;
+            output_write.write = Some(
+                (self.open)(filename, append).with_context(
+                    || format!("Failed to create output file {}{}", filename, hint)
+                )?
+            );
+            self.open_writes += 1;
+        }
+
+        let output_write = &mut self.writes[w];
+        output_write.last_used = self.use_count;
+        Ok(output_write.write.as_mut().unwrap())
+    }
+
+    /// Writes the number of lines counted for each output file, including the
+    /// file for unmatched lines, to the file itself.
+    fn write_line_counts(&mut self, line_ending: &[u8]) -> Result<()> {
+        for w in 0..self.writes.len() {
+            let line_count = self.writes[w].line_count;
+            let filename = self.writes[w].filename.clone();
+            let write = self.use_write(w, "")?;
+            write!(write, "{}", line_count)
+                .and_then(|_| write.write_all(line_ending))
+                .with_context(|| format!("Failed to write to output file {}", filename))?;
+        }
+
+        if let Some((write, path)) = &mut self.unmatched {
+            write!(write, "{}", self.unmatched_line_count)
+                .and_then(|_| write.write_all(line_ending))
+                .with_context(
+                    || format!("Failed to write to output file {}", path.display())
+                )?;
+        }
+
+        Ok(())
+    }
 
-        return Ok(&mut self.writes[w]);
+    /// Closes the least recently used open output file.
+    fn close_least_recently_used(&mut self) -> Result<()> {
+        let lru = self.writes.iter_mut()
+            .filter(|o| o.write.is_some())
+            .min_by_key(|o| o.last_used);
+
+        if let Some(output_write) = lru {
+            let mut write = output_write.write.take().unwrap();
+            self.open_writes -= 1;
+            write.flush().with_context(
+                || format!("Failed to write to output file {}", output_write.filename)
+            )?;
+        }
+
+        Ok(())
     }
 }
 
 /// Perform the unweave operation into multiple files, one file per matched stream.
-pub(crate) fn unweave_into_files(opts: &UnweaveOptionsFiles) -> Result<()> {
-    let mut output_files = OutputFiles::new_for_template(&opts.output.as_ref().unwrap())?;
-    let mut tag_finder = TagFinder::new(&opts.pattern)?;
+///
+/// Returns the number of lines that matched the pattern.
+pub fn unweave_into_files(opts: &UnweaveOptionsFiles) -> Result<u64> {
+    let template = opts.output.as_ref().unwrap();
+    let output_files = if opts.dry_run {
+        OutputFiles::with_writer_factory(template, opts.append, OutputFiles::dry_run_writer_factory())?
+    } else {
+        OutputFiles::new_for_template(template, opts.append, opts.buffer_size)?
+    };
+    unweave_into_files_with_output_files(opts, output_files)
+}
+
+/// Perform the unweave operation into multiple outputs, one output per matched
+/// stream, using the specified factory to create the Write objects for the
+/// output file names produced by the output template. The factory is asked to
+/// append to existing outputs with the append option, and when reopening
+/// outputs that were closed to limit the number of open files. With the dry
+/// run option, the factory is not used.
+///
+/// Returns the number of lines that matched the pattern.
+pub fn unweave_into_files_with_writer_factory(opts: &UnweaveOptionsFiles,
+                                              open: WriterFactory) -> Result<u64> {
+    let open = if opts.dry_run { OutputFiles::dry_run_writer_factory() } else { open };
+    let output_files = OutputFiles::with_writer_factory(opts.output.as_ref().unwrap(),
+                                                        opts.append, open)?;
+    unweave_into_files_with_output_files(opts, output_files)
+}
+
+/// Perform the unweave operation into the specified output files, and print
+/// the output file listing of a dry run and the statistics if requested.
+/// The output being closed early (e.g., when writing to standard output piped
+/// to "head") stops the operation without an error. Returns the number of
+/// lines that matched the pattern.
+fn unweave_into_files_with_output_files(opts: &UnweaveOptionsFiles,
+                                        mut output_files: OutputFiles) -> Result<u64> {
+    if let Err(e) = unweave_into_output_files(opts, &mut output_files) {
+        if !is_broken_pipe(&e) {
+            return Err(e);
+        }
+    }
+
+    if opts.dry_run {
+        let mut stdout = io::stdout().lock();
+        write_output_file_listing(&mut stdout, &output_files.output_file_line_counts())?;
+        stdout.flush()?;
+    }
+
+    if let Some(manifest) = &opts.manifest {
+        let write_manifest = || -> Result<()> {
+            let mut out = BufWriter::new(File::create(manifest)?);
+            write_manifest(&mut out, &output_files.manifest())?;
+            out.flush()?;
+            Ok(())
+        };
+        write_manifest().with_context(
+            || format!("Failed to write manifest file {}", manifest.display())
+        )?;
+    }
+
+    if opts.stats {
+        write_stats(&mut io::stderr().lock(), output_files.stream_line_counts())?;
+    }
+
+    Ok(output_files.stream_line_counts().iter().map(|(_, c)| c).sum())
+}
+
+/// Writes the name and number of lines of each output file.
+fn write_output_file_listing(out: &mut dyn Write, files: &[(String, u64)]) -> Result<()> {
+    writeln!(out, "LINES\tFILE")?;
+    for (filename, count) in files {
+        writeln!(out, "{}\t{}", count, filename)?;
+    }
+    Ok(())
+}
+
+/// Writes the tag, output file name and number of lines of each stream and
+/// output file pair.
+fn write_manifest(out: &mut dyn Write, entries: &[ManifestEntry]) -> Result<()> {
+    writeln!(out, "TAG\tFILE\tLINES")?;
+    for (tag, filename, count) in entries {
+        out.write_all(tag)?;
+        writeln!(out, "\t{}\t{}", filename, count)?;
+    }
+    Ok(())
+}
+
+/// Finds the distinct tags of all lines in the inputs.
+fn scan_tags(opts: &UnweaveOptionsFiles) -> Result<Vec<Vec<u8>>> {
+    let mut tag_finder = TagFinder::new(&opts.tag)?;
+    let mut tags = AHashSet::new();
 
     for input in &opts.inputs {
-        let mut file_lines = FileLines::new(input, opts.mmap)?;
+        let mut file_lines = FileLines::new(input, opts.mmap, opts.buffer_size, opts.line_terminator())?;
+        file_lines.set_max_line(opts.max_line());
         while let Some(line) = file_lines.next() {
-            let tag = match tag_finder.find_in(&line) {
-                Some(tag_range) => &line[tag_range],
-                None => continue
+            if let Some(tag) = tag_finder.find_tag_in(line) {
+                if !tags.contains(tag.as_ref()) {
+                    tags.insert(tag.into_owned());
+                }
+            }
+        }
+        file_lines.check_line_length(input)?;
+    }
+
+    Ok(tags.into_iter().collect())
+}
+
+/// Perform the unweave operation into the specified output files. With
+/// multiple jobs, and output files that can be created in other threads, the
+/// output files are written by a pool of writer threads (see
+/// unweave_into_output_files_sharded).
+fn unweave_into_output_files(opts: &UnweaveOptionsFiles, output_files: &mut OutputFiles) -> Result<()> {
+    let stream_tags = if opts.stable_numbers { Some(scan_tags(opts)?) } else { None };
+
+    configure_output_files(opts, output_files, stream_tags.clone(),
+                           opts.max_open_files.or_else(default_max_open_files));
+
+    if let Some(unmatched_file) = &opts.unmatched_file {
+        output_files.set_unmatched_file(unmatched_file)?;
+    }
+
+    let failed_streams = match output_files.file_buffer_size {
+        Some(buffer_size) if opts.jobs > 1 && !opts.dry_run && output_files.shardable() =>
+            unweave_into_output_files_sharded(opts, output_files, buffer_size, stream_tags)?,
+        _ => read_into_output_files(opts, output_files, None)?,
+    };
+
+    if failed_streams > 0 {
+        bail!(UnweaveError::OutputStreamsFailed(failed_streams));
+    }
+
+    Ok(())
+}
+
+/// Configures the naming of output files, and the maximum number of open
+/// output files.
+fn configure_output_files(opts: &UnweaveOptionsFiles, output_files: &mut OutputFiles,
+                          stream_tags: Option<Vec<Vec<u8>>>, max_open_files: Option<usize>) {
+    if let Some(stream_tags) = stream_tags {
+        output_files.set_stream_tags(stream_tags);
+    }
+    output_files.set_filename_case(opts.filename_case);
+    output_files.set_sanitize_chars(opts.sanitize_filenames.clone());
+    output_files.set_no_merge(opts.no_merge);
+    output_files.set_max_open_files(max_open_files);
+}
+
+/// Perform the unweave operation with the output files of the streams written
+/// by a pool of writer threads. Each writer thread owns the streams whose tags
+/// (as they appear in file names) hash to it, so that the lines of each stream
+/// are written in order, and streams sharing an output file are written by the
+/// same thread. Lines are read, matched and counted in the current thread,
+/// which also writes unmatched lines. Returns the number of streams which
+/// failed to be written, when keeping going.
+fn unweave_into_output_files_sharded(opts: &UnweaveOptionsFiles, output_files: &mut OutputFiles,
+                                     buffer_size: usize, stream_tags: Option<Vec<Vec<u8>>>)
+    -> Result<usize>
+{
+    let jobs = opts.jobs as usize;
+    // The limit of open files is shared between the writer threads
+    let max_open_files = opts.max_open_files.or_else(default_max_open_files)
+        .map(|m| std::cmp::max(m / jobs, 1));
+
+    std::thread::scope(|scope| {
+        let mut senders = Vec::new();
+        let handles: Vec<_> = (0..jobs).map(|_| {
+            let (sender, receiver) = mpsc::sync_channel(SHARD_CHANNEL_BATCHES);
+            senders.push(sender);
+            let stream_tags = stream_tags.clone();
+            scope.spawn(move || {
+                let mut output_files = OutputFiles::new_for_template(
+                    opts.output.as_ref().unwrap(), opts.append, buffer_size)?;
+                configure_output_files(opts, &mut output_files, stream_tags, max_open_files);
+                let failed_streams = write_shard(opts, &mut output_files, receiver)?;
+                Ok::<_, anyhow::Error>((failed_streams, output_files.manifest()))
+            })
+        }).collect();
+
+        // Dropping the shards when reading is done, or has failed, lets the
+        // writer threads finish.
+        let read_result = read_into_output_files(opts, output_files, Some(Shards::new(opts, senders)));
+
+        let mut failed_streams = 0;
+        let mut write_result = Ok(());
+        for handle in handles {
+            match handle.join().unwrap() {
+                Ok((n, manifest)) => {
+                    failed_streams += n;
+                    output_files.add_manifest(manifest);
+                },
+                Err(e) if write_result.is_ok() => write_result = Err(e),
+                Err(_) => {},
+            }
+        }
+
+        // Reading stops early if a writer thread fails, so its error takes
+        // precedence.
+        write_result?;
+        Ok(read_result? + failed_streams)
+    })
+}
+
+/// The number of lines sent to a writer thread at a time.
+const SHARD_BATCH_LINES: usize = 1024;
+
+/// The number of batches of lines that may be queued for a writer thread.
+const SHARD_CHANNEL_BATCHES: usize = 4;
+
+/// A line of a stream, sent to the writer thread that owns the stream.
+struct ShardedLine {
+    tag: Vec<u8>,
+    line: Vec<u8>,
+    highlight_range: Option<Range<usize>>,
+    terminated: bool,
+    input: usize,
+}
+
+/// The channels to the writer threads, along with the lines batched for
+/// each of them.
+struct Shards {
+    senders: Vec<SyncSender<Vec<ShardedLine>>>,
+    batches: Vec<Vec<ShardedLine>>,
+    batch_lines: usize,
+}
+
+impl Shards {
+    fn new(opts: &UnweaveOptionsFiles, senders: Vec<SyncSender<Vec<ShardedLine>>>) -> Self {
+        // Lines are sent as soon as they are read when following, or when
+        // they are expected to reach their output files without delay.
+        let batch_lines = if opts.follow || opts.line_buffered { 1 } else { SHARD_BATCH_LINES };
+        let batches = senders.iter().map(|_| Vec::new()).collect();
+        Shards { senders, batches, batch_lines }
+    }
+
+    /// Queues a line for the writer thread that owns the streams with the
+    /// specified shard key. Returns false if the writer thread has stopped
+    /// due to an error.
+    fn send(&mut self, shard_key: &[u8], line: ShardedLine) -> bool {
+        let mut hasher = DefaultHasher::new();
+        shard_key.hash(&mut hasher);
+        let shard = (hasher.finish() % self.senders.len() as u64) as usize;
+
+        self.batches[shard].push(line);
+        if self.batches[shard].len() < self.batch_lines {
+            return true;
+        }
+
+        let batch = std::mem::take(&mut self.batches[shard]);
+        self.senders[shard].send(batch).is_ok()
+    }
+}
+
+impl Drop for Shards {
+    fn drop(&mut self) {
+        for (sender, batch) in self.senders.iter().zip(self.batches.iter_mut()) {
+            if !batch.is_empty() {
+                let _ = sender.send(std::mem::take(batch));
+            }
+        }
+    }
+}
+
+/// Writes the lines received from the reading thread to the output files of
+/// their streams. Returns the number of streams which failed to be written,
+/// when keeping going.
+fn write_shard(opts: &UnweaveOptionsFiles, output_files: &mut OutputFiles,
+               receiver: Receiver<Vec<ShardedLine>>) -> Result<usize> {
+    let line_ending = opts.output_line_ending().as_bytes();
+    let mut failed_tags = AHashSet::new();
+
+    for batch in receiver {
+        for ShardedLine { tag, line, highlight_range, terminated, input } in batch {
+            if failed_tags.contains(&tag) {
+                continue;
+            }
+            let input = &opts.inputs[input];
+            let output_file = match output_files.write_for_tag(&tag, input) {
+                Ok(output_file) => output_file,
+                Err(e) => {
+                    handle_write_result(opts, Err(e))?;
+                    failed_tags.insert(tag);
+                    continue;
+                },
             };
-            let output_file = output_files.write_for_tag(tag)?;
-            output_file.write(line)
-                .and_then(|_| output_file.write(b"\n"))
+            if opts.count_only {
+                continue;
+            }
+            let line_ending = if opts.preserve_eol && !terminated { b"" } else { line_ending };
+            let result = write_stream_line(opts, output_file, &tag, &line, highlight_range,
+                                           line_ending)
                 .with_context(
                     || format!("Failed to write to output file {}",
-                                output_files.filename_for_tag(tag)
+                                output_files.filename_for_tag(&tag, input)
                                             .unwrap_or("<unknown>".to_string()))
-                )?;
+                );
+            if handle_write_result(opts, result)? {
+                failed_tags.insert(tag);
+            }
+        }
+    }
+
+    if opts.count_only {
+        output_files.write_line_counts(line_ending)?;
+    }
+
+    output_files.flush()?;
+
+    Ok(failed_tags.len())
+}
+
+/// Reads the lines of the inputs and writes them to the output files of their
+/// streams, or, if shards are provided, sends them to the writer threads that
+/// own their streams. Unmatched lines are always written to the unmatched file
+/// in this thread. Returns the number of streams which failed to be written,
+/// when keeping going.
+fn read_into_output_files(opts: &UnweaveOptionsFiles, output_files: &mut OutputFiles,
+                          mut shards: Option<Shards>) -> Result<usize> {
+    let mut tag_finder = TagFinder::new(&opts.tag)?;
+    let line_ending = opts.output_line_ending().as_bytes();
+    let highlight = opts.highlights();
+    let mut progress = Progress::new(opts.progress);
+    // The streams which failed to be written, when keeping going
+    let mut failed_tags = AHashSet::new();
+    let mut unmatched_failed = false;
+
+    'inputs: for (i, input) in opts.inputs.iter().enumerate() {
+        let mut file_lines = if opts.follow && i == opts.inputs.len() - 1 {
+            FileLines::new_following(input, opts.buffer_size, opts.line_terminator())?
+        } else {
+            FileLines::new(input, opts.mmap, opts.buffer_size, opts.line_terminator())?
+        };
+        file_lines.check_binary(input, opts.binary)?;
+        file_lines.set_max_line(opts.max_line());
+        while let Some((line, terminated)) = file_lines.next_with_terminator() {
+            progress.count_line(line);
+            let line_ending = if opts.preserve_eol && !terminated { b"" } else { line_ending };
+            let tag = match tag_finder.find_tag_in(line) {
+                Some(tag) => tag,
+                None => {
+                    let unmatched = output_files.write_for_unmatched();
+                    if let Some((output_file, path)) = unmatched.filter(|_| !opts.count_only) {
+                        if unmatched_failed {
+                            continue;
+                        }
+                        let result = output_file.write_all(line)
+                            .and_then(|_| output_file.write_all(line_ending))
+                            .and_then(|_| if opts.line_buffered { output_file.flush() } else { Ok(()) })
+                            .with_context(
+                                || format!("Failed to write to output file {}", path.display())
+                            );
+                        unmatched_failed = handle_write_result(opts, result)?;
+                    }
+                    continue
+                }
+            };
+            if failed_tags.contains(tag.as_ref()) {
+                continue;
+            }
+            if opts.uniq && output_files.is_repeated_line(&tag, line) {
+                continue;
+            }
+            let highlight_range = if highlight && !opts.tag.strip {
+                tag_finder.found_tag_range()
+            } else {
+                None
+            };
+            let line = &tag_finder.strip_found_tag(line)[..];
+            let count = output_files.count_line_for_tag(&tag);
+            if opts.max_lines_per_stream.is_some_and(|m| count > m) {
+                continue;
+            }
+            if let Some(shards) = &mut shards {
+                let sharded_line = ShardedLine {
+                    tag: tag.to_vec(), line: line.to_vec(), highlight_range, terminated, input: i
+                };
+                if !shards.send(&output_files.shard_key(&tag), sharded_line) {
+                    break 'inputs;
+                }
+                continue;
+            }
+            let output_file = match output_files.write_for_tag(&tag, input) {
+                Ok(output_file) => output_file,
+                Err(e) => {
+                    handle_write_result(opts, Err(e))?;
+                    failed_tags.insert(tag.into_owned());
+                    continue;
+                },
+            };
+            if opts.count_only {
+                continue;
+            }
+            let result = write_stream_line(opts, output_file, &tag, line, highlight_range, line_ending)
+                .with_context(
+                    || format!("Failed to write to output file {}",
+                                output_files.filename_for_tag(&tag, input)
+                                            .unwrap_or("<unknown>".to_string()))
+                );
+            if handle_write_result(opts, result)? {
+                failed_tags.insert(tag.into_owned());
+            }
         }
+        file_lines.check_line_length(input)?;
+    }
+
+    if opts.count_only {
+        output_files.write_line_counts(line_ending)?;
     }
 
+    output_files.flush()?;
+
+    Ok(failed_tags.len() + unmatched_failed as usize)
+}
+
+/// Writes a line of a stream to its output file, prefixed with the stream tag
+/// if requested, and flushes the output file when line buffered.
+fn write_stream_line(opts: &UnweaveOptionsFiles, output_file: &mut dyn Write, tag: &[u8],
+                     line: &[u8], highlight_range: Option<Range<usize>>,
+                     line_ending: &[u8]) -> io::Result<()> {
+    if opts.prefix_tag {
+        output_file.write_all(tag)?;
+        output_file.write_all(b": ")?;
+    }
+    write_highlighted(output_file, line, highlight_range)?;
+    output_file.write_all(line_ending)?;
+    if opts.line_buffered {
+        output_file.flush()?;
+    }
     Ok(())
 }
 
+/// Handles the result of writing a line of a stream. Errors are returned,
+/// unless keeping going, in which case they are reported. Returns whether
+/// the write failed, in which case the remaining lines of the stream should be
+/// skipped.
+fn handle_write_result(opts: &UnweaveOptionsFiles, result: Result<()>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(false),
+        Err(e) if opts.keep_going && !is_broken_pipe(&e) => {
+            writeln!(io::stderr().lock(), "Error: {:#}", e)?;
+            Ok(true)
+        },
+        Err(e) => Err(e),
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use tempdir::TempDir;
     use std::fs::{self};
-    use crate::UnweaveMmap;
+    use crate::{UnweaveMmap, UnweaveOptionsTag, UnweaveTagGroup, UnweaveLineEnding, UnweaveHighlight};
+    use crate::util::test_writers::{FailingFlush, FlushRecorder};
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     struct TestParams {
         mmap: UnweaveMmap,
@@ -167,10 +1065,11 @@ mod tests {
         fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2").unwrap();
 
         let opts = UnweaveOptionsFiles {
-            pattern: "A|B|C".to_string(),
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: test_params.mmap,
+            ..Default::default()
         };
 
         unweave_into_files(&opts).unwrap();
@@ -197,6 +1096,958 @@ mod tests {
         }
     }
 
+    fn unweave_into_files_stable_numbers_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output-%t-%1d");
+        fs::write(&inputs[0], b"C:1\nZ:1\nA:1").unwrap();
+        fs::write(&inputs[1], b"B:1\nC:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            stable_numbers: true,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A-0")).unwrap() == b"A:1\n");
+        assert!(fs::read(tmpdir.path().join("output-B-1")).unwrap() == b"B:1\n");
+        assert!(fs::read(tmpdir.path().join("output-C-2")).unwrap() == b"C:1\nC:2\n");
+    }
+
+    #[test]
+    fn unweave_into_files_stable_numbers() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_stable_numbers_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn unweave_into_files_filename_case() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\na:1\nB:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|a|B"),
+            output: Some(tmpdir.path().join("output-%t")),
+            inputs,
+            filename_case: UnweaveFilenameCase::Lower,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-a")).unwrap() == b"A:1\na:1\nA:2\n");
+        assert!(fs::read(tmpdir.path().join("output-b")).unwrap() == b"B:1\n");
+    }
+
+    #[test]
+    fn unweave_into_files_tag_field() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"C,A,x\nD,B,Z\nE,A,y\nF").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag {
+                field: Some((",".to_string(), 2)),
+                ..UnweaveOptionsTag::new("[A-Z]")
+            },
+            output: Some(tmpdir.path().join("output-%t")),
+            inputs,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"C,A,x\nE,A,y\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"D,B,Z\n");
+        assert!(fs::read_dir(tmpdir.path()).unwrap().count() == 3);
+    }
+
+    #[test]
+    fn unweave_into_files_no_merge() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1\na:1\nA:2").unwrap();
+
+        let mut opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|a|B"),
+            output: Some(tmpdir.path().join("output-%t")),
+            inputs,
+            filename_case: UnweaveFilenameCase::Lower,
+            no_merge: true,
+            ..Default::default()
+        };
+
+        let err = unweave_into_files(&opts).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<UnweaveError>(),
+            Some(UnweaveError::OutputFileCollision(t1, t2, _)) if t1 == "A" && t2 == "a"
+        ));
+
+        // Without %t all streams share the output file
+        opts.output = Some(tmpdir.path().join("output"));
+        unweave_into_files(&opts).unwrap();
+        assert!(fs::read(tmpdir.path().join("output")).unwrap() == b"A:1\nB:1\na:1\nA:2\n");
+    }
+
+    #[test]
+    fn unweave_into_files_sanitize_filenames() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"a/b:1\nc:1\na/b:2").unwrap();
+
+        let mut opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("^[^:]+"),
+            output: Some(tmpdir.path().join("output-%t")),
+            inputs,
+            ..Default::default()
+        };
+
+        let err = unweave_into_files(&opts).unwrap_err();
+        assert!(err.to_string().contains("--sanitize-filenames"));
+
+        opts.sanitize_filenames = Some("/\0".to_string());
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-a_b")).unwrap() == b"a/b:1\na/b:2\n");
+        assert!(fs::read(tmpdir.path().join("output-c")).unwrap() == b"c:1\n");
+    }
+
+    #[test]
+    fn unweave_into_files_max_open_files() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1\nC:1\nA:2\nC:2\nB:2\nA:3").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(tmpdir.path().join("output-%t")),
+            inputs,
+            max_open_files: Some(2),
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"A:1\nA:2\nA:3\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"B:1\nB:2\n");
+        assert!(fs::read(tmpdir.path().join("output-C")).unwrap() == b"C:1\nC:2\n");
+    }
+
+    #[test]
+    fn unweave_into_files_tag_group() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"x1:A\ny2:B\nx3:A\n").unwrap();
+
+        for group in &[UnweaveTagGroup::Index(2), UnweaveTagGroup::Name("t".to_string())] {
+            let opts = UnweaveOptionsFiles {
+                tag: UnweaveOptionsTag {
+                    group: group.clone(),
+                    ..UnweaveOptionsTag::new(r"([xy])\d:(?P<t>A|B)")
+                },
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: UnweaveMmap::Allow,
+                ..Default::default()
+            };
+
+            unweave_into_files(&opts).unwrap();
+
+            assert!(fs::read(tmpdir.path().join("output-A")).unwrap() ==
+                    concat!("x1:A\n",
+                            "x3:A\n").as_bytes());
+            assert!(fs::read(tmpdir.path().join("output-B")).unwrap() ==
+                    "y2:B\n".as_bytes());
+        }
+    }
+
+    #[test]
+    fn unweave_into_files_tag_join() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"h1 [E] a\nh2 [E] b\nh1 [W] c\nh1 [E] d\n").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag {
+                join: Some("-".to_string()),
+                ..UnweaveOptionsTag::new(r"(\w+)\s+\[(\w+)\]")
+            },
+            output: Some(output.clone()),
+            inputs,
+            mmap: UnweaveMmap::Allow,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-h1-E")).unwrap() ==
+                concat!("h1 [E] a\n",
+                        "h1 [E] d\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-h2-E")).unwrap() ==
+                "h2 [E] b\n".as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-h1-W")).unwrap() ==
+                "h1 [W] c\n".as_bytes());
+    }
+
+    #[test]
+    fn unweave_into_files_unknown_tag_group() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+
+        for group in &[UnweaveTagGroup::Index(2), UnweaveTagGroup::Name("u".to_string())] {
+            let opts = UnweaveOptionsFiles {
+                tag: UnweaveOptionsTag {
+                    group: group.clone(),
+                    ..UnweaveOptionsTag::new(r"(?P<t>A|B|C)")
+                },
+                output: Some(output.clone()),
+                inputs: inputs.clone(),
+                mmap: UnweaveMmap::Allow,
+                ..Default::default()
+            };
+
+            assert!(unweave_into_files(&opts).is_err());
+        }
+    }
+
+    #[test]
+    fn unweave_into_files_unmatched_file() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        let unmatched = tmpdir.path().join("unmatched");
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nA:2\nY:1").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            unmatched_file: Some(unmatched.clone()),
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() ==
+                "B:1\n".as_bytes());
+        assert!(fs::read(&unmatched).unwrap() ==
+                concat!("Z:1\n",
+                        "Y:1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_into_files_line_ending() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        let unmatched = tmpdir.path().join("unmatched");
+        fs::write(&inputs[0], b"A:1\r\nB:1\nZ:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            unmatched_file: Some(unmatched.clone()),
+            line_ending: UnweaveLineEnding::Crlf,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() ==
+                concat!("A:1\r\n",
+                        "A:2\r\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() ==
+                "B:1\r\n".as_bytes());
+        assert!(fs::read(&unmatched).unwrap() ==
+                "Z:1\r\n".as_bytes());
+    }
+
+    #[test]
+    fn unweave_into_files_null_data() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        let unmatched = tmpdir.path().join("unmatched");
+        fs::write(&inputs[0], b"A:1\nA:x\0B:1\r\0Z:1\0A:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("^(A|B|C)"),
+            output: Some(output.clone()),
+            inputs,
+            unmatched_file: Some(unmatched.clone()),
+            null_data: true,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() ==
+                b"A:1\nA:x\0A:2\0");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"B:1\r\0");
+        assert!(fs::read(&unmatched).unwrap() == b"Z:1\0");
+    }
+
+    #[test]
+    fn unweave_into_files_append() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"A:1\nB:1").unwrap();
+        fs::write(&inputs[1], b"A:2\nC:1").unwrap();
+
+        for input in &inputs {
+            let opts = UnweaveOptionsFiles {
+                tag: UnweaveOptionsTag::new("A|B|C"),
+                output: Some(output.clone()),
+                inputs: vec![input.clone()],
+                append: true,
+                ..Default::default()
+            };
+
+            unweave_into_files(&opts).unwrap();
+        }
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() ==
+                "B:1\n".as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-C")).unwrap() ==
+                "C:1\n".as_bytes());
+    }
+
+    #[test]
+    fn unweave_into_files_input_name() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        let output = tmpdir.path().join("output-%i-%t");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
+        fs::write(&inputs[1], b"A:3\nB:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs: inputs.clone(),
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-input1-A")).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-input1-B")).unwrap() ==
+                "B:1\n".as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-input2-A")).unwrap() ==
+                "A:3\n".as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-input2-B")).unwrap() ==
+                "B:2\n".as_bytes());
+
+        let output = PathBuf::from("%I.%t");
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output),
+            inputs: inputs.clone(),
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("input1.A")).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("input2.B")).unwrap() ==
+                "B:2\n".as_bytes());
+    }
+
+    /// A Write sink that can be inspected after being handed over as a
+    /// Box<dyn Write>.
+    #[derive(Clone, Default)]
+    struct SharedSink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unweave_into_files_stdout() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nA:2\nC:1").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(PathBuf::from("-")),
+            inputs,
+            prefix_tag: true,
+            ..Default::default()
+        };
+
+        let sink = SharedSink::default();
+        let factory_sink = sink.clone();
+        let mut output_files = OutputFiles::with_writer_factory(
+            Path::new("-"),
+            false,
+            Box::new(move |_, _| Ok(Box::new(factory_sink.clone())))
+        ).unwrap();
+
+        unweave_into_output_files(&opts, &mut output_files).unwrap();
+
+        assert!(*sink.0.borrow() ==
+                concat!("A: A:1\n",
+                        "B: B:1\n",
+                        "A: A:2\n",
+                        "C: C:1\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_into_files_with_writer_factory() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(PathBuf::from("out.%t")),
+            unmatched_file: Some(PathBuf::from("out.none")),
+            inputs,
+            ..Default::default()
+        };
+
+        let sinks: Rc<RefCell<Vec<(String, SharedSink)>>> = Rc::default();
+        let factory_sinks = sinks.clone();
+
+        super::unweave_into_files_with_writer_factory(&opts, Box::new(move |name, _| {
+            let sink = SharedSink::default();
+            factory_sinks.borrow_mut().push((name.to_string(), sink.clone()));
+            Ok(Box::new(sink))
+        })).unwrap();
+
+        let sinks: Vec<_> = sinks.borrow().iter()
+            .map(|(n, s)| (n.clone(), s.0.borrow().clone()))
+            .collect();
+        assert!(sinks == [("out.none".to_string(), b"Z:1\n".to_vec()),
+                          ("out.A".to_string(), b"A:1\nA:2\n".to_vec()),
+                          ("out.B".to_string(), b"B:1\n".to_vec())]);
+    }
+
+    #[test]
+    fn unweave_into_files_line_buffered() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1\nZ:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(PathBuf::from("out.%t")),
+            inputs,
+            line_buffered: true,
+            ..Default::default()
+        };
+
+        let recorders: Rc<RefCell<Vec<(String, FlushRecorder)>>> = Rc::default();
+        let factory_recorders = recorders.clone();
+
+        super::unweave_into_files_with_writer_factory(&opts, Box::new(move |name, _| {
+            let recorder = FlushRecorder::default();
+            factory_recorders.borrow_mut().push((name.to_string(), recorder.clone()));
+            Ok(Box::new(recorder))
+        })).unwrap();
+
+        // One flush per line, followed by the final flush
+        let flushes: Vec<_> = recorders.borrow().iter()
+            .map(|(n, r)| (n.clone(), r.flushes.borrow().clone()))
+            .collect();
+        assert!(flushes == [("out.A".to_string(), vec![4, 8, 8]),
+                            ("out.B".to_string(), vec![4, 4])]);
+    }
+
+    #[test]
+    fn unweave_into_files_flush_error() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(PathBuf::from("-")),
+            inputs,
+            ..Default::default()
+        };
+
+        let mut output_files = OutputFiles::with_writer_factory(
+            Path::new("-"),
+            false,
+            Box::new(|_, _| Ok(Box::new(FailingFlush)))
+        ).unwrap();
+
+        assert!(unweave_into_output_files(&opts, &mut output_files).is_err());
+    }
+
+    #[test]
+    fn unweave_into_files_stats() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(PathBuf::from("-")),
+            inputs,
+            stats: true,
+            ..Default::default()
+        };
+
+        let mut output_files = OutputFiles::with_writer_factory(
+            Path::new("-"),
+            false,
+            Box::new(|_, _| Ok(Box::new(SharedSink::default())))
+        ).unwrap();
+
+        unweave_into_output_files(&opts, &mut output_files).unwrap();
+
+        assert!(output_files.stream_line_counts() == [(b"A".to_vec(), 2), (b"B".to_vec(), 1)]);
+
+        let mut stats = Vec::new();
+        write_stats(&mut stats, output_files.stream_line_counts()).unwrap();
+        assert!(stats ==
+                concat!("STREAM\tLINES\tTAG\n",
+                        "0\t2\tA\n",
+                        "1\t1\tB\n").as_bytes());
+    }
+
+    #[test]
+    fn unweave_into_files_manifest() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let manifest = tmpdir.path().join("manifest");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2\nC:3").unwrap();
+
+        let mut opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(tmpdir.path().join("output-%t")),
+            inputs,
+            manifest: Some(manifest.clone()),
+            ..Default::default()
+        };
+
+        let output = |t| tmpdir.path().join(format!("output-{}", t)).display().to_string();
+        let expected = format!("TAG\tFILE\tLINES\nA\t{}\t2\nB\t{}\t2\nC\t{}\t3\n",
+                               output("A"), output("B"), output("C"));
+
+        unweave_into_files(&opts).unwrap();
+        assert!(fs::read_to_string(&manifest).unwrap() == expected);
+
+        // The output files are written by multiple threads
+        opts.jobs = 2;
+        unweave_into_files(&opts).unwrap();
+        assert!(fs::read_to_string(&manifest).unwrap() == expected);
+    }
+
+    #[test]
+    fn unweave_into_files_dry_run() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t-%2d");
+        let unmatched = tmpdir.path().join("unmatched");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nB:2\nC:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            unmatched_file: Some(unmatched.clone()),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let mut output_files = OutputFiles::with_writer_factory(
+            &output,
+            false,
+            OutputFiles::dry_run_writer_factory()
+        ).unwrap();
+
+        unweave_into_output_files(&opts, &mut output_files).unwrap();
+
+        let dir = tmpdir.path().to_string_lossy();
+        let expected = vec![
+            (format!("{}/output-A-00", dir), 2),
+            (format!("{}/output-B-01", dir), 2),
+            (format!("{}/output-C-02", dir), 2),
+            (format!("{}/unmatched", dir), 1),
+        ];
+        assert!(output_files.output_file_line_counts() == expected);
+        assert!(fs::read_dir(tmpdir.path()).unwrap().count() == 1);
+
+        let mut listing = Vec::new();
+        write_output_file_listing(&mut listing, &expected[..1]).unwrap();
+        assert!(listing == format!("LINES\tFILE\n2\t{}/output-A-00\n", dir).as_bytes());
+    }
+
+    fn unweave_into_files_count_only_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        let unmatched = tmpdir.path().join("unmatched");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2\nZ:1\nC:1\nA:3\nC:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output),
+            inputs,
+            mmap: test_params.mmap,
+            unmatched_file: Some(unmatched.clone()),
+            max_open_files: Some(1),
+            count_only: true,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"3\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"1\n");
+        assert!(fs::read(tmpdir.path().join("output-C")).unwrap() == b"2\n");
+        assert!(fs::read(&unmatched).unwrap() == b"1\n");
+    }
+
+    #[test]
+    fn unweave_into_files_count_only() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_count_only_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn unweave_into_files_trim_tag() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"[ A ] 1\n[B] 1\n[A] 2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag { trim: true, ..UnweaveOptionsTag::new(r"\[([^]]*)\]") },
+            output: Some(output.clone()),
+            inputs,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"[ A ] 1\n[A] 2\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"[B] 1\n");
+    }
+
+    fn unweave_into_files_whole_line_tag_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"ok A\nfail B\nok A \nok C\n-\nok A").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag { whole_line: true, ..UnweaveOptionsTag::new("ok") },
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            ..Default::default()
+        };
+
+        assert!(unweave_into_files(&opts).unwrap() == 4);
+
+        assert!(fs::read(tmpdir.path().join("output-ok A")).unwrap() == b"ok A\nok A \nok A\n");
+        assert!(fs::read(tmpdir.path().join("output-ok C")).unwrap() == b"ok C\n");
+        assert!(fs::read_dir(tmpdir.path()).unwrap().count() == 3);
+    }
+
+    #[test]
+    fn unweave_into_files_whole_line_tag() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_whole_line_tag_with_params(test_params);
+        }
+    }
+
+    fn unweave_into_files_strip_tag_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"[A] one\nx [B] two\n[A] three [A]").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag { strip: true, ..UnweaveOptionsTag::new(r"\[(\w+)\] ?") },
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"[] one\n[] three [A]\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"x [] two\n");
+    }
+
+    #[test]
+    fn unweave_into_files_strip_tag() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_strip_tag_with_params(test_params);
+        }
+    }
+
+    fn unweave_into_files_highlight_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"x A:1\nB:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("[AB]"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            highlight: UnweaveHighlight::Always,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"x \x1b[1;7mA\x1b[0m:1\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"\x1b[1;7mB\x1b[0m:2\n");
+    }
+
+    #[test]
+    fn unweave_into_files_highlight() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_highlight_with_params(test_params);
+        }
+    }
+
+    fn unweave_into_files_uniq_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"A:1\nA:1\nB:1\nA:1\nB:1\nA:2\nA:1").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            uniq: true,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"A:1\nA:2\nA:1\n");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"B:1\n");
+    }
+
+    #[test]
+    fn unweave_into_files_uniq() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_uniq_with_params(test_params);
+        }
+    }
+
+    fn unweave_into_files_preserve_eol_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"A:1\nB:1\nA:2").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"A:1\nA:2\n");
+
+        let opts = UnweaveOptionsFiles { preserve_eol: true, ..opts };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() == b"A:1\nA:2");
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() == b"B:1\n");
+    }
+
+    #[test]
+    fn unweave_into_files_preserve_eol() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_preserve_eol_with_params(test_params);
+        }
+    }
+
+    fn unweave_into_files_keep_going_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t").join("lines");
+        fs::write(&inputs[0], b"A:1\nB:1\nC:1\nA:2\nB:2\nC:2").unwrap();
+        fs::create_dir(tmpdir.path().join("output-A")).unwrap();
+        fs::create_dir(tmpdir.path().join("output-C")).unwrap();
+        // A file in place of the output directory, so that creating the
+        // output file fails regardless of permissions
+        fs::write(tmpdir.path().join("output-B"), b"").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            mmap: test_params.mmap,
+            ..Default::default()
+        };
+
+        let err = unweave_into_files(&opts).unwrap_err();
+        assert!(err.downcast_ref::<UnweaveError>().is_none());
+        assert!(fs::read(tmpdir.path().join("output-C").join("lines")).is_err());
+
+        let opts = UnweaveOptionsFiles { keep_going: true, ..opts };
+
+        let err = unweave_into_files(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::OutputStreamsFailed(1))));
+        assert!(fs::read(tmpdir.path().join("output-A").join("lines")).unwrap() == b"A:1\nA:2\n");
+        assert!(fs::read(tmpdir.path().join("output-C").join("lines")).unwrap() == b"C:1\nC:2\n");
+    }
+
+    #[test]
+    fn unweave_into_files_keep_going() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_keep_going_with_params(test_params);
+        }
+    }
+
+    /// Returns the names and contents of the files in a directory, sorted by
+    /// name.
+    fn dir_files(dir: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut files: Vec<_> = fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().path())
+            .map(|p| (p.file_name().unwrap().to_string_lossy().into_owned(), fs::read(&p).unwrap()))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn unweave_into_files_jobs_with_params(test_params: &TestParams) {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1"), tmpdir.path().join("input2")];
+        for (i, input) in inputs.iter().enumerate() {
+            let lines: Vec<String> = (0..5000)
+                .map(|n| match n % 7 {
+                    0 => format!("unmatched {}\n", n),
+                    // Tags differing only in case share output files when
+                    // lowercasing file names
+                    1 => format!("[Tag{}] {} {}\n", n % 5, i, n),
+                    _ => format!("[tag{}] {} {}\n", (n * 31) % 53, i, n),
+                })
+                .collect();
+            fs::write(input, lines.concat()).unwrap();
+        }
+
+        let serial_dir = tmpdir.path().join("serial");
+        let parallel_dir = tmpdir.path().join("parallel");
+
+        // With stream numbers, tags differing only in case get separate files
+        for (template, nfiles) in [("out-%t", 53 + 1), ("out-%t-%3d", 53 + 5 + 1)] {
+            for dir in [&serial_dir, &parallel_dir] {
+                let _ = fs::remove_dir_all(dir);
+                fs::create_dir(dir).unwrap();
+                let opts = UnweaveOptionsFiles {
+                    tag: UnweaveOptionsTag::new(r"^\[(\w+)\]"),
+                    output: Some(dir.join(template)),
+                    unmatched_file: Some(dir.join("unmatched")),
+                    inputs: inputs.clone(),
+                    mmap: test_params.mmap,
+                    prefix_tag: true,
+                    stable_numbers: true,
+                    filename_case: UnweaveFilenameCase::Lower,
+                    jobs: if dir == &serial_dir { 1 } else { 4 },
+                    ..Default::default()
+                };
+                assert!(unweave_into_files(&opts).unwrap() == 2 * 5000 - 2 * 715);
+            }
+
+            let serial_files = dir_files(&serial_dir);
+            assert!(serial_files.len() == nfiles);
+            assert!(serial_files == dir_files(&parallel_dir));
+        }
+
+        // Streams which fail to be written in a writer thread are reported.
+        // Both "Tag2" and "tag2" are written to the failing "out-tag2".
+        for n in 0..53 {
+            fs::create_dir(tmpdir.path().join(format!("out-tag{}", n))).unwrap();
+        }
+        fs::remove_dir(tmpdir.path().join("out-tag2")).unwrap();
+        fs::write(tmpdir.path().join("out-tag2"), b"").unwrap();
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new(r"^\[(\w+)\]"),
+            output: Some(tmpdir.path().join("out-%t").join("lines")),
+            inputs: inputs.clone(),
+            mmap: test_params.mmap,
+            filename_case: UnweaveFilenameCase::Lower,
+            keep_going: true,
+            jobs: 4,
+            ..Default::default()
+        };
+        let err = unweave_into_files(&opts).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(UnweaveError::OutputStreamsFailed(2))));
+        assert!(fs::read(tmpdir.path().join("out-tag1").join("lines")).is_ok());
+    }
+
+    #[test]
+    fn unweave_into_files_jobs() {
+        for test_params in TEST_PARAMS {
+            unweave_into_files_jobs_with_params(test_params);
+        }
+    }
+
+    #[test]
+    fn unweave_into_files_max_lines_per_stream() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let inputs = vec![tmpdir.path().join("input1")];
+        let output = tmpdir.path().join("output-%t");
+        fs::write(&inputs[0], b"A:1\nA:2\nB:1\nA:3\nA:4\nB:2\nA:5").unwrap();
+
+        let opts = UnweaveOptionsFiles {
+            tag: UnweaveOptionsTag::new("A|B|C"),
+            output: Some(output.clone()),
+            inputs,
+            max_lines_per_stream: Some(2),
+            ..Default::default()
+        };
+
+        unweave_into_files(&opts).unwrap();
+
+        assert!(fs::read(tmpdir.path().join("output-A")).unwrap() ==
+                concat!("A:1\n",
+                        "A:2\n").as_bytes());
+        assert!(fs::read(tmpdir.path().join("output-B")).unwrap() ==
+                concat!("B:1\n",
+                        "B:2\n").as_bytes());
+    }
+
     #[test]
     fn unweave_into_files_incomplete_file_pattern() {
         let tmpdir = TempDir::new("unweave-test").unwrap();
@@ -204,10 +2055,11 @@ mod tests {
         let output = tmpdir.path().join("output-%t-%5");
 
         let opts = UnweaveOptionsFiles {
-            pattern: "A|B|C".to_string(),
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: UnweaveMmap::Allow,
+            ..Default::default()
         };
 
         assert!(unweave_into_files(&opts).is_err());
@@ -220,10 +2072,11 @@ mod tests {
         let output = tmpdir.path().join("output-%b");
 
         let opts = UnweaveOptionsFiles {
-            pattern: "A|B|C".to_string(),
+            tag: UnweaveOptionsTag::new("A|B|C"),
             output: Some(output.clone()),
-            inputs: inputs,
+            inputs,
             mmap: UnweaveMmap::Allow,
+            ..Default::default()
         };
 
         assert!(unweave_into_files(&opts).is_err());
@@ -16,28 +16,135 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::Result;
-use crate::{UnweaveMmap, UnweaveTab};
-use std::io::{BufRead, BufReader, Read, self, Seek, SeekFrom};
+use crate::{UnweaveMmap, UnweaveOptionsTag, UnweaveTagGroup, UnweaveError,
+            UnweaveProgress, UnweaveBinary, UnweaveShowControl, UnweaveMaxLineAction};
+use std::io::{BufRead, BufReader, Read, Write, self, Seek, SeekFrom, IsTerminal};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::bail;
 use memchr::memchr;
+use tempfile::NamedTempFile;
+use flate2::bufread::MultiGzDecoder;
 use unicode_segmentation::UnicodeSegmentation;
 
 /// Finds stream tags with a regex pattern.
 ///
-/// The first capture group (or the whole match if there is no capture group)
-/// is used as the stream tag.
+/// The selected capture group (by default the last capture group, or the
+/// whole match if there is no capture group) is used as the stream tag.
+///
+/// Alternatively, if a join separator is provided, the stream tag is composed
+/// of the contents of all capture groups joined with the separator. Composite
+/// tags don't correspond to a range within the line, so they are returned as
+/// owned buffers, losing the zero-copy benefits of the range based lookup.
+///
+/// Tags can be rewritten with a sequence of regex replacements, applied in
+/// order. When matching case-insensitively, the replacement patterns are also
+/// matched case-insensitively, and tags are then normalized to lowercase so
+/// that tags differing only in case are treated as the same stream.
+///
+/// Tags can optionally be trimmed of surrounding ASCII whitespace, before any
+/// replacements are applied. This only affects the tag, not the line contents.
+///
+/// When inverted, lines that match are treated as having no tag, and all other
+/// lines get the INVERTED_TAG tag.
 pub(crate) struct TagFinder {
+    patterns: Vec<TagPattern>,
+    join: Option<Vec<u8>>,
+    ignore_case: bool,
+    invert: bool,
+    trim: bool,
+    match_window: Option<usize>,
+    // The delimiter and the 1-based index of the field of lines to search
+    field: Option<(Vec<u8>, usize)>,
+    whole_line: bool,
+    strip: bool,
+    // The byte range of the tag found by the last search, if any
+    last_tag_range: Option<std::ops::Range<usize>>,
+    replacements: Vec<(regex::bytes::Regex, Vec<u8>)>,
+}
+
+/// The tag of lines that don't match the pattern, when matching is inverted.
+pub(crate) const INVERTED_TAG: &[u8] = b"unmatched";
+
+/// A compiled tag pattern, along with the capture group holding the tag.
+struct TagPattern {
     re: regex::bytes::Regex,
     capture_locations: regex::bytes::CaptureLocations,
+    group: usize,
 }
 
-impl TagFinder {
-    /// Creates a new TagFinder with the specified regex pattern.
-    pub(crate) fn new(pattern: &str) -> Result<TagFinder> {
-        let re = regex::bytes::Regex::new(pattern)?;
+/// Compiles a regular expression used for finding or rewriting tags, with the
+/// case sensitivity, syntax flags and size limit of the tag options.
+fn build_tag_regex(pattern: &str, opts: &UnweaveOptionsTag) -> Result<regex::bytes::Regex> {
+    let mut builder = regex::bytes::RegexBuilder::new(pattern);
+    builder.case_insensitive(opts.ignore_case)
+        .multi_line(opts.regex_flags.multi_line)
+        .dot_matches_new_line(opts.regex_flags.dot_matches_new_line)
+        .swap_greed(opts.regex_flags.swap_greed)
+        .ignore_whitespace(opts.regex_flags.ignore_whitespace);
+    if let Some(size_limit) = opts.size_limit {
+        builder.size_limit(size_limit).dfa_size_limit(size_limit);
+    }
+    builder.build().map_err(|e| match e {
+        regex::Error::CompiledTooBig(l) => UnweaveError::PatternTooLarge(pattern.to_string(), l).into(),
+        e => e.into(),
+    })
+}
+
+impl TagPattern {
+    /// Compiles a tag pattern, resolving the tag group within it.
+    fn new(pattern: &str, opts: &UnweaveOptionsTag) -> Result<TagPattern> {
+        let re = build_tag_regex(pattern, opts)?;
         let capture_locations = re.capture_locations();
-        Ok(TagFinder { re, capture_locations })
+        let group = match &opts.group {
+            UnweaveTagGroup::Last => capture_locations.len() - 1,
+            UnweaveTagGroup::Index(i) if *i < capture_locations.len() => *i,
+            UnweaveTagGroup::Index(i) => bail!(UnweaveError::UnknownTagGroup(i.to_string())),
+            UnweaveTagGroup::Name(n) => {
+                match re.capture_names().position(|name| name == Some(n.as_str())) {
+                    Some(i) => i,
+                    None => bail!(UnweaveError::UnknownTagGroup(n.clone())),
+                }
+            }
+        };
+        Ok(TagPattern { re, capture_locations, group })
+    }
+}
+
+impl TagFinder {
+    /// Creates a new TagFinder with the specified tag options. With multiple
+    /// patterns, the first pattern that matches a line determines its tag.
+    pub(crate) fn new(opts: &UnweaveOptionsTag) -> Result<TagFinder> {
+        let patterns = opts.patterns.iter()
+            .map(|p| TagPattern::new(p, opts))
+            .collect::<Result<Vec<_>>>()?;
+        let join = opts.join.as_ref().map(|j| j.as_bytes().to_vec());
+        let mut replacements = Vec::new();
+        for (pattern, replacement) in &opts.replace {
+            let re = build_tag_regex(pattern, opts)?;
+            replacements.push((re, replacement.as_bytes().to_vec()));
+        }
+        Ok(TagFinder { patterns, join, ignore_case: opts.ignore_case, invert: opts.invert,
+                       trim: opts.trim, match_window: opts.match_window,
+                       field: opts.field.as_ref().map(|(d, n)| (d.as_bytes().to_vec(), *n)),
+                       whole_line: opts.whole_line, strip: opts.strip, last_tag_range: None,
+                       replacements })
+    }
+
+    /// Finds the first pattern that matches a line, filling in its capture
+    /// locations. With a match window, only the start of the line is searched.
+    fn match_in<'p>(patterns: &'p mut [TagPattern], line: &[u8],
+                    match_window: Option<usize>) -> Option<&'p TagPattern> {
+        let line = match match_window {
+            Some(w) if w < line.len() => &line[..utf8_boundary_before(line, w)],
+            _ => line,
+        };
+        patterns.iter_mut().find_map(|p| {
+            p.re.captures_read(&mut p.capture_locations, line).is_some().then_some(&*p)
+        })
     }
 
     /// Finds the stream tag in a line.
@@ -45,23 +152,338 @@ impl TagFinder {
     /// Returns the byte range of the tag within the line, or None if no
     /// tag was found.
     pub(crate) fn find_in(&mut self, line: &[u8]) -> Option<std::ops::Range<usize>> {
-        self.re.captures_read(&mut self.capture_locations, &line)
-            .map(|_| self.capture_locations
-                         .get(self.capture_locations.len() - 1)
-                         .map(|m| m.0..m.1)
-            ).flatten()
+        let pattern = Self::match_in(&mut self.patterns, line, self.match_window)?;
+        pattern.capture_locations
+            .get(pattern.group)
+            .map(|m| m.0..m.1)
+    }
+
+    /// Returns a line without the tag found by the last search, when
+    /// stripping tags. Otherwise, or if no tag was found, the line is
+    /// returned unchanged.
+    pub(crate) fn strip_found_tag<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        let range = match &self.last_tag_range {
+            Some(r) if self.strip => r.clone(),
+            _ => return Cow::Borrowed(line),
+        };
+
+        if range.start == 0 {
+            Cow::Borrowed(&line[range.end..])
+        } else if range.end == line.len() {
+            Cow::Borrowed(&line[..range.start])
+        } else {
+            Cow::Owned([&line[..range.start], &line[range.end..]].concat())
+        }
+    }
+
+    /// Finds the tag in a line, returning its byte range within the line, or
+    /// None if no tag was found or the tag isn't a single range of the line,
+    /// as with joined or whole line tags.
+    pub(crate) fn tag_range_in(&mut self, line: &[u8]) -> Option<std::ops::Range<usize>> {
+        self.find_raw_tag_in(line);
+        self.found_tag_range()
+    }
+
+    /// Returns the byte range of the tag found by the last search, like
+    /// tag_range_in.
+    pub(crate) fn found_tag_range(&self) -> Option<std::ops::Range<usize>> {
+        self.last_tag_range.clone()
+    }
+
+    /// Finds the tag in a line and returns the line without it, when
+    /// stripping tags.
+    pub(crate) fn strip_tag<'a>(&mut self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self.strip {
+            return Cow::Borrowed(line);
+        }
+        self.find_raw_tag_in(line);
+        self.strip_found_tag(line)
+    }
+
+    /// Finds the stream tag in a line.
+    ///
+    /// Returns the tag contents, either borrowed from the line, or owned in
+    /// case of a composite tag, or None if no tag was found.
+    pub(crate) fn find_tag_in<'a>(&mut self, line: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let mut tag = match (self.find_raw_tag_in(line), self.invert) {
+            (tag, false) => tag?,
+            (None, true) => Cow::Borrowed(INVERTED_TAG),
+            (Some(_), true) => return None,
+        };
+
+        if self.trim {
+            tag = match tag {
+                Cow::Borrowed(t) => Cow::Borrowed(t.trim_ascii()),
+                Cow::Owned(t) => Cow::Owned(t.trim_ascii().to_vec()),
+            };
+        }
+
+        for (re, replacement) in &self.replacements {
+            let replaced = match re.replace(&tag, replacement.as_slice()) {
+                Cow::Owned(t) => Some(t),
+                Cow::Borrowed(_) => None,
+            };
+            if let Some(t) = replaced {
+                tag = Cow::Owned(t);
+            }
+        }
+
+        if self.ignore_case {
+            Some(Cow::Owned(lowercase(&tag)))
+        } else {
+            Some(tag)
+        }
+    }
+
+    /// Finds the stream tag in a line, without any normalization. With whole
+    /// line tags, the tag of a matching line is the line itself, trimmed of
+    /// ASCII whitespace. With a tag field, only that field of the line is
+    /// searched, and lines without the field have no tag.
+    fn find_raw_tag_in<'a>(&mut self, line: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        self.last_tag_range = None;
+
+        let (field_start, line) = match &self.field {
+            Some((delimiter, n)) => nth_field(line, delimiter, *n)?,
+            None => (0, line),
+        };
+
+        if self.whole_line {
+            return Self::match_in(&mut self.patterns, line, self.match_window)
+                .map(|_| Cow::Borrowed(line.trim_ascii()));
+        }
+
+        let join = match &self.join {
+            Some(j) => j,
+            None => {
+                let range = self.find_in(line)?;
+                self.last_tag_range = Some(field_start + range.start..field_start + range.end);
+                return Some(Cow::Borrowed(&line[range]));
+            },
+        };
+
+        Self::match_in(&mut self.patterns, line, self.match_window)
+            .map(|p| Self::joined_groups(p, line, join))
     }
+
+    /// Returns the contents of all the capture groups of a matched pattern,
+    /// joined with the specified separator.
+    fn joined_groups<'a>(pattern: &TagPattern, line: &'a [u8], join: &[u8]) -> Cow<'a, [u8]> {
+        let capture_locations = &pattern.capture_locations;
+        let ngroups = capture_locations.len();
+        if ngroups == 1 {
+            let m = capture_locations.get(0).unwrap();
+            return Cow::Borrowed(&line[m.0..m.1]);
+        }
+
+        let mut tag = Vec::new();
+        for i in 1..ngroups {
+            if i > 1 {
+                tag.extend_from_slice(join);
+            }
+            if let Some(m) = capture_locations.get(i) {
+                tag.extend_from_slice(&line[m.0..m.1]);
+            }
+        }
+
+        Cow::Owned(tag)
+    }
+}
+
+/// Returns the offset and contents of the nth (counting from 1) field of the
+/// line, with fields separated by the delimiter, or None if the line has fewer
+/// fields.
+fn nth_field<'a>(line: &'a [u8], delimiter: &[u8], n: usize) -> Option<(usize, &'a [u8])> {
+    let mut start = 0;
+    for _ in 1..n {
+        start += memchr::memmem::find(&line[start..], delimiter)? + delimiter.len();
+    }
+    let end = memchr::memmem::find(&line[start..], delimiter).map_or(line.len(), |m| start + m);
+    Some((start, &line[start..end]))
+}
+
+/// Returns the sort key of a line, which is the first capture group of the
+/// first match of the sort key regex, or the whole match if the regex has no
+/// capture groups, or None if there is no match.
+fn find_sort_key<'a>(line: &'a [u8], sort_key: &regex::bytes::Regex) -> Option<&'a [u8]> {
+    let captures = sort_key.captures(line)?;
+    captures.get(1).or_else(|| captures.get(0)).map(|m| m.as_bytes())
+}
+
+/// Compares sort keys, numerically if both keys are numbers, otherwise
+/// bytewise, with numbers ordered before other keys. Lines without a key are
+/// ordered after lines with a key.
+fn compare_sort_keys(a: Option<&[u8]>, b: Option<&[u8]>) -> std::cmp::Ordering {
+    let number = |k: &[u8]| std::str::from_utf8(k).ok()?.trim().parse::<f64>().ok();
+
+    match (a, b) {
+        (Some(a), Some(b)) => match (number(a), number(b)) {
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.cmp(b),
+        },
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }
+}
+
+/// Stably sorts items by the sort key of their line, as returned by the line
+/// function (see find_sort_key and compare_sort_keys).
+pub(crate) fn sort_by_line_key<'a, T>(items: &mut Vec<T>, sort_key: &regex::bytes::Regex,
+                                      line: impl Fn(&T) -> &'a [u8]) {
+    let mut keyed: Vec<_> = items.drain(..)
+        .map(|t| (find_sort_key(line(&t), sort_key).map(<[u8]>::to_vec), t))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| compare_sort_keys(a.as_deref(), b.as_deref()));
+    items.extend(keyed.into_iter().map(|(_, t)| t));
+}
+
+/// Moves an offset within a byte slice back to the start of the UTF-8 sequence
+/// it falls in, if any, so that splitting the slice at the offset doesn't
+/// split the sequence.
+fn utf8_boundary_before(v: &[u8], offset: usize) -> usize {
+    let mut boundary = offset;
+    while boundary > 0 && offset - boundary < 3 && (v[boundary] & 0xc0) == 0x80 {
+        boundary -= 1;
+    }
+    if (v[boundary] & 0xc0) == 0xc0 { boundary } else { offset }
+}
+
+/// The escape sequence starting a highlighted stream tag (bold, inverse).
+pub(crate) const HIGHLIGHT_START: &[u8] = b"\x1b[1;7m";
+/// The escape sequence ending a highlighted stream tag.
+pub(crate) const HIGHLIGHT_END: &[u8] = b"\x1b[0m";
+
+/// Writes data, wrapping the specified byte range of it, if any and not empty,
+/// in the highlight escape sequences.
+pub(crate) fn write_highlighted<W: Write + ?Sized>(out: &mut W, data: &[u8],
+                                                  range: Option<std::ops::Range<usize>>)
+    -> io::Result<()>
+{
+    match range {
+        Some(r) if !r.is_empty() => {
+            out.write_all(&data[..r.start])?;
+            out.write_all(HIGHLIGHT_START)?;
+            out.write_all(&data[r.clone()])?;
+            out.write_all(HIGHLIGHT_END)?;
+            out.write_all(&data[r.end..])
+        },
+        _ => out.write_all(data),
+    }
+}
+
+/// Converts a byte slice to lowercase. Valid UTF-8 data is converted using
+/// the Unicode rules, otherwise only ASCII characters are converted.
+fn lowercase(v: &[u8]) -> Vec<u8> {
+    match std::str::from_utf8(v) {
+        Ok(s) => s.to_lowercase().into_bytes(),
+        Err(_) => v.to_ascii_lowercase(),
+    }
+}
+
+/// The number of lines between progress reports.
+const PROGRESS_INTERVAL: u64 = 1_000_000;
+
+/// Reports the number of lines processed, and the number of bytes in them
+/// (excluding line terminators), every time a fixed number of lines has been
+/// processed.
+pub(crate) struct Progress<'w> {
+    out: Option<Box<dyn Write + 'w>>,
+    interval: u64,
+    lines: u64,
+    bytes: u64,
+}
+
+impl<'w> Progress<'w> {
+    /// Creates a new Progress which reports to standard error, if enabled by
+    /// the progress mode.
+    pub(crate) fn new(mode: UnweaveProgress) -> Self {
+        let enabled = match mode {
+            UnweaveProgress::Never => false,
+            UnweaveProgress::Auto => io::stderr().is_terminal(),
+            UnweaveProgress::Always => true,
+        };
+        if enabled {
+            Self::with_writer(Box::new(io::stderr()), PROGRESS_INTERVAL)
+        } else {
+            Progress { out: None, interval: PROGRESS_INTERVAL, lines: 0, bytes: 0 }
+        }
+    }
+
+    /// Creates a new Progress which reports to the specified writer every
+    /// "interval" lines.
+    pub(crate) fn with_writer(out: Box<dyn Write + 'w>, interval: u64) -> Self {
+        Progress { out: Some(out), interval, lines: 0, bytes: 0 }
+    }
+
+    /// Counts a processed line.
+    pub(crate) fn count_line(&mut self, line: &[u8]) {
+        self.count_lines(1, line.len() as u64);
+    }
+
+    /// Counts a number of processed lines with the specified total number of
+    /// bytes, reporting progress if a report interval boundary was crossed.
+    /// Failures to write the report are ignored.
+    pub(crate) fn count_lines(&mut self, lines: u64, bytes: u64) {
+        let out = match &mut self.out {
+            Some(out) => out,
+            None => return,
+        };
+
+        let before = self.lines / self.interval;
+        self.lines += lines;
+        self.bytes += bytes;
+
+        if self.lines / self.interval != before {
+            let _ = writeln!(out, "unweave: processed {} lines ({} bytes)", self.lines, self.bytes);
+        }
+    }
+}
+
+/// Writes a table with the stream number, the number of lines and the tag of
+/// each stream.
+pub(crate) fn write_stats(out: &mut dyn Write, streams: &[(Vec<u8>, u64)]) -> Result<()> {
+    writeln!(out, "STREAM\tLINES\tTAG")?;
+    for (i, (tag, count)) in streams.iter().enumerate() {
+        write!(out, "{}\t{}\t", i, count)?;
+        out.write_all(tag)?;
+        writeln!(out)?;
+    }
+    Ok(())
 }
 
-/// Iterator for the lines contained in a slice of [u8].
+/// The byte terminating lines, or whether to detect it from the data.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum LineTerminator {
+    Byte(u8),
+    Detect,
+}
+
+impl LineTerminator {
+    /// Returns the terminator byte, detecting it from the specified start of
+    /// the data if needed. Data containing carriage returns but no newlines
+    /// is taken to use lone carriage returns as line terminators.
+    pub(crate) fn resolve(self, data: &[u8]) -> u8 {
+        match self {
+            Self::Byte(b) => b,
+            Self::Detect if memchr(b'\n', data).is_none() && memchr(b'\r', data).is_some() => b'\r',
+            Self::Detect => b'\n',
+        }
+    }
+}
+
+/// Iterator for the lines contained in a slice of [u8], each ending with
+/// the specified terminator byte.
 pub(crate) struct SliceFullLines<'a> {
     buf: &'a [u8],
     last: usize,
+    terminator: u8,
 }
 
 impl<'a> SliceFullLines<'a> {
-    pub(crate) fn new(buf: &'a [u8]) -> Self {
-        SliceFullLines { buf, last: 0 }
+    pub(crate) fn new(buf: &'a [u8], terminator: u8) -> Self {
+        SliceFullLines { buf, last: 0, terminator }
     }
 }
 
@@ -69,7 +491,7 @@ impl<'a> Iterator for SliceFullLines<'a> {
     type Item = &'a [u8];
 
     fn next(&mut self) -> Option<&'a [u8]> {
-        match memchr(b'\n', &self.buf[self.last..]) {
+        match memchr(self.terminator, &self.buf[self.last..]) {
             Some(m) => {
                 let line = &self.buf[self.last..=(self.last + m)];
                 self.last = self.last + m + 1;
@@ -88,59 +510,301 @@ impl<'a> Iterator for SliceFullLines<'a> {
     }
 }
 
-pub(crate) fn trim_newline(v: &[u8]) -> &[u8]
+/// Iterator for the records contained in a slice of [u8], separated by the
+/// non-empty matches of a regex. Each record is returned along with its length
+/// including the separator that follows it.
+pub(crate) struct SliceRecords<'a> {
+    buf: &'a [u8],
+    last: usize,
+    separator: &'a regex::bytes::Regex,
+}
+
+impl<'a> SliceRecords<'a> {
+    pub(crate) fn new(buf: &'a [u8], separator: &'a regex::bytes::Regex) -> Self {
+        SliceRecords { buf, last: 0, separator }
+    }
+}
+
+impl<'a> Iterator for SliceRecords<'a> {
+    type Item = (&'a [u8], usize);
+
+    fn next(&mut self) -> Option<(&'a [u8], usize)> {
+        let rest = &self.buf[self.last..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (record, len) = match self.separator.find_iter(rest).find(|m| !m.is_empty()) {
+            Some(m) => (&rest[..m.start()], m.end()),
+            None => (rest, rest.len()),
+        };
+        self.last += len;
+
+        Some((record, len))
+    }
+}
+
+/// Iterator for the lines contained in a slice of [u8], returning each line
+/// without its terminator, along with its full length. The lines are either
+/// terminated by a byte, or are records separated by a regex, in which case
+/// a trailing terminator is also trimmed from each record.
+pub(crate) enum SliceTrimmedLines<'a> {
+    Lines(SliceFullLines<'a>),
+    Records(SliceRecords<'a>, u8),
+}
+
+impl<'a> SliceTrimmedLines<'a> {
+    pub(crate) fn new(buf: &'a [u8], terminator: LineTerminator,
+                      separator: Option<&'a regex::bytes::Regex>) -> Self {
+        let terminator = terminator.resolve(buf);
+        match separator {
+            Some(separator) => Self::Records(SliceRecords::new(buf, separator), terminator),
+            None => Self::Lines(SliceFullLines::new(buf, terminator)),
+        }
+    }
+}
+
+impl<'a> Iterator for SliceTrimmedLines<'a> {
+    type Item = (&'a [u8], usize);
+
+    fn next(&mut self) -> Option<(&'a [u8], usize)> {
+        match self {
+            Self::Lines(lines) => {
+                let line = lines.next()?;
+                Some((trim_newline(line, lines.terminator), line.len()))
+            },
+            Self::Records(records, terminator) => {
+                let (record, len) = records.next()?;
+                Some((trim_newline(record, *terminator), len))
+            },
+        }
+    }
+}
+
+/// Trims the terminator from the end of a line. For newline terminated
+/// lines, a carriage return before the newline is also trimmed.
+pub(crate) fn trim_newline(v: &[u8], terminator: u8) -> &[u8]
 {
-    let mut t = &v[..];
-    if t.last() == Some(&b'\n') {
+    let mut t = v;
+    if t.last() == Some(&terminator) {
         t = &t[..t.len() - 1]
     }
-    if t.last() == Some(&b'\r') {
+    if terminator == b'\n' && t.last() == Some(&b'\r') {
         t = &t[..t.len() - 1]
     }
     t
 }
 
+/// The expected access pattern of a memory mapping.
+enum MmapAdvice {
+    Sequential,
+    SequentialWillNeed,
+}
+
+/// Advises the kernel about the expected access pattern of a memory mapping,
+/// to reduce page fault stalls. This is only a hint, so failures are ignored.
+#[cfg(unix)]
+fn advise_mmap(mmap: &memmap::Mmap, advice: MmapAdvice) {
+    if mmap.is_empty() {
+        return;
+    }
+
+    let addr = mmap.as_ptr() as *mut libc::c_void;
+    let len = mmap.len();
+
+    unsafe {
+        libc::madvise(addr, len, libc::MADV_SEQUENTIAL);
+        if let MmapAdvice::SequentialWillNeed = advice {
+            libc::madvise(addr, len, libc::MADV_WILLNEED);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn advise_mmap(_mmap: &memmap::Mmap, _advice: MmapAdvice) {}
+
 /// Iterator like struct for the lines contained in a file, accessed using
 /// memory mapping.
 pub(crate) struct FileLinesMmap {
     mmap: memmap::Mmap,
     last: usize,
+    terminator: u8,
+    max_line: Option<MaxLine>,
+    too_long: bool,
 }
 
 /// Iterator like struct for the lines contained in a file, accessed using
-/// a BufRead object.
+/// a BufRead object. When following, reaching the end of the file waits for
+/// more data instead of ending the iteration.
 pub(crate) struct FileLinesBufreader {
     bufreader: BufReader<Box<dyn Read>>,
     buf: Vec<u8>,
+    offset: u64,
+    // The number of bytes of the current line read from the input, which is
+    // more than the length of the line if it was truncated
+    consumed: u64,
+    // When following, the flag which stops following when set
+    follow: Option<&'static AtomicBool>,
+    terminator: u8,
+    error: Option<io::Error>,
+    max_line: Option<MaxLine>,
+    too_long: bool,
+}
+
+/// The maximum length of lines in bytes, not counting the line terminator,
+/// and what to do with longer lines.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct MaxLine {
+    pub(crate) bytes: usize,
+    pub(crate) action: UnweaveMaxLineAction,
+}
+
+/// Returns the line truncated to the maximum length, or fails if the line is
+/// longer than the maximum length and the action is to fail.
+pub(crate) fn limit_line_length<'a>(line: &'a [u8], max_line: Option<MaxLine>,
+                                    path: &Path) -> Result<&'a [u8]> {
+    match max_line {
+        Some(MaxLine { bytes, action }) if line.len() > bytes => match action {
+            UnweaveMaxLineAction::Truncate => Ok(&line[..bytes]),
+            UnweaveMaxLineAction::Error => bail!(UnweaveError::LineTooLong(path.to_path_buf(), bytes)),
+        },
+        _ => Ok(line),
+    }
+}
+
+/// How long to wait before checking for more data when following a file.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set when following should stop, e.g., after SIGINT.
+static FOLLOW_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a SIGINT handler which stops following files, allowing the
+/// unweave operation to complete normally.
+pub fn stop_following_on_interrupt() -> Result<()> {
+    ctrlc::set_handler(|| FOLLOW_INTERRUPTED.store(true, Ordering::Relaxed))?;
+    Ok(())
 }
 
 impl FileLinesMmap {
-    fn next(&mut self) -> Option<&[u8]> {
-        match memchr(b'\n', &self.mmap[self.last..]) {
-            Some(m) => {
-                let line = &self.mmap[self.last..(self.last + m)];
-                self.last = self.last + m + 1;
-                Some(trim_newline(line))
-            },
-            None => {
-                let line = &self.mmap[self.last..];
-                if line.is_empty() {
-                    None
-                } else {
-                    self.last = self.mmap.len();
-                    Some(trim_newline(line))
+    fn next(&mut self) -> Option<(&[u8], bool)> {
+        let rest = &self.mmap[self.last..];
+        if rest.is_empty() || self.too_long {
+            return None;
+        }
+
+        // Lines that would fail are only scanned up to just past the maximum
+        // length.
+        let scanned = match self.max_line {
+            Some(MaxLine { bytes, action: UnweaveMaxLineAction::Error }) =>
+                &rest[..std::cmp::min(rest.len(), bytes.saturating_add(1))],
+            _ => rest,
+        };
+        let (len, terminated) = match memchr(self.terminator, scanned) {
+            Some(m) => (m, true),
+            None => (scanned.len(), false),
+        };
+
+        let mut line = &rest[..len];
+        if let Some(MaxLine { bytes, action }) = self.max_line {
+            if len > bytes {
+                if action == UnweaveMaxLineAction::Error {
+                    self.too_long = true;
+                    return None;
                 }
+                line = &line[..bytes];
             }
         }
+
+        self.last += len + terminated as usize;
+        Some((trim_newline(line, self.terminator), terminated))
     }
 }
 
 impl FileLinesBufreader {
-    fn next(&mut self) -> Option<&[u8]> {
+    /// Whether reading stops at the end of the file, instead of waiting for
+    /// more data.
+    fn stops_at_end(&self) -> bool {
+        self.follow.is_none_or(|interrupted| interrupted.load(Ordering::Relaxed))
+    }
+
+    fn next(&mut self) -> Option<(&[u8], bool)> {
+        if self.too_long {
+            return None;
+        }
+
+        self.offset += self.consumed;
+        self.consumed = 0;
         self.buf.clear();
-        match self.bufreader.read_until(b'\n', &mut self.buf) {
-            Ok(nread) if nread > 0 => Some(trim_newline(&self.buf)),
-            _ => None
+
+        let result = match self.max_line {
+            Some(max_line) => self.read_line_bounded(max_line),
+            None => self.read_line(),
+        };
+        if let Err(e) = result {
+            self.error = Some(e);
+            return None;
+        }
+
+        if self.buf.is_empty() || self.too_long {
+            None
+        } else {
+            let terminated = self.buf.last() == Some(&self.terminator);
+            Some((trim_newline(&self.buf, self.terminator), terminated))
+        }
+    }
+
+    /// Reads the next line, including its terminator, into the buffer.
+    fn read_line(&mut self) -> io::Result<()> {
+        loop {
+            self.bufreader.read_until(self.terminator, &mut self.buf)?;
+            if self.buf.last() == Some(&self.terminator) || self.stops_at_end() {
+                break;
+            }
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+        self.consumed = self.buf.len() as u64;
+        Ok(())
+    }
+
+    /// Reads the next line, including its terminator, into the buffer,
+    /// keeping at most the maximum length of the line in memory. Truncated
+    /// lines are read to their end, but lines that would fail are only read
+    /// up to the maximum length.
+    fn read_line_bounded(&mut self, max_line: MaxLine) -> io::Result<()> {
+        loop {
+            let available = match self.bufreader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                if self.stops_at_end() {
+                    return Ok(());
+                }
+                std::thread::sleep(FOLLOW_POLL_INTERVAL);
+                continue;
+            }
+
+            let (used, terminated) = match memchr(self.terminator, available) {
+                Some(m) => (m + 1, true),
+                None => (available.len(), false),
+            };
+            let len = used - terminated as usize;
+            let room = max_line.bytes.saturating_sub(self.buf.len());
+            if len > room && max_line.action == UnweaveMaxLineAction::Error {
+                self.too_long = true;
+                return Ok(());
+            }
+
+            self.buf.extend_from_slice(&available[..std::cmp::min(len, room)]);
+            if terminated {
+                self.buf.push(self.terminator);
+            }
+            self.bufreader.consume(used);
+            self.consumed += used as u64;
+            if terminated {
+                return Ok(());
+            }
         }
     }
 }
@@ -152,15 +816,57 @@ pub(crate) enum FileLines {
     Bufreader(FileLinesBufreader),
 }
 
-/// Opens a file at the specified path. The path "/dev/stdin" is treated
+/// The magic bytes at the start of gzip compressed data.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Checks whether the path has a ".gz" extension.
+fn path_has_gzip_extension(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "gz")
+}
+
+/// Checks whether the path refers to gzip compressed data, either based on
+/// its extension or, for regular files, based on its magic bytes. Non-regular
+/// files are not read, to avoid consuming data from streaming sources.
+fn path_is_gzip(path: &Path) -> bool {
+    if path_has_gzip_extension(path) {
+        return true;
+    }
+
+    let mut file = match File::open(path) {
+        Ok(f) if f.metadata().is_ok_and(|m| m.is_file()) => f,
+        _ => return false,
+    };
+
+    let mut magic = [0; 2];
+    file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC
+}
+
+/// Opens a file at the specified path for buffered reading with the specified
+/// buffer size, transparently decompressing gzip compressed data.
+fn open_file(path: &Path, buffer_size: usize) -> Result<BufReader<Box<dyn Read>>> {
+    let mut bufreader = BufReader::with_capacity(buffer_size, open_raw_file(path)?);
+
+    if path_has_gzip_extension(path) || bufreader.fill_buf()?.starts_with(GZIP_MAGIC) {
+        Ok(BufReader::with_capacity(buffer_size, Box::new(MultiGzDecoder::new(bufreader))))
+    } else {
+        Ok(bufreader)
+    }
+}
+
+/// Opens a file at the specified path. The empty path denotes standard input
+/// and is read through io::stdin(). The path "/dev/stdin" is also treated
 /// specially, falling back to io::stdin() if normal open fails.
-fn open_file(path: &Path) -> Result<Box<dyn Read>> {
+fn open_raw_file(path: &Path) -> Result<Box<dyn Read>> {
+    if path.as_os_str().is_empty() {
+        return Ok(Box::new(io::stdin()));
+    }
+
     let res = File::open(path);
     if let Ok(f) = res {
         return Ok(Box::new(f));
     };
 
-    if path.to_string_lossy() == "/dev/stdin" {
+    if path_is_stdin(path) {
         return Ok(Box::new(io::stdin()));
     }
 
@@ -169,37 +875,230 @@ fn open_file(path: &Path) -> Result<Box<dyn Read>> {
 
 impl FileLines {
     /// Creates a new FileLines object, backed by either mmap or BufRead
-    /// depending on the path capabilities and user preference.
-    pub(crate) fn new(path: &Path, mmap: UnweaveMmap) -> Result<Self> {
-        if mmap == UnweaveMmap::Allow {
-            let ret = Self::new_mmap(path);
+    /// depending on the path capabilities and user preference, for lines
+    /// ending with the specified terminator. A detected terminator is
+    /// detected from the whole mapping, or from the first buffered data. Gzip
+    /// compressed data is always accessed through BufRead.
+    pub(crate) fn new(path: &Path, mmap: UnweaveMmap, buffer_size: usize,
+                      terminator: LineTerminator) -> Result<Self> {
+        if !path.as_os_str().is_empty() && mmap == UnweaveMmap::Allow && !path_is_gzip(path) {
+            let ret = Self::new_mmap(path, terminator);
             if ret.is_ok() {
                 return ret;
             }
         }
 
-        Self::new_bufreader(path)
+        Self::new_bufreader(path, buffer_size, terminator)
+    }
+
+    /// Creates a new FileLines object which keeps waiting for more data at the
+    /// end of the file, until interrupted (see stop_following_on_interrupt).
+    pub(crate) fn new_following(path: &Path, buffer_size: usize,
+                                terminator: LineTerminator) -> Result<Self> {
+        Self::new_following_until(path, buffer_size, terminator, &FOLLOW_INTERRUPTED)
+    }
+
+    /// Creates a new FileLines object which keeps waiting for more data at the
+    /// end of the file, until the "interrupted" flag is set. Always backed by
+    /// a BufRead object, since the mapping of a growing file doesn't grow with
+    /// it.
+    fn new_following_until(path: &Path, buffer_size: usize, terminator: LineTerminator,
+                           interrupted: &'static AtomicBool) -> Result<Self> {
+        let mut bufreader = open_file(path, buffer_size)?;
+        let terminator = terminator.resolve(bufreader.fill_buf()?);
+        Ok(FileLines::Bufreader(FileLinesBufreader {
+            bufreader, buf: Vec::new(), offset: 0, consumed: 0, follow: Some(interrupted), terminator,
+            error: None, max_line: None, too_long: false
+        }))
     }
 
     /// Creates a new FileLines object, backed by mmap.
-    fn new_mmap(path: &Path) -> Result<Self> {
+    fn new_mmap(path: &Path, terminator: LineTerminator) -> Result<Self> {
         let mmap = unsafe { memmap::Mmap::map(&File::open(path)?)? };
-        Ok(FileLines::Mmap(FileLinesMmap { mmap, last: 0 }))
+        // Lines are processed as they are read, so we don't advise WILLNEED,
+        // which would read ahead the whole file. This is also the case for
+        // the reread two-pass mode, in which the file would otherwise be
+        // read into the page cache twice, increasing cache pressure.
+        advise_mmap(&mmap, MmapAdvice::Sequential);
+        let terminator = terminator.resolve(&mmap);
+        Ok(FileLines::Mmap(FileLinesMmap { mmap, last: 0, terminator, max_line: None, too_long: false }))
+    }
+
+    /// Creates a new FileLines object, backed by a BufRead object with the
+    /// specified buffer size.
+    fn new_bufreader(path: &Path, buffer_size: usize, terminator: LineTerminator) -> Result<Self> {
+        let mut bufreader = open_file(path, buffer_size)?;
+        let terminator = terminator.resolve(bufreader.fill_buf()?);
+        Ok(FileLines::Bufreader(FileLinesBufreader {
+            bufreader, buf: Vec::new(), offset: 0, consumed: 0, follow: None, terminator,
+            error: None, max_line: None, too_long: false
+        }))
+    }
+
+    /// Checks whether the start of the file appears to contain binary data,
+    /// acting according to "binary". Must be called before reading any lines.
+    pub(crate) fn check_binary(&mut self, path: &Path, binary: UnweaveBinary) -> Result<()> {
+        let (head, terminator) = match self {
+            Self::Mmap(m) => (&m.mmap[..], m.terminator),
+            Self::Bufreader(b) => (b.bufreader.fill_buf()?, b.terminator),
+        };
+        check_binary_input(path, head, terminator, binary)
     }
 
-    /// Creates a new FileLines object, backed by a BufRead object.
-    fn new_bufreader(path: &Path) -> Result<Self> {
-        let bufreader = BufReader::new(open_file(path)?);
-        Ok(FileLines::Bufreader(FileLinesBufreader { bufreader, buf: Vec::new() }))
+    /// Sets the maximum length of lines. Must be called before reading any
+    /// lines.
+    pub(crate) fn set_max_line(&mut self, max_line: Option<MaxLine>) {
+        match self {
+            Self::Mmap(m) => m.max_line = max_line,
+            Self::Bufreader(b) => b.max_line = max_line,
+        }
+    }
+
+    /// Fails if the lines ended early at a line longer than the maximum
+    /// length, instead of truncating it.
+    pub(crate) fn check_line_length(&self, path: &Path) -> Result<()> {
+        let (too_long, max_line) = match self {
+            Self::Mmap(m) => (m.too_long, m.max_line),
+            Self::Bufreader(b) => (b.too_long, b.max_line),
+        };
+        match max_line {
+            Some(MaxLine { bytes, .. }) if too_long =>
+                bail!(UnweaveError::LineTooLong(path.to_path_buf(), bytes)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the byte offset within the file of the next line.
+    pub(crate) fn offset(&self) -> u64 {
+        match self {
+            Self::Mmap(m) => m.last as u64,
+            Self::Bufreader(b) => b.offset + b.consumed,
+        }
     }
 
     /// Returns the next line, or None if there are no more lines.
     pub(crate) fn next(&mut self) -> Option<&[u8]> {
+        self.next_with_terminator().map(|(line, _)| line)
+    }
+
+    /// Returns the next line along with whether it ended with the line
+    /// terminator, which is only not the case for the last line of a file,
+    /// or None if there are no more lines.
+    pub(crate) fn next_with_terminator(&mut self) -> Option<(&[u8], bool)> {
         match self {
             Self::Mmap(m) => m.next(),
             Self::Bufreader(b) => b.next(),
         }
     }
+
+    /// Converts into an Iterator over owned copies of the lines, which also
+    /// yields the read error, if any, that ended the lines. Prefer next() in
+    /// performance sensitive code, since it doesn't copy the lines.
+    pub(crate) fn into_owned_iter(self) -> FileLinesOwned {
+        FileLinesOwned { file_lines: self, failed: false }
+    }
+}
+
+/// Iterator over owned copies of the lines of a file, without their line
+/// terminators.
+pub struct FileLinesOwned {
+    file_lines: FileLines,
+    failed: bool,
+}
+
+impl FileLinesOwned {
+    /// Opens the file at the path, which may be the stdin path or gzip
+    /// compressed, for iterating over its newline terminated lines.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file_lines = FileLines::new(path, UnweaveMmap::Allow, crate::DEFAULT_BUFFER_SIZE,
+                                        LineTerminator::Byte(b'\n'))?;
+        Ok(file_lines.into_owned_iter())
+    }
+}
+
+impl Iterator for FileLinesOwned {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.failed {
+            return None;
+        }
+
+        match self.file_lines.next() {
+            Some(line) => Some(Ok(line.to_vec())),
+            None => match &mut self.file_lines {
+                FileLines::Bufreader(b) => {
+                    self.failed = b.error.is_some();
+                    b.error.take().map(Err)
+                },
+                FileLines::Mmap(_) => None,
+            },
+        }
+    }
+}
+
+/// Whether an error was caused by writing to a pipe whose reading end has been
+/// closed.
+pub(crate) fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// The number of bytes at the start of an input that are checked for binary
+/// data.
+const BINARY_CHECK_SIZE: usize = 8 * 1024;
+
+/// Whether the data appears to be binary, i.e., more than a tenth of it are
+/// NUL bytes (unless NUL is the line terminator) or invalid UTF-8 sequences.
+/// Only the first BINARY_CHECK_SIZE bytes are checked.
+fn is_binary(data: &[u8], terminator: u8) -> bool {
+    let data = &data[..data.len().min(BINARY_CHECK_SIZE)];
+    let mut nsuspicious = 0;
+
+    if terminator != b'\0' {
+        nsuspicious += memchr::memchr_iter(b'\0', data).count();
+    }
+    // A sequence cut off by the end of the checked data is not invalid.
+    let mut rest = data;
+    while let Err(e) = std::str::from_utf8(rest) {
+        match e.error_len() {
+            Some(len) => {
+                nsuspicious += 1;
+                rest = &rest[e.valid_up_to() + len..];
+            },
+            None => break,
+        }
+    }
+
+    nsuspicious * 10 > data.len()
+}
+
+/// Checks whether the start of an input appears to contain binary data,
+/// failing or warning about it, depending on "binary".
+pub(crate) fn check_binary_input(path: &Path, data: &[u8], terminator: u8,
+                                 binary: UnweaveBinary) -> Result<()> {
+    if binary == UnweaveBinary::Process || !is_binary(data, terminator) {
+        return Ok(());
+    }
+
+    if binary == UnweaveBinary::Refuse {
+        bail!(UnweaveError::BinaryInput(path.to_path_buf()));
+    }
+
+    writeln!(io::stderr().lock(), "Warning: input '{}' appears to contain binary data",
+             path.display())?;
+    Ok(())
+}
+
+impl FileContents {
+    /// Checks whether the start of the contents appears to contain binary
+    /// data, acting according to "binary".
+    pub(crate) fn check_binary(&self, path: &Path, terminator: LineTerminator,
+                               binary: UnweaveBinary) -> Result<()> {
+        let contents = self.contents();
+        check_binary_input(path, contents, terminator.resolve(contents), binary)
+    }
 }
 
 /// Provides access to file contents using mmap.
@@ -221,9 +1120,10 @@ pub(crate) enum FileContents {
 
 impl FileContents {
     /// Creates a new FileContents object, backed by either mmap or buffer
-    /// depending on the path capabilities and user preference.
+    /// depending on the path capabilities and user preference. Gzip
+    /// compressed data is always read to a buffer.
     pub(crate) fn new(path: &Path, mmap: UnweaveMmap) -> Result<Self> {
-        if !path.as_os_str().is_empty() && mmap == UnweaveMmap::Allow {
+        if !path.as_os_str().is_empty() && mmap == UnweaveMmap::Allow && !path_is_gzip(path) {
             let ret = Self::new_mmap(path);
             if ret.is_ok() {
                 return ret;
@@ -236,12 +1136,15 @@ impl FileContents {
     /// Creates a new FileContents object, backed by mmap.
     fn new_mmap(path: &Path) -> Result<Self> {
         let mmap = unsafe { memmap::Mmap::map(&File::open(path)?)? };
+        // The whole contents are going to be accessed (twice), so start
+        // reading them in early.
+        advise_mmap(&mmap, MmapAdvice::SequentialWillNeed);
         Ok(FileContents::Mmap(FileContentsMmap { mmap }))
     }
 
     /// Creates a new FileContents object, backed by a buffer.
     fn new_buf(path: &Path) -> Result<Self> {
-        let mut reader = open_file(path)?;
+        let mut reader = open_file(path, crate::DEFAULT_BUFFER_SIZE)?;
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
         Ok(FileContents::Buf(FileContentsBuf { buf }))
@@ -250,16 +1153,64 @@ impl FileContents {
     /// Returns the file contents as byte slice.
     pub(crate) fn contents(&self) -> &[u8] {
         match self {
-            Self::Mmap(m) => &*m.mmap,
+            Self::Mmap(m) => &m.mmap,
             Self::Buf(b) => &b.buf,
         }
     }
 }
 
-/// Try to infer if the file at "path" can be reread. If seek fails or the file
-/// offset is not the expected one assume that we can't reread.  Note that this
-/// check may provide a false positive if the path is a device that fakes
-/// successful seeks without actually seeking.
+/// Returns the path used to denote standard input. This is "/dev/stdin" on
+/// systems that support it, potentially giving direct access to the
+/// underlying file in case of redirection, and the empty path otherwise,
+/// which is read through io::stdin() without relying on any file existing.
+pub fn stdin_path() -> PathBuf {
+    let dev_stdin = Path::new("/dev/stdin");
+    if dev_stdin.exists() {
+        dev_stdin.to_path_buf()
+    } else {
+        PathBuf::new()
+    }
+}
+
+/// Whether "path" is a path used to denote standard input.
+pub(crate) fn path_is_stdin(path: &Path) -> bool {
+    path.as_os_str().is_empty() || path.to_string_lossy() == "/dev/stdin"
+}
+
+/// Input data copied to a temporary file, so that it can be reread. The
+/// temporary file is deleted when the SpilledInput is dropped.
+pub(crate) struct SpilledInput {
+    file: NamedTempFile,
+}
+
+impl SpilledInput {
+    /// Copies all the data from "reader" to a temporary file.
+    pub(crate) fn new(reader: &mut dyn Read) -> Result<Self> {
+        let mut file = tempfile::Builder::new().prefix("unweave").tempfile()?;
+        io::copy(reader, &mut file)?;
+        Ok(SpilledInput{file})
+    }
+
+    /// Copies all the data from the file at "path" to a temporary file.
+    pub(crate) fn from_path(path: &Path) -> Result<Self> {
+        Self::new(&mut open_raw_file(path)?)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// Returns the size of the file at "path", or 0 if the size is not known.
+pub(crate) fn path_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+/// Try to infer if the file at "path" can be reread. Gzip compressed files
+/// can be reread, since they are decompressed anew on every read. If seek
+/// fails or the file offset is not the expected one assume that we can't
+/// reread.  Note that this check may provide a false positive if the path is
+/// a device that fakes successful seeks without actually seeking.
 pub(crate) fn path_contents_can_be_reread(path: &Path) -> bool {
     let mut file = match File::open(path) {
         Ok(f) => f,
@@ -267,72 +1218,58 @@ pub(crate) fn path_contents_can_be_reread(path: &Path) -> bool {
     };
 
     match file.seek(SeekFrom::Start(1)) {
-        Ok(pos) if pos == 1 => {}
+        Ok(1) => {}
         _ => return false,
     };
 
     true
 }
 
-pub(crate) fn ascii_grapheme_count(b: u8) -> u32 {
-    (b >= 0x20 && b != 0x7f) as u32
+/// Number of file descriptors to leave available for inputs and other files
+/// when deriving the maximum number of open output files from the limit.
+const RESERVED_FILE_DESCRIPTORS: u64 = 16;
+
+/// Returns the maximum number of output files to keep open at the same time,
+/// based on the limit of open file descriptors, or None if there is no limit.
+#[cfg(unix)]
+pub(crate) fn default_max_open_files() -> Option<usize> {
+    let mut rlimit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit) } != 0 ||
+        rlimit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+
+    // rlim_t is not u64 on all platforms
+    #[allow(clippy::unnecessary_cast)]
+    let max = (rlimit.rlim_cur as u64).saturating_sub(RESERVED_FILE_DESCRIPTORS).max(1);
+    Some(max.min(usize::MAX as u64) as usize)
 }
 
-pub(crate) fn str_grapheme_count(grapheme: &str) -> u32 {
-    if grapheme.len() > 1 {
-        1
-    } else {
-        ascii_grapheme_count(grapheme.as_bytes()[0])
-    }
-}
-
-pub(crate) fn grapheme_count_tab_expanded(line: &[u8], tab: UnweaveTab,
-                                          mut out: Option<&mut Vec<u8>>) -> u32 {
-    let mut grapheme_count: u32 = 0;
-
-    for_each_grapheme(line,
-        |g| {
-            match g {
-                Grapheme::Unicode(s) => {
-                    match (s, tab) {
-                        ("\t", UnweaveTab::Expand(tw)) => {
-                            let nspaces = tw - grapheme_count % tw;
-                            if let Some(out) = &mut out {
-                                out.extend(std::iter::repeat(b' ').take(nspaces as usize));
-                            }
-                            grapheme_count += nspaces;
-                        }
-                        _ => {
-                            if let Some(out) = &mut out {
-                                out.extend_from_slice(s.as_bytes());
-                            }
-                            grapheme_count += str_grapheme_count(s);
-                        }
-                    }
-                },
-                Grapheme::Ascii(b) => {
-                    match (b, tab) {
-                        (b'\t', UnweaveTab::Expand(tw)) => {
-                            let nspaces = tw - grapheme_count % tw;
-                            if let Some(out) = &mut out {
-                                out.extend(std::iter::repeat(b' ').take(nspaces as usize));
-                            }
-                            grapheme_count += nspaces;
-                        }
-                        _ => {
-                            if let Some(out) = &mut out {
-                                out.push(b);
-                            }
-                            grapheme_count += ascii_grapheme_count(b);
-                        }
-                    }
-                }
-            }
-            Ok(())
-        }
-    ).unwrap();
+#[cfg(not(unix))]
+pub(crate) fn default_max_open_files() -> Option<usize> {
+    None
+}
 
-    grapheme_count
+/// Returns the width of the terminal standard output is connected to, or None
+/// if standard output is not a terminal.
+#[cfg(unix)]
+pub(crate) fn terminal_width() -> Option<u32> {
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut winsize) } != 0 ||
+        winsize.ws_col == 0
+    {
+        return None;
+    }
+
+    Some(u32::from(winsize.ws_col))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn terminal_width() -> Option<u32> {
+    None
 }
 
 pub(crate) enum Grapheme<'a> {
@@ -386,31 +1323,467 @@ pub(crate) fn for_each_grapheme(line: &[u8],
     Ok(())
 }
 
+/// Returns the line with ASCII control characters other than tab replaced
+/// according to "show_control". Lines without such characters are borrowed.
+pub(crate) fn show_control_chars(line: &[u8], show_control: UnweaveShowControl) -> Cow<'_, [u8]> {
+    let is_control = |b: u8| (b < 0x20 && b != b'\t') || b == 0x7f;
+
+    if show_control == UnweaveShowControl::Raw || !line.iter().any(|b| is_control(*b)) {
+        return Cow::Borrowed(line);
+    }
+
+    let mut shown = Vec::with_capacity(line.len());
+    for &b in line {
+        if !is_control(b) {
+            shown.push(b);
+            continue;
+        }
+        match show_control {
+            UnweaveShowControl::Caret => shown.extend_from_slice(&[b'^', b ^ 0x40]),
+            UnweaveShowControl::Hex => shown.extend_from_slice(format!("\\x{:02x}", b).as_bytes()),
+            UnweaveShowControl::Drop | UnweaveShowControl::Raw => {},
+        }
+    }
+
+    Cow::Owned(shown)
+}
+
+/// Returns the offset and contents of each part of the line separated by
+/// "split_on", or of the whole line if there is no "split_on".
+pub(crate) fn sub_records<'a>(line: &'a [u8], split_on: Option<&'a [u8]>)
+    -> impl Iterator<Item=(usize, &'a [u8])> + 'a
+{
+    let mut start = Some(0);
+
+    std::iter::from_fn(move || {
+        let cur = start?;
+        let end = match split_on {
+            Some(sep) => memchr::memmem::find(&line[cur..], sep).map(|pos| cur + pos),
+            None => None,
+        };
+        start = end.map(|end| end + split_on.map_or(0, |sep| sep.len()));
+        Some((cur, &line[cur..end.unwrap_or(line.len())]))
+    })
+}
+
+/// Returns the offset and contents of each line of a record, without its
+/// terminator. A record always has at least one, possibly empty, line.
+pub(crate) fn record_lines(record: &[u8], terminator: LineTerminator)
+    -> impl Iterator<Item=(usize, &[u8])>
+{
+    let terminator = terminator.resolve(record);
+    let mut start = Some(0);
+
+    std::iter::from_fn(move || {
+        let cur = start?;
+        let end = memchr(terminator, &record[cur..]).map(|pos| cur + pos + 1);
+        start = end;
+        Some((cur, trim_newline(&record[cur..end.unwrap_or(record.len())], terminator)))
+    })
+}
+
+/// Calls "callback_fn" with the byte offset of each invalid UTF-8 sequence in
+/// the line, i.e., of each sequence for_each_grapheme handles byte by byte.
+pub(crate) fn for_each_invalid_utf8(line: &[u8], mut callback_fn: impl FnMut(usize)) {
+    if line.is_ascii() {
+        return;
+    }
+
+    let mut offset = 0;
+
+    while let Err(e) = std::str::from_utf8(&line[offset..]) {
+        offset += e.valid_up_to();
+        callback_fn(offset);
+        offset += e.error_len().unwrap_or(line.len() - offset);
+    }
+}
+
+/// Writes a summary of the invalid UTF-8 sequences found, listing the line
+/// number and byte offset within the line of the first few.
+pub(crate) fn write_invalid_utf8_summary(out: &mut dyn Write, count: u64,
+                                         first: &[(u64, usize)]) -> Result<()> {
+    write!(out, "Warning: found {} invalid UTF-8 sequence{}",
+           count, if count == 1 { "" } else { "s" })?;
+    for (i, (line_number, offset)) in first.iter().enumerate() {
+        let sep = if i == 0 { ", first at" } else { "," };
+        write!(out, "{} line {} byte {}", sep, line_number, offset)?;
+    }
+    if count > first.len() as u64 {
+        write!(out, ", ...")?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write sinks shared by the tests of the output modes.
+#[cfg(test)]
+pub(crate) mod test_writers {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    /// A Write sink that accepts writes but fails to flush.
+    pub(crate) struct FailingFlush;
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::other("flush failed"))
+        }
+    }
+
+    /// A Write sink that records the amount of data written at each flush.
+    /// Clones share the recorded data, so that it can be inspected after a
+    /// clone has been handed over as a Box<dyn Write>.
+    #[derive(Clone, Default)]
+    pub(crate) struct FlushRecorder {
+        pub(crate) data: Rc<RefCell<Vec<u8>>>,
+        pub(crate) flushes: Rc<RefCell<Vec<usize>>>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes.borrow_mut().push(self.data.borrow().len());
+            Ok(())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{DEFAULT_BUFFER_SIZE, UnweaveRegexFlags};
+    use tempdir::TempDir;
 
     #[test]
-    fn expand_tabs_ascii() {
-        let mut out = Vec::new();
-        let ngraphemes = grapheme_count_tab_expanded(b"ab\tcdefghijk\tl\t",
-                                                     UnweaveTab::Expand(8),
-                                                     Some(&mut out));
+    fn tag_finder_match_window() {
+        let opts = UnweaveOptionsTag {
+            match_window: Some(8),
+            ..UnweaveOptionsTag::new("T[0-9]")
+        };
+        let mut tag_finder = TagFinder::new(&opts).unwrap();
+        assert!(tag_finder.find_tag_in(b"[T1] abc T2").as_deref() == Some(&b"T1"[..]));
+        assert!(tag_finder.find_in(b"[T1] abc T2") == Some(1..3));
+        assert!(tag_finder.find_tag_in(b"[-] abcd T2").is_none());
+        assert!(tag_finder.find_tag_in(b"T3").as_deref() == Some(&b"T3"[..]));
+
+        // The window is shortened to avoid splitting the 'é' at its end.
+        let opts = UnweaveOptionsTag {
+            match_window: Some(3),
+            ..UnweaveOptionsTag::new("^(.*)$")
+        };
+        let mut tag_finder = TagFinder::new(&opts).unwrap();
+        assert!(tag_finder.find_tag_in("abé".as_bytes()).as_deref() == Some(&b"ab"[..]));
+        assert!(tag_finder.find_tag_in("aéb".as_bytes()).as_deref() == Some("aé".as_bytes()));
+    }
+
+    #[test]
+    fn tag_finder_field() {
+        let opts = UnweaveOptionsTag {
+            field: Some((", ".to_string(), 2)),
+            strip: true,
+            ..UnweaveOptionsTag::new("[A-Z]+")
+        };
+        let mut tag_finder = TagFinder::new(&opts).unwrap();
+        assert!(tag_finder.find_tag_in(b"XY, ab CD, EF").as_deref() == Some(&b"CD"[..]));
+        assert!(tag_finder.found_tag_range() == Some(7..9));
+        assert!(tag_finder.strip_found_tag(b"XY, ab CD, EF") == &b"XY, ab , EF"[..]);
+        assert!(tag_finder.find_tag_in(b"XY, ab, CD").is_none());
+        assert!(tag_finder.find_tag_in(b"XY CD").is_none());
+        assert!(tag_finder.find_tag_in(b", CD").as_deref() == Some(&b"CD"[..]));
+    }
+
+    #[test]
+    fn tag_finder_size_limit() {
+        let opts = UnweaveOptionsTag::new(r"(\w{50})+");
+        assert!(TagFinder::new(&opts).is_ok());
+
+        let opts = UnweaveOptionsTag { size_limit: Some(1000), ..opts };
+        let e = TagFinder::new(&opts).err().unwrap();
+        assert!(matches!(e.downcast_ref::<UnweaveError>(),
+                         Some(UnweaveError::PatternTooLarge(_, 1000))));
+
+        let opts = UnweaveOptionsTag {
+            replace: vec![(r"\w{50}".to_string(), "".to_string())],
+            ..UnweaveOptionsTag::new("a")
+        };
+        assert!(TagFinder::new(&opts).is_ok());
+        assert!(TagFinder::new(&UnweaveOptionsTag { size_limit: Some(1000), ..opts }).is_err());
+    }
+
+    #[test]
+    fn tag_finder_regex_flags() {
+        let opts = UnweaveOptionsTag::new(r"\[ (\w+) \]  # the tag");
+        let mut tag_finder = TagFinder::new(&opts).unwrap();
+        assert!(tag_finder.find_tag_in(b"[A] a").is_none());
+
+        let opts = UnweaveOptionsTag {
+            regex_flags: UnweaveRegexFlags { ignore_whitespace: true, ..Default::default() },
+            ..opts
+        };
+        let mut tag_finder = TagFinder::new(&opts).unwrap();
+        assert!(tag_finder.find_tag_in(b"[A] a").as_deref() == Some(&b"A"[..]));
 
-        let expected = b"ab      cdefghijk       l       ";
-        assert!(ngraphemes == expected.len() as u32);
-        assert!(out == expected);
+        let opts = UnweaveOptionsTag {
+            regex_flags: UnweaveRegexFlags { swap_greed: true, ..Default::default() },
+            ..UnweaveOptionsTag::new("^(.+)-")
+        };
+        let mut tag_finder = TagFinder::new(&opts).unwrap();
+        assert!(tag_finder.find_tag_in(b"a-b-c").as_deref() == Some(&b"a"[..]));
     }
 
     #[test]
-    fn expand_tabs_unicode() {
+    fn progress_reports() {
         let mut out = Vec::new();
-        let ngraphemes = grapheme_count_tab_expanded("αβ\tγδεζηθικλ\tμ\t".as_bytes(),
-                                                     UnweaveTab::Expand(8),
-                                                     Some(&mut out));
+        let mut progress = Progress::with_writer(Box::new(&mut out), 2);
+        progress.count_line(b"abc");
+        progress.count_line(b"d");
+        progress.count_line(b"ef");
+        progress.count_lines(3, 10);
+        drop(progress);
+
+        assert!(out ==
+                concat!("unweave: processed 2 lines (4 bytes)\n",
+                        "unweave: processed 6 lines (16 bytes)\n").as_bytes());
+    }
+
+    #[test]
+    fn line_terminator_detect() {
+        assert!(LineTerminator::Detect.resolve(b"a\rb\r") == b'\r');
+        assert!(LineTerminator::Detect.resolve(b"a\r\nb\r\n") == b'\n');
+        assert!(LineTerminator::Detect.resolve(b"a\nb") == b'\n');
+        assert!(LineTerminator::Detect.resolve(b"") == b'\n');
+        assert!(LineTerminator::Byte(b'\0').resolve(b"a\rb") == b'\0');
+    }
+
+    #[test]
+    fn slice_records() {
+        let re = regex::bytes::Regex::new("\n\n+").unwrap();
+        let records: Vec<_> = SliceRecords::new(b"a\nb\n\nc\n\n\nd\n", &re).collect();
+        assert!(records == vec![(&b"a\nb"[..], 5), (&b"c"[..], 4), (&b"d\n"[..], 2)]);
+
+        let re = regex::bytes::Regex::new("x*").unwrap();
+        let records: Vec<_> = SliceRecords::new(b"abxc", &re).collect();
+        assert!(records == vec![(&b"ab"[..], 3), (&b"c"[..], 1)]);
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(!is_binary(b"", b'\n'));
+        assert!(!is_binary("A:1 αβγ\nB:1\n".as_bytes(), b'\n'));
+        assert!(is_binary(b"A:1\0\0\nB:1\0\0\n", b'\n'));
+        assert!(!is_binary(b"A:1\0\0\nB:1\0\0\n", b'\0'));
+        assert!(is_binary(b"A:1\xff\xfe\nB:1\xff\n", b'\n'));
+        // A multibyte sequence cut off at the end is not invalid
+        assert!(!is_binary(b"A:\xce", b'\n'));
+    }
+
+    #[test]
+    fn invalid_utf8_offsets() {
+        let mut offsets = Vec::new();
+        for_each_invalid_utf8(b"\xce\xb1\xce\x79a\xff\xce", |o| offsets.push(o));
+        assert!(offsets == vec![2, 5, 6]);
+
+        offsets.clear();
+        for_each_invalid_utf8("αβ".as_bytes(), |o| offsets.push(o));
+        assert!(offsets.is_empty());
+    }
+
+    #[test]
+    fn file_lines_following() {
+        // A flag of the test's own, so that following isn't stopped for other
+        // tests
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let path = tmpdir.path().join("input");
+        std::fs::write(&path, b"a\nb").unwrap();
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            let mut file = std::fs::OpenOptions::new().append(true).open(&writer_path).unwrap();
+            std::io::Write::write_all(&mut file, b"c\nd\ne").unwrap();
+            std::thread::sleep(Duration::from_millis(300));
+            INTERRUPTED.store(true, Ordering::Relaxed);
+        });
+
+        let mut file_lines = FileLines::new_following_until(&path, DEFAULT_BUFFER_SIZE,
+                                                            LineTerminator::Byte(b'\n'),
+                                                            &INTERRUPTED).unwrap();
+        let mut lines = Vec::new();
+        while let Some(line) = file_lines.next() {
+            lines.push(line.to_vec());
+        }
+        writer.join().unwrap();
+
+        assert!(lines == vec![b"a".to_vec(), b"bc".to_vec(), b"d".to_vec(), b"e".to_vec()]);
+    }
+
+    #[test]
+    fn file_lines_owned_iter() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let path = tmpdir.path().join("input");
+        std::fs::write(&path, b"a\nbb\n\nccc").unwrap();
+
+        for mmap in [UnweaveMmap::Allow, UnweaveMmap::Disallow] {
+            let file_lines = FileLines::new(&path, mmap, DEFAULT_BUFFER_SIZE,
+                                            LineTerminator::Byte(b'\n')).unwrap();
+            let lines = file_lines.into_owned_iter()
+                .filter(|line| !matches!(line, Ok(l) if l.is_empty()))
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap();
+            assert!(lines == vec![b"a".to_vec(), b"bb".to_vec(), b"ccc".to_vec()]);
+        }
+
+        let lengths: Vec<_> = FileLinesOwned::open(&path).unwrap()
+            .map(|line| line.unwrap().len())
+            .collect();
+        assert!(lengths == vec![1, 2, 0, 3]);
+
+        // Read errors end the lines and are yielded once
+        struct Failing(bool);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 {
+                    return Err(io::Error::other("failed"));
+                }
+                self.0 = true;
+                buf[..2].copy_from_slice(b"a\n");
+                Ok(2)
+            }
+        }
+        let file_lines = FileLines::Bufreader(FileLinesBufreader {
+            bufreader: BufReader::new(Box::new(Failing(false))), buf: Vec::new(), offset: 0,
+            consumed: 0, follow: None, terminator: b'\n', error: None, max_line: None,
+            too_long: false
+        });
+        let lines: Vec<_> = file_lines.into_owned_iter().collect();
+        assert!(lines.len() == 2);
+        assert!(lines[0].as_ref().unwrap() == b"a");
+        assert!(lines[1].is_err());
+    }
+
+    #[test]
+    fn file_lines_max_line() {
+        let tmpdir = TempDir::new("unweave-test").unwrap();
+        let path = tmpdir.path().join("input");
+        let mut data = b"a\n".to_vec();
+        data.extend(std::iter::repeat_n(b'x', 100_000));
+        data.extend_from_slice(b"\nbcd\nefgh");
+        std::fs::write(&path, &data).unwrap();
+
+        let truncate = MaxLine { bytes: 3, action: UnweaveMaxLineAction::Truncate };
+        let error = MaxLine { bytes: 3, action: UnweaveMaxLineAction::Error };
+
+        for mmap in [UnweaveMmap::Allow, UnweaveMmap::Disallow] {
+            // A small buffer makes the long line span many reads
+            let mut file_lines = FileLines::new(&path, mmap, 16, LineTerminator::Byte(b'\n')).unwrap();
+            file_lines.set_max_line(Some(truncate));
+            let mut lines = Vec::new();
+            let mut offsets = Vec::new();
+            loop {
+                offsets.push(file_lines.offset());
+                match file_lines.next() {
+                    Some(line) => lines.push(line.to_vec()),
+                    None => break,
+                }
+            }
+            assert!(lines == vec![b"a".to_vec(), b"xxx".to_vec(), b"bcd".to_vec(), b"efg".to_vec()]);
+            assert!(offsets == vec![0, 2, 100_003, 100_007, data.len() as u64]);
+            assert!(file_lines.check_line_length(&path).is_ok());
+
+            let mut file_lines = FileLines::new(&path, mmap, 16, LineTerminator::Byte(b'\n')).unwrap();
+            file_lines.set_max_line(Some(error));
+            assert!(file_lines.next() == Some(&b"a"[..]));
+            assert!(file_lines.next().is_none());
+            assert!(file_lines.next().is_none());
+            let err = file_lines.check_line_length(&path).unwrap_err();
+            assert!(matches!(err.downcast_ref::<UnweaveError>(), Some(UnweaveError::LineTooLong(_, 3))));
+        }
+
+        assert!(limit_line_length(b"abcd", Some(truncate), &path).unwrap() == b"abc");
+        assert!(limit_line_length(b"abc", Some(error), &path).unwrap() == b"abc");
+        assert!(limit_line_length(b"abcd", Some(error), &path).is_err());
+        assert!(limit_line_length(b"abcd", None, &path).unwrap() == b"abcd");
+    }
+
+    #[test]
+    fn spilled_input_from_non_seekable_reader() {
+        // A reader that supports only Read, like a pipe.
+        struct NonSeekable<'a>(&'a [u8]);
+        impl Read for NonSeekable<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        let spilled = SpilledInput::new(&mut NonSeekable(b"A:1\nB:1\n")).unwrap();
+        let path = spilled.path().to_path_buf();
+
+        assert!(path_contents_can_be_reread(&path));
+        assert!(std::fs::read(&path).unwrap() == b"A:1\nB:1\n");
+
+        drop(spilled);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn show_control_chars_modes() {
+        let line = b"a\x01b\tc\x1b[0m\x7f";
+
+        assert!(show_control_chars(line, UnweaveShowControl::Raw) == &line[..]);
+        assert!(show_control_chars(line, UnweaveShowControl::Caret) == &b"a^Ab\tc^[[0m^?"[..]);
+        assert!(show_control_chars(line, UnweaveShowControl::Hex) ==
+                &b"a\\x01b\tc\\x1b[0m\\x7f"[..]);
+        assert!(show_control_chars(line, UnweaveShowControl::Drop) == &b"ab\tc[0m"[..]);
+        assert!(matches!(show_control_chars(b"a\tb", UnweaveShowControl::Caret), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn record_lines_split() {
+        let lines = |record, terminator| record_lines(record, terminator).collect::<Vec<_>>();
+        assert!(lines(b"a\r\nbc\n\nd", LineTerminator::Byte(b'\n')) ==
+                vec![(0, &b"a"[..]), (3, &b"bc"[..]), (6, &b""[..]), (7, &b"d"[..])]);
+        assert!(lines(b"a\rb", LineTerminator::Detect) == vec![(0, &b"a"[..]), (2, &b"b"[..])]);
+        assert!(lines(b"", LineTerminator::Byte(b'\n')) == vec![(0, &b""[..])]);
+    }
+
+    #[test]
+    fn sub_records_split() {
+        let split = |line: &'static [u8], sep: Option<&'static [u8]>|
+            sub_records(line, sep).collect::<Vec<_>>();
+
+        assert!(split(b"A:1;B:1", Some(b";")) == vec![(0, &b"A:1"[..]), (4, &b"B:1"[..])]);
+        assert!(split(b"a::b::", Some(b"::")) == vec![(0, &b"a"[..]), (3, &b"b"[..]), (6, &b""[..])]);
+        assert!(split(b"A:1;B:1", None) == vec![(0, &b"A:1;B:1"[..])]);
+        assert!(split(b"", Some(b";")) == vec![(0, &b""[..])]);
+    }
+
+    #[test]
+    fn sort_by_line_key_numeric() {
+        let sort_key = regex::bytes::Regex::new(r"t=(\S+)").unwrap();
+        let mut lines: Vec<&[u8]> = vec![b"a t=10", b"b", b"c t=9", b"d t=x", b"e t=9.5", b"f t=9", b"g t=1x"];
+        sort_by_line_key(&mut lines, &sort_key, |l| *l);
+
+        assert!(lines == [&b"c t=9"[..], b"f t=9", b"e t=9.5", b"a t=10", b"g t=1x", b"d t=x", b"b"]);
+    }
+
+    #[test]
+    fn empty_path_is_stdin() {
+        let path = PathBuf::new();
 
-        let expected = "αβ      γδεζηθικλ       μ       ";
-        assert!(ngraphemes == expected.chars().count() as u32);
-        assert!(out == expected.as_bytes());
+        assert!(path_is_stdin(&path));
+        assert!(path_is_stdin(&stdin_path()));
+        // The empty path is routed to io::stdin() instead of being opened
+        assert!(open_raw_file(&path).is_ok());
+        assert!(!path_is_gzip(&path));
+        assert!(path_size(&path) == 0);
     }
 }
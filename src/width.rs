@@ -0,0 +1,212 @@
+// Copyright 2022 Alexandros Frantzis
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Terminal width calculations for lines and graphemes.
+
+use crate::{UnweaveTab, UnweaveCharWidth};
+use crate::util::{for_each_grapheme, Grapheme};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use memchr::memchr;
+
+/// Returns the number of terminal cells an ASCII character occupies, i.e., 1
+/// for printable characters and 0 for control characters.
+pub fn ascii_grapheme_count(b: u8) -> u32 {
+    (b >= 0x20 && b != 0x7f) as u32
+}
+
+/// Returns the number of terminal cells a grapheme occupies. With monospace
+/// char width every printable grapheme occupies a single cell, otherwise the
+/// width is the display width of the grapheme cluster as a whole, so that
+/// combining marks add no width, and wide graphemes (e.g., CJK characters,
+/// emoji and emoji ZWJ sequences) occupy two cells.
+pub fn str_grapheme_count(grapheme: &str, char_width: UnweaveCharWidth) -> u32 {
+    if grapheme.len() == 1 {
+        return ascii_grapheme_count(grapheme.as_bytes()[0]);
+    }
+
+    match char_width {
+        UnweaveCharWidth::Mono => 1,
+        UnweaveCharWidth::Display => grapheme.width() as u32,
+    }
+}
+
+/// Whether the string consists of a single grapheme that occupies a single
+/// terminal cell.
+pub(crate) fn is_single_width_grapheme(s: &str) -> bool {
+    let mut graphemes = s.graphemes(true);
+    match (graphemes.next(), graphemes.next()) {
+        (Some(g), None) => str_grapheme_count(g, UnweaveCharWidth::Display) == 1,
+        _ => false,
+    }
+}
+
+/// Returns the number of terminal cells a line occupies, expanding tabs
+/// according to "tab". If "out" is provided, the tab expanded line is
+/// appended to it.
+pub fn grapheme_count_tab_expanded(line: &[u8], tab: UnweaveTab,
+                                   char_width: UnweaveCharWidth,
+                                   out: Option<&mut Vec<u8>>) -> u32 {
+    grapheme_count_tab_expanded_at(line, tab, char_width, 0, out)
+}
+
+/// Like grapheme_count_tab_expanded, for a line that is printed after the
+/// specified number of terminal cells, with tab stops measured from the start
+/// of the cells. The returned count doesn't include the preceding cells.
+pub fn grapheme_count_tab_expanded_at(line: &[u8], tab: UnweaveTab,
+                                      char_width: UnweaveCharWidth, origin: u32,
+                                      out: Option<&mut Vec<u8>>) -> u32 {
+    // Pure ASCII lines without tabs to expand occupy a cell per printable
+    // character, and need no grapheme segmentation.
+    if line.is_ascii() && !(tab.is_expand() && memchr(b'\t', line).is_some()) {
+        if let Some(out) = out {
+            out.extend_from_slice(line);
+        }
+        return line.iter().map(|b| ascii_grapheme_count(*b)).sum();
+    }
+
+    grapheme_count_tab_expanded_general(line, tab, char_width, origin, out)
+}
+
+fn grapheme_count_tab_expanded_general(line: &[u8], tab: UnweaveTab,
+                                       char_width: UnweaveCharWidth, origin: u32,
+                                       mut out: Option<&mut Vec<u8>>) -> u32 {
+    let mut grapheme_count: u32 = 0;
+
+    for_each_grapheme(line,
+        |g| {
+            match g {
+                Grapheme::Unicode(s) => {
+                    match (s, tab) {
+                        ("\t", UnweaveTab::Expand(tw)) => {
+                            let nspaces = tw - (origin + grapheme_count) % tw;
+                            if let Some(out) = &mut out {
+                                out.extend(std::iter::repeat_n(b' ', nspaces as usize));
+                            }
+                            grapheme_count += nspaces;
+                        }
+                        _ => {
+                            if let Some(out) = &mut out {
+                                out.extend_from_slice(s.as_bytes());
+                            }
+                            grapheme_count += str_grapheme_count(s, char_width);
+                        }
+                    }
+                },
+                Grapheme::Ascii(b) => {
+                    match (b, tab) {
+                        (b'\t', UnweaveTab::Expand(tw)) => {
+                            let nspaces = tw - (origin + grapheme_count) % tw;
+                            if let Some(out) = &mut out {
+                                out.extend(std::iter::repeat_n(b' ', nspaces as usize));
+                            }
+                            grapheme_count += nspaces;
+                        }
+                        _ => {
+                            if let Some(out) = &mut out {
+                                out.push(b);
+                            }
+                            grapheme_count += ascii_grapheme_count(b);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    ).unwrap();
+
+    grapheme_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_tabs_ascii() {
+        let mut out = Vec::new();
+        let ngraphemes = grapheme_count_tab_expanded(b"ab\tcdefghijk\tl\t",
+                                                     UnweaveTab::Expand(8),
+                                                     UnweaveCharWidth::Display,
+                                                     Some(&mut out));
+
+        let expected = b"ab      cdefghijk       l       ";
+        assert!(ngraphemes == expected.len() as u32);
+        assert!(out == expected);
+    }
+
+    #[test]
+    fn expand_tabs_unicode() {
+        let mut out = Vec::new();
+        let ngraphemes = grapheme_count_tab_expanded("αβ\tγδεζηθικλ\tμ\t".as_bytes(),
+                                                     UnweaveTab::Expand(8),
+                                                     UnweaveCharWidth::Display,
+                                                     Some(&mut out));
+
+        let expected = "αβ      γδεζηθικλ       μ       ";
+        assert!(ngraphemes == expected.chars().count() as u32);
+        assert!(out == expected.as_bytes());
+    }
+
+    #[test]
+    fn grapheme_count_wide() {
+        let line = "ab日本語c".as_bytes();
+        let tab = UnweaveTab::Expand(8);
+
+        assert!(grapheme_count_tab_expanded(line, tab, UnweaveCharWidth::Mono, None) == 6);
+        assert!(grapheme_count_tab_expanded(line, tab, UnweaveCharWidth::Display, None) == 9);
+    }
+
+    #[test]
+    fn grapheme_count_combining_and_emoji() {
+        let char_width = UnweaveCharWidth::Display;
+
+        // "e" followed by a combining acute accent
+        assert!(str_grapheme_count("e\u{301}", char_width) == 1);
+        // Emoji, including a ZWJ sequence and one with a skin tone modifier
+        assert!(str_grapheme_count("\u{1f600}", char_width) == 2);
+        assert!(str_grapheme_count("\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}", char_width) == 2);
+        assert!(str_grapheme_count("\u{1f44d}\u{1f3fd}", char_width) == 2);
+
+        let line = "ae\u{301}\u{1f600}b".as_bytes();
+        let mut out = Vec::new();
+        assert!(grapheme_count_tab_expanded(line, UnweaveTab::Expand(8), char_width, Some(&mut out)) == 5);
+        assert!(out == line);
+    }
+
+    #[test]
+    fn grapheme_count_ascii_fast_path() {
+        let lines: &[&[u8]] = &[
+            b"", b"abc", b"a b\x01c\x7f", b"\x1b[1mbold\x1b[0m", b"a\tb", b"\t\t",
+        ];
+
+        for line in lines {
+            for tab in [UnweaveTab::NoExpand, UnweaveTab::Expand(4)] {
+                for origin in [0, 3] {
+                    let mut out = Vec::new();
+                    let mut out_general = Vec::new();
+                    let count = grapheme_count_tab_expanded_at(
+                        line, tab, UnweaveCharWidth::Display, origin, Some(&mut out));
+                    let count_general = grapheme_count_tab_expanded_general(
+                        line, tab, UnweaveCharWidth::Display, origin, Some(&mut out_general));
+                    assert!(count == count_general);
+                    assert!(out == out_general);
+                }
+            }
+        }
+    }
+}